@@ -0,0 +1,281 @@
+/*!
+ * # Benchmarks of the FHE AES-128 primitives
+ *
+ * Measures, for every selected TFHE parameter set and rayon thread count:
+ * - `key_expansion`: One `key_expansion_fhe`.
+ * - `sub_bytes` / `mix_columns`: One SubBytes and one MixColumns of a 16-byte state.
+ * - `gal_mul`: One `gal_mul` per MixColumns and inverse MixColumns constant.
+ * - `encrypt_block` / `decrypt_block`: One `aes_encrypt_block` and one `aes_decrypt_block`.
+ * - `batch`: The encryption of N blocks in parallel, with the throughput in bytes.
+ * - `aes_ctr_keystream` / `chacha20_keystream`: The generation of 16 * N keystream bytes with
+ *   AES-CTR and with ChaCha20 on `FheUint32` words, to compare the two transciphering paths.
+ *
+ * The benchmarks are configured with environment variables:
+ * - `FHE_AES128_BENCH_PARAMS`: Comma-separated parameter sets, as accepted by `--params`.
+ * - `FHE_AES128_BENCH_THREADS`: Comma-separated rayon thread counts.
+ * - `FHE_AES128_BENCH_BATCHES`: Comma-separated batch sizes, in blocks.
+ * - `FHE_AES128_BENCH_QUICK`: When set, a quick run for CI: the `message-1-carry-1` parameters,
+ *   whose bootstraps are the cheapest, batches of 2 blocks and short measurements.
+ *
+ * ```sh
+ * cargo bench --bench aes
+ * FHE_AES128_BENCH_PARAMS=default,multi-bit FHE_AES128_BENCH_THREADS=8,32 cargo bench --bench aes
+ * FHE_AES128_BENCH_QUICK=1 cargo bench --bench aes -- --noplot
+ * ```
+ */
+
+use clap::ValueEnum;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use fhe_aes128::chacha20::ChaCha20Stream;
+use fhe_aes128::context::FheAesContext;
+use fhe_aes128::encryption::{gal_mul, mix_columns, sub_bytes};
+use fhe_aes128::key_expansion::key_expansion_fhe;
+use fhe_aes128::params::ParameterSet;
+use fhe_aes128::round_keys::{KeySchedule, ScheduleMode};
+use fhe_aes128::transciphering::{AesCtrKeystream, KeystreamGenerator};
+use fhe_aes128::{aes_decrypt_block, aes_encrypt_block};
+use rayon::prelude::*;
+use std::time::Duration;
+use tfhe::prelude::*;
+use tfhe::{generate_keys, ClientKey, FheUint32, FheUint8};
+
+/// The benchmark configuration read from the environment.
+struct Settings {
+    params: Vec<ParameterSet>,
+    threads: Vec<usize>,
+    batches: Vec<usize>,
+    quick: bool,
+}
+
+/// Reads a comma-separated list from the environment variable `name`, or `default`.
+fn env_list<T>(name: &str, default: &str, parse: impl Fn(&str) -> Option<T>) -> Vec<T> {
+    let value = std::env::var(name).unwrap_or_else(|_| default.to_string());
+
+    value
+        .split(',')
+        .map(|x| parse(x.trim()).unwrap_or_else(|| panic!("invalid value {:?} in {}", x, name)))
+        .collect()
+}
+
+impl Settings {
+    fn from_env() -> Self {
+        let quick = std::env::var_os("FHE_AES128_BENCH_QUICK").is_some();
+        let threads = rayon::current_num_threads().to_string();
+
+        let (params, batches) = match quick {
+            true => ("message-1-carry-1", "2"),
+            false => ("default", "2,4,8"),
+        };
+
+        Self {
+            params: env_list("FHE_AES128_BENCH_PARAMS", params, |x| {
+                ParameterSet::from_str(x, true).ok()
+            }),
+            threads: env_list("FHE_AES128_BENCH_THREADS", &threads, |x| x.parse().ok()),
+            batches: env_list("FHE_AES128_BENCH_BATCHES", batches, |x| x.parse().ok()),
+            quick,
+        }
+    }
+}
+
+/// Encrypts `bytes` with the client key.
+fn encrypt_bytes(bytes: &[u8], cks: &ClientKey) -> Vec<FheUint8> {
+    bytes.iter().map(|x| FheUint8::encrypt(*x, cks)).collect()
+}
+
+/// Returns an array of trivial encryptions of 0, to hold the output of a primitive.
+///
+/// Trivial encryptions need the server key, so this must run inside `FheAesContext::install`.
+fn zeros<const N: usize>() -> [FheUint8; N] {
+    std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8))
+}
+
+/// Benchmarks every primitive with the keys of one parameter set in a context of `threads` threads.
+///
+/// Every FHE input and output is built inside the context, like the measured operations.
+fn bench_primitives(
+    c: &mut Criterion,
+    settings: &Settings,
+    params: ParameterSet,
+    threads: usize,
+    cks: &ClientKey,
+    context: &FheAesContext,
+) {
+    let (key, state, expanded_key) = context
+        .install(|| {
+            let key: [FheUint8; 16] = std::array::from_fn(|i| FheUint8::encrypt(i as u8, cks));
+            let state = encrypt_bytes(&[0x32; 16], cks);
+
+            let mut expanded_key = zeros::<176>();
+            key_expansion_fhe(&key, &mut expanded_key).map(|_| (key, state, expanded_key))
+        })
+        .expect("key expansion failed");
+
+    let mut group = c.benchmark_group(format!("{}/{}-threads", params, threads));
+    group
+        .sample_size(10)
+        .warm_up_time(Duration::from_secs(1))
+        .measurement_time(Duration::from_secs(if settings.quick { 5 } else { 60 }));
+
+    group.bench_function("key_expansion", |b| {
+        b.iter_batched(
+            || context.install(zeros::<176>),
+            |mut output| {
+                context
+                    .install(|| key_expansion_fhe(&key, &mut output))
+                    .unwrap();
+                output
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("sub_bytes", |b| {
+        b.iter_batched(
+            || context.install(|| state.clone()),
+            |mut state| {
+                context.install(|| sub_bytes(&mut state)).unwrap();
+                state
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("mix_columns", |b| {
+        b.iter_batched(
+            || context.install(|| state.clone()),
+            |mut state| {
+                context.install(|| mix_columns(&mut state));
+                state
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    for constant in [0x02u8, 0x03, 0x09, 0x0b, 0x0d, 0x0e] {
+        group.bench_with_input(
+            BenchmarkId::new("gal_mul", format!("{:#04x}", constant)),
+            &constant,
+            |b, &constant| {
+                b.iter_batched(
+                    || context.install(|| state[0].clone()),
+                    |byte| context.install(|| gal_mul(byte, constant)),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+
+    group.bench_function("encrypt_block", |b| {
+        b.iter_batched(
+            || context.install(zeros::<16>),
+            |mut output| {
+                context
+                    .install(|| aes_encrypt_block(&state, &mut output, &expanded_key))
+                    .unwrap();
+                output
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("decrypt_block", |b| {
+        b.iter_batched(
+            || context.install(zeros::<16>),
+            |mut output| {
+                context
+                    .install(|| aes_decrypt_block(&state, &mut output, &expanded_key))
+                    .unwrap();
+                output
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    for &blocks in &settings.batches {
+        let inputs: Vec<Vec<FheUint8>> = context.install(|| {
+            (0..blocks)
+                .map(|i| encrypt_bytes(&[i as u8; 16], cks))
+                .collect()
+        });
+
+        group.throughput(Throughput::Bytes(16 * blocks as u64));
+        group.bench_with_input(BenchmarkId::new("batch", blocks), &inputs, |b, inputs| {
+            b.iter(|| {
+                context
+                    .install(|| {
+                        inputs
+                            .par_iter()
+                            .map(|input| {
+                                let mut output = zeros::<16>();
+                                aes_encrypt_block(input, &mut output, &expanded_key).map(|_| output)
+                            })
+                            .collect::<Result<Vec<_>, _>>()
+                    })
+                    .unwrap()
+            })
+        });
+    }
+
+    // The same number of keystream bytes from both transciphering ciphers
+    let (schedule, chacha20_key, nonce) = context.install(|| {
+        let schedule = KeySchedule::from_expanded_key(Box::new(expanded_key), ScheduleMode::Cached);
+        let chacha20_key = encrypt_bytes(&[0x42; 32], cks);
+        let nonce: Vec<FheUint8> = (0..12u8).map(FheUint8::encrypt_trivial).collect();
+
+        (schedule, chacha20_key, nonce)
+    });
+
+    for &blocks in &settings.batches {
+        let len = 16 * blocks;
+        group.throughput(Throughput::Bytes(len as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("aes_ctr_keystream", len),
+            &len,
+            |b, &len| {
+                b.iter(|| {
+                    context
+                        .install(|| AesCtrKeystream::new(&schedule, [0u8; 16]).next_keystream(len))
+                        .unwrap()
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("chacha20_keystream", len),
+            &len,
+            |b, &len| {
+                b.iter_batched(
+                    || {
+                        context
+                            .install(|| ChaCha20Stream::<FheUint32>::new(&chacha20_key, 0, &nonce))
+                            .unwrap()
+                    },
+                    |mut stream| context.install(|| stream.next_keystream(len)).unwrap(),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_aes(c: &mut Criterion) {
+    let settings = Settings::from_env();
+
+    for &params in &settings.params {
+        let (cks, sks) = generate_keys(params.config());
+
+        for &threads in &settings.threads {
+            let context = FheAesContext::new(params, sks.clone(), threads)
+                .expect("cannot build the FHE context");
+
+            bench_primitives(c, &settings, params, threads, &cks, &context);
+        }
+    }
+}
+
+criterion_group!(benches, bench_aes);
+criterion_main!(benches);
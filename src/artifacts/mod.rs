@@ -0,0 +1,304 @@
+/// This module reads and writes the serialized artifacts exchanged between the CLI subcommands.
+/// Splitting the pipeline into artifacts lets the client-side steps (key generation, key encryption,
+/// verification) and the server-side steps (key expansion, encryption, decryption, transciphering)
+/// run on different machines.
+/// It includes functions for performing the following operations:
+/// - `save` / `load`: Writes and reads any serializable value with `bincode`.
+/// - `load_context`: Reads a server key into an `FheAesContext`.
+/// - `open_input` / `create_output`: Opens a file, or stdin / stdout for the path `-`.
+/// - `ChunkWriter` / `ChunkReader`: Writes and reads encrypted bytes as a stream of chunks.
+/// - `read_chunk`: Reads a chunk of clear input bytes.
+///
+/// Every encrypted artifact (encrypted key, expanded key schedule, ciphertext blocks, transciphered
+/// bytes) is a stream of `Vec<FheUint8>` chunks, so any of them can be decrypted by `verify`.
+/// A single `Vec<FheUint8>` written by `save` is a stream of one chunk. Streaming lets files of
+/// any length be processed without holding all of their encrypted bytes in memory.
+///
+/// Every artifact starts with a header recording the TFHE parameter set of its keys or
+/// ciphertexts, so the subcommands can refuse to combine artifacts of different parameter sets.
+use crate::context::FheAesContext;
+use crate::error::Error;
+use crate::params::ParameterSet;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use tfhe::{FheUint8, ServerKey};
+
+/// Identifies the files written by this crate.
+const MAGIC: [u8; 4] = *b"FAES";
+
+/// The header written at the start of every artifact.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    magic: [u8; 4],
+    params: ParameterSet,
+}
+
+/// Writes the header of an artifact produced with `params`.
+fn write_header(writer: &mut impl Write, params: ParameterSet) -> Result<(), Error> {
+    let header = Header {
+        magic: MAGIC,
+        params,
+    };
+
+    bincode::serialize_into(writer, &header)
+        .map_err(|e| Error::Serialization(format!("cannot serialize header: {}", e)))
+}
+
+/// Reads the header of an artifact and returns its parameter set.
+fn read_header(reader: &mut impl Read) -> Result<ParameterSet, Error> {
+    let header: Header = bincode::deserialize_from(reader)
+        .map_err(|e| Error::Serialization(format!("cannot deserialize header: {}", e)))?;
+
+    if header.magic != MAGIC {
+        return Err(Error::Serialization(
+            "not an artifact of this program, or written by an older version".to_string(),
+        ));
+    }
+
+    Ok(header.params)
+}
+
+/// Serializes `value` to the file at `path`, replacing any existing file.
+///
+/// # Arguments
+/// * `path` - The file to write.
+/// * `params` - The parameter set of the keys or ciphertexts in `value`.
+/// * `value` - The value to serialize, e.g. a key or a `Vec<FheUint8>`.
+///
+/// # Errors
+/// This function will return an error if the file cannot be created or the value cannot be serialized.
+pub fn save<T: Serialize>(path: &Path, params: ParameterSet, value: &T) -> Result<(), Error> {
+    let file = File::create(path)
+        .map_err(|e| Error::Io(format!("cannot create {}", path.display()), e))?;
+    let mut writer = BufWriter::new(file);
+
+    write_header(&mut writer, params)?;
+    bincode::serialize_into(&mut writer, value)
+        .map_err(|e| Error::Serialization(format!("cannot serialize {}: {}", path.display(), e)))?;
+
+    writer
+        .flush()
+        .map_err(|e| Error::Io(format!("cannot write {}", path.display()), e))
+}
+
+/// Deserializes a value from the file at `path`.
+///
+/// # Arguments
+/// * `path` - The file to read, written by `save`.
+///
+/// # Returns
+/// * `(ParameterSet, T)` - The parameter set recorded in the file and the value.
+///
+/// # Errors
+/// This function will return an error if the file cannot be opened or does not hold a value of type `T`.
+pub fn load<T: DeserializeOwned>(path: &Path) -> Result<(ParameterSet, T), Error> {
+    let file =
+        File::open(path).map_err(|e| Error::Io(format!("cannot open {}", path.display()), e))?;
+    let mut reader = BufReader::new(file);
+
+    let params = read_header(&mut reader)?;
+    let value = bincode::deserialize_from(&mut reader).map_err(|e| {
+        Error::Serialization(format!("cannot deserialize {}: {}", path.display(), e))
+    })?;
+
+    Ok((params, value))
+}
+
+/// Loads the server key at `path` into a context whose workers all hold it.
+///
+/// # Arguments
+/// * `path` - The server key file written by the `keygen` subcommand.
+/// * `threads` - The number of worker threads of the context, or 0 for the rayon default.
+///
+/// # Returns
+/// * `FheAesContext` - The context, whose parameter set every other input must match.
+///
+/// # Errors
+/// This function will return an error if the server key cannot be loaded or the context cannot be
+/// built.
+pub fn load_context(path: &Path, threads: usize) -> Result<FheAesContext, Error> {
+    let (params, sks): (ParameterSet, ServerKey) = load(path)?;
+
+    FheAesContext::new(params, sks, threads)
+}
+
+/// Opens `path` for reading, or stdin if `path` is `-`.
+///
+/// # Errors
+/// This function will return an error if the file cannot be opened.
+pub fn open_input(path: &Path) -> Result<Box<dyn Read>, Error> {
+    if path == Path::new("-") {
+        return Ok(Box::new(std::io::stdin().lock()));
+    }
+
+    let file =
+        File::open(path).map_err(|e| Error::Io(format!("cannot open {}", path.display()), e))?;
+    Ok(Box::new(file))
+}
+
+/// Opens `path` for writing, or stdout if `path` is `-`.
+///
+/// # Errors
+/// This function will return an error if the file cannot be created.
+pub fn create_output(path: &Path) -> Result<Box<dyn Write>, Error> {
+    if path == Path::new("-") {
+        return Ok(Box::new(std::io::stdout().lock()));
+    }
+
+    let file = File::create(path)
+        .map_err(|e| Error::Io(format!("cannot create {}", path.display()), e))?;
+    Ok(Box::new(file))
+}
+
+/// Reads up to `len` bytes from `reader`; fewer bytes are only returned at the end of the input.
+///
+/// # Errors
+/// This function will return an error if reading fails.
+pub fn read_chunk(reader: &mut impl Read, len: usize) -> Result<Vec<u8>, Error> {
+    let mut chunk = Vec::with_capacity(len);
+    reader
+        .take(len as u64)
+        .read_to_end(&mut chunk)
+        .map_err(|e| Error::Io("cannot read input".to_string(), e))?;
+
+    Ok(chunk)
+}
+
+/// Writes encrypted bytes as a stream of chunks, flushing after each chunk.
+pub struct ChunkWriter {
+    writer: BufWriter<Box<dyn Write>>,
+}
+
+impl ChunkWriter {
+    /// Creates a chunk stream of ciphertexts of the parameter set `params` at `path`, or on
+    /// stdout if `path` is `-`.
+    ///
+    /// # Errors
+    /// This function will return an error if the file cannot be created.
+    pub fn create(path: &Path, params: ParameterSet) -> Result<Self, Error> {
+        let mut writer = BufWriter::new(create_output(path)?);
+        write_header(&mut writer, params)?;
+
+        Ok(Self { writer })
+    }
+
+    /// Appends `chunk` to the stream.
+    ///
+    /// # Errors
+    /// This function will return an error if the chunk cannot be serialized or written.
+    pub fn write_chunk(&mut self, chunk: &[FheUint8]) -> Result<(), Error> {
+        bincode::serialize_into(&mut self.writer, chunk)
+            .map_err(|e| Error::Serialization(format!("cannot serialize chunk: {}", e)))?;
+        self.writer
+            .flush()
+            .map_err(|e| Error::Io("cannot write chunk".to_string(), e))
+    }
+}
+
+/// Reads the chunks of a stream written by `ChunkWriter` or `save`, one at a time.
+pub struct ChunkReader {
+    reader: BufReader<Box<dyn Read>>,
+    params: ParameterSet,
+}
+
+impl ChunkReader {
+    /// Opens the chunk stream at `path`, or on stdin if `path` is `-`, and reads its header.
+    ///
+    /// # Errors
+    /// This function will return an error if the file cannot be opened or has no valid header.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let mut reader = BufReader::new(open_input(path)?);
+        let params = read_header(&mut reader)?;
+
+        Ok(Self { reader, params })
+    }
+
+    /// Returns the parameter set of the ciphertexts in the stream.
+    pub fn params(&self) -> ParameterSet {
+        self.params
+    }
+}
+
+impl Iterator for ChunkReader {
+    type Item = Result<Vec<FheUint8>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let at_end = match self.reader.fill_buf() {
+            Ok(buffer) => buffer.is_empty(),
+            Err(e) => return Some(Err(Error::Io("cannot read chunk".to_string(), e))),
+        };
+        if at_end {
+            return None;
+        }
+
+        Some(
+            bincode::deserialize_from(&mut self.reader)
+                .map_err(|e| Error::Serialization(format!("cannot deserialize chunk: {}", e))),
+        )
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the artifact serialization.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- artifacts::tests::artifacts_round_trip --exact --show-output
+/// ```
+mod tests {
+    use rand::Rng;
+    use tfhe::prelude::*;
+    use tfhe::{generate_keys, ClientKey, ConfigBuilder, FheUint8};
+
+    use super::*;
+    use crate::utils::TestDir;
+
+    #[test]
+    fn artifacts_round_trip() {
+        let config = ConfigBuilder::default().build();
+        let (cks, sks) = generate_keys(config);
+
+        let dir = TestDir::new("artifacts");
+        let client_key_path = dir.join("client_key.bin");
+        let server_key_path = dir.join("server_key.bin");
+        let bytes_path = dir.join("bytes.bin");
+
+        save(&client_key_path, ParameterSet::Default, &cks).unwrap();
+        save(&server_key_path, ParameterSet::Default, &sks).unwrap();
+
+        let data: [u8; 16] = rand::thread_rng().gen();
+        let data_fhe: Vec<FheUint8> = data.iter().map(|x| FheUint8::encrypt(*x, &cks)).collect();
+        save(&bytes_path, ParameterSet::Default, &data_fhe).unwrap();
+
+        let (params, cks): (ParameterSet, ClientKey) = load(&client_key_path).unwrap();
+        assert_eq!(params, ParameterSet::Default);
+        let context = load_context(&server_key_path, 1).unwrap();
+        assert_eq!(context.params(), ParameterSet::Default);
+        let (_, data_fhe): (ParameterSet, Vec<FheUint8>) = load(&bytes_path).unwrap();
+
+        // Exercise the loaded server key before decrypting with the loaded client key
+        let result: Vec<u8> =
+            context.install(|| data_fhe.iter().map(|x| (x ^ 0u8).decrypt(&cks)).collect());
+        assert_eq!(result, data);
+
+        // A chunk stream is read back in order, whatever the chunk sizes
+        let mut writer = ChunkWriter::create(&bytes_path, ParameterSet::MultiBit).unwrap();
+        writer.write_chunk(&data_fhe[..5]).unwrap();
+        writer.write_chunk(&data_fhe[5..]).unwrap();
+        drop(writer);
+
+        let reader = ChunkReader::open(&bytes_path).unwrap();
+        assert_eq!(reader.params(), ParameterSet::MultiBit);
+        let chunks: Vec<Vec<FheUint8>> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(chunks.len(), 2);
+
+        let result: Vec<u8> = chunks.iter().flatten().map(|x| x.decrypt(&cks)).collect();
+        assert_eq!(result, data);
+    }
+}
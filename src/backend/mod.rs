@@ -0,0 +1,371 @@
+/// This module defines the byte backends the AES round functions and the key expansion run on.
+/// The round functions only need XORs, multiplications by `x` in GF(2^8) and table lookups, so
+/// they are generic over `AesByte` and the same circuit runs on encrypted or clear bytes.
+/// It includes the following items:
+/// - `AesByte`: The byte operations used by the AES circuit.
+/// - `FheUint8`: The FHE backend, used in production.
+/// - `u8`: The clear backend, which runs the whole circuit in microseconds for unit tests.
+/// - `CountedByte` / `OpCounter`: A clear backend that counts the operations it performs.
+/// - `OpCounts`: A snapshot of the counted operations, with an estimate of the bootstraps they need.
+///
+/// The counted operations of a clear run are exactly those of the FHE run, since the circuit does
+/// not branch on the values of its bytes.
+use crate::cost::CostModel;
+use crate::error::Error;
+use crate::params::ParameterSet;
+use std::ops::{BitXor, BitXorAssign};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tfhe::prelude::*;
+use tfhe::{FheUint8, MatchValues};
+
+/// A byte the AES circuit can run on, either encrypted or in the clear.
+pub trait AesByte:
+    Clone + Send + Sync + BitXor<Output = Self> + BitXorAssign + BitXorAssign<u8>
+{
+    /// A precomputed lookup table, e.g. the S-Box.
+    type Table: Send + Sync;
+
+    /// Returns the constant byte `value` (a trivial encryption for FHE bytes).
+    fn constant(value: u8) -> Self;
+
+    /// Multiplication by `x` in GF(2^8), modulo the AES polynomial `x^8 + x^4 + x^3 + x + 1`.
+    fn xtime(&self) -> Self;
+
+    /// Precomputes the lookup table mapping each byte `x` to `values[x]`.
+    ///
+    /// # Errors
+    /// This function will return an error if the homomorphic table cannot be built.
+    fn table(values: &[u8; 256]) -> Result<Self::Table, Error>;
+
+    /// Looks the byte up in `table`.
+    ///
+    /// # Errors
+    /// This function will return an error if the homomorphic lookup fails.
+    fn lookup(&self, table: &Self::Table) -> Result<Self, Error>;
+}
+
+impl AesByte for FheUint8 {
+    type Table = MatchValues<u8>;
+
+    fn constant(value: u8) -> Self {
+        FheUint8::encrypt_trivial(value)
+    }
+
+    fn xtime(&self) -> Self {
+        // AES uses the irreducible polynomial x^8 + x^4 + x^3 + x + 1 (0x1b) for field reduction
+        let high_bit_set = (self.clone() & 0x80).ne(0);
+
+        let mut shifted = self.clone();
+        shifted <<= 1u8;
+
+        high_bit_set.if_then_else(&(shifted.clone() ^ 0x1bu8), &shifted)
+    }
+
+    fn table(values: &[u8; 256]) -> Result<Self::Table, Error> {
+        let match_vector = (0u8..=255u8).map(|x| (x, values[x as usize])).collect();
+
+        MatchValues::new(match_vector).map_err(|e| Error::Fhe(e.to_string()))
+    }
+
+    fn lookup(&self, table: &Self::Table) -> Result<Self, Error> {
+        let (result, _): (FheUint8, _) = self
+            .match_value(table)
+            .map_err(|e| Error::Fhe(e.to_string()))?;
+
+        Ok(result)
+    }
+}
+
+impl AesByte for u8 {
+    type Table = [u8; 256];
+
+    fn constant(value: u8) -> Self {
+        value
+    }
+
+    fn xtime(&self) -> Self {
+        (self << 1) ^ if self & 0x80 != 0 { 0x1b } else { 0 }
+    }
+
+    fn table(values: &[u8; 256]) -> Result<Self::Table, Error> {
+        Ok(*values)
+    }
+
+    fn lookup(&self, table: &Self::Table) -> Result<Self, Error> {
+        Ok(table[*self as usize])
+    }
+}
+
+/// Counts the operations performed on `CountedByte`s, from any thread.
+#[derive(Debug, Default)]
+pub struct OpCounter {
+    xor: AtomicU64,
+    scalar_xor: AtomicU64,
+    xtime: AtomicU64,
+    lookup: AtomicU64,
+    clone: AtomicU64,
+}
+
+impl OpCounter {
+    /// Returns the operations counted so far.
+    pub fn counts(&self) -> OpCounts {
+        OpCounts {
+            xor: self.xor.load(Ordering::Relaxed),
+            scalar_xor: self.scalar_xor.load(Ordering::Relaxed),
+            xtime: self.xtime.load(Ordering::Relaxed),
+            lookup: self.lookup.load(Ordering::Relaxed),
+            clone: self.clone.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the operations counted so far and resets the counters, to count the operations of
+    /// the next computation on their own.
+    pub fn take(&self) -> OpCounts {
+        OpCounts {
+            xor: self.xor.swap(0, Ordering::Relaxed),
+            scalar_xor: self.scalar_xor.swap(0, Ordering::Relaxed),
+            xtime: self.xtime.swap(0, Ordering::Relaxed),
+            lookup: self.lookup.swap(0, Ordering::Relaxed),
+            clone: self.clone.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+/// The number of operations of each kind performed by a computation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OpCounts {
+    /// XORs of two bytes.
+    pub xor: u64,
+    /// XORs of a byte with a public constant.
+    pub scalar_xor: u64,
+    /// Multiplications by `x` in GF(2^8).
+    pub xtime: u64,
+    /// Table lookups (S-Box substitutions).
+    pub lookup: u64,
+    /// Copies of bytes.
+    pub clone: u64,
+}
+
+impl OpCounts {
+    /// Estimates the number of programmable bootstraps the operations need on `FheUint8`s with
+    /// the default parameters. See the `cost` module for the cost model.
+    pub fn estimated_pbs(&self) -> u64 {
+        CostModel::new(ParameterSet::Default, 1).cost(self).pbs
+    }
+}
+
+/// A clear byte that records every operation performed on it in an `OpCounter`.
+///
+/// The bytes created with `CountedByte::new` share the counter; constants have none, and the
+/// result of an operation uses the counter of either operand.
+#[derive(Debug)]
+pub struct CountedByte {
+    value: u8,
+    counter: Option<Arc<OpCounter>>,
+}
+
+impl CountedByte {
+    /// Creates a byte counting its operations in `counter`.
+    pub fn new(value: u8, counter: &Arc<OpCounter>) -> Self {
+        Self {
+            value,
+            counter: Some(counter.clone()),
+        }
+    }
+
+    /// Returns the clear value of the byte.
+    pub fn value(&self) -> u8 {
+        self.value
+    }
+
+    /// Increments the counter of `kind`, if the byte has a counter.
+    fn count(&self, kind: impl Fn(&OpCounter) -> &AtomicU64) {
+        if let Some(counter) = &self.counter {
+            kind(counter).fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Clone for CountedByte {
+    fn clone(&self) -> Self {
+        self.count(|counter| &counter.clone);
+
+        Self {
+            value: self.value,
+            counter: self.counter.clone(),
+        }
+    }
+}
+
+impl BitXor for CountedByte {
+    type Output = Self;
+
+    fn bitxor(self, rhs: Self) -> Self {
+        let counter = self.counter.or(rhs.counter);
+        let result = Self {
+            value: self.value ^ rhs.value,
+            counter,
+        };
+        result.count(|counter| &counter.xor);
+
+        result
+    }
+}
+
+impl BitXorAssign for CountedByte {
+    fn bitxor_assign(&mut self, rhs: Self) {
+        self.value ^= rhs.value;
+        if self.counter.is_none() {
+            self.counter = rhs.counter;
+        }
+        self.count(|counter| &counter.xor);
+    }
+}
+
+impl BitXorAssign<u8> for CountedByte {
+    fn bitxor_assign(&mut self, rhs: u8) {
+        self.value ^= rhs;
+        self.count(|counter| &counter.scalar_xor);
+    }
+}
+
+impl AesByte for CountedByte {
+    type Table = [u8; 256];
+
+    fn constant(value: u8) -> Self {
+        Self {
+            value,
+            counter: None,
+        }
+    }
+
+    fn xtime(&self) -> Self {
+        self.count(|counter| &counter.xtime);
+
+        Self {
+            value: self.value.xtime(),
+            counter: self.counter.clone(),
+        }
+    }
+
+    fn table(values: &[u8; 256]) -> Result<Self::Table, Error> {
+        Ok(*values)
+    }
+
+    fn lookup(&self, table: &Self::Table) -> Result<Self, Error> {
+        self.count(|counter| &counter.lookup);
+
+        Ok(Self {
+            value: table[self.value as usize],
+            counter: self.counter.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the clear and counting backends.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- backend::tests::clear_backends --exact --show-output
+/// ```
+mod tests {
+    use aes::cipher::{BlockEncrypt, KeyInit};
+    use aes::Aes128;
+    use rand::Rng;
+
+    use super::*;
+    use crate::key_expansion::key_expansion_fhe;
+    use crate::{aes_decrypt_block, aes_encrypt_block};
+
+    #[test]
+    fn clear_backends() {
+        // FIPS-197 appendix A.1 key expansion
+        let key: [u8; 16] = [
+            0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf,
+            0x4f, 0x3c,
+        ];
+        let mut expanded_key = [0u8; 176];
+        key_expansion_fhe(&key, &mut expanded_key).unwrap();
+        assert_eq!(
+            expanded_key[160..176],
+            [
+                0xd0, 0x14, 0xf9, 0xa8, 0xc9, 0xee, 0x25, 0x89, 0xe1, 0x3f, 0x0c, 0xc8, 0xb6, 0x63,
+                0x0c, 0xa6
+            ]
+        );
+
+        // The clear circuit matches the reference implementation and round-trips
+        let mut rng = rand::thread_rng();
+        let key: [u8; 16] = rng.gen();
+        let input: [u8; 16] = rng.gen();
+
+        let mut expanded_key = [0u8; 176];
+        key_expansion_fhe(&key, &mut expanded_key).unwrap();
+
+        let mut output = [0u8; 16];
+        aes_encrypt_block(&input, &mut output, &expanded_key).unwrap();
+
+        let mut expected = input;
+        Aes128::new((&key).into()).encrypt_block((&mut expected).into());
+        assert_eq!(output, expected);
+
+        let mut decrypted = [0u8; 16];
+        aes_decrypt_block(&output, &mut decrypted, &expanded_key).unwrap();
+        assert_eq!(decrypted, input);
+
+        // Blocks of any other length are rejected instead of panicking
+        for len in [15, 17] {
+            let block = vec![0u8; len];
+            assert!(aes_encrypt_block(&block, &mut output, &expanded_key).is_err());
+            assert!(aes_decrypt_block(&block, &mut output, &expanded_key).is_err());
+        }
+
+        // The counting backend counts the operations of the FHE circuit
+        let key_counter = Arc::new(OpCounter::default());
+        let block_counter = Arc::new(OpCounter::default());
+
+        let key_counted: [CountedByte; 16] =
+            std::array::from_fn(|i| CountedByte::new(key[i], &key_counter));
+        let mut expanded_key: [CountedByte; 176] =
+            std::array::from_fn(|_| CountedByte::constant(0));
+        key_expansion_fhe(&key_counted, &mut expanded_key).unwrap();
+
+        let key_counts = key_counter.counts();
+        assert_eq!(key_counts.lookup, 40);
+        assert_eq!(key_counts.xor, 160);
+        assert_eq!(key_counts.scalar_xor, 10);
+
+        let input_counted: Vec<CountedByte> = input
+            .iter()
+            .map(|x| CountedByte::new(*x, &block_counter))
+            .collect();
+        let mut output: [CountedByte; 16] = std::array::from_fn(|_| CountedByte::constant(0));
+        aes_encrypt_block(&input_counted, &mut output, &expanded_key).unwrap();
+
+        assert_eq!(output.map(|x| x.value()), expected);
+
+        // 11 round key additions, 10 SubBytes and 9 MixColumns of 16 bytes, each MixColumns byte
+        // with 3 XORs and two `gal_mul` of 3 XORs and 4 `xtime` in total
+        let block_counts = block_counter.counts();
+        assert_eq!(block_counts.lookup, 160);
+        assert_eq!(block_counts.xor, 11 * 16 + 9 * 16 * 6);
+        assert_eq!(block_counts.xtime, 9 * 16 * 4);
+        assert_eq!(block_counts.scalar_xor, 0);
+
+        println!(
+            "key expansion: {:?}, ~{} PBS",
+            key_counts,
+            key_counts.estimated_pbs()
+        );
+        println!(
+            "block: {:?}, ~{} PBS",
+            block_counts,
+            block_counts.estimated_pbs()
+        );
+    }
+}
@@ -0,0 +1,442 @@
+/// This module implements a NIST AESAVS test harness for the FHE AES circuit.
+/// It parses the CAVP response files (`.rsp`) of the AES Algorithm Validation Suite and checks
+/// every vector against `key_expansion_fhe`, `aes_encrypt_block` and `aes_decrypt_block`, so the
+/// circuit is validated with standard vectors and not only against the `aes` crate.
+/// It includes the following items:
+/// - `Direction` / `TestVector`: A vector of an `[ENCRYPT]` or `[DECRYPT]` section.
+/// - `parse_rsp`: Parses the vectors of a response file.
+/// - `CavpBackend`: How the harness encrypts and decrypts the bytes of the vectors.
+/// - `Simulated` / `FheBackend`: The clear `u8` backend (fast) and the `FheUint8` backend (slow).
+/// - `check_vector`: Checks a known-answer, multi-block or Monte Carlo vector.
+/// - `FileResult` / `run_file` / `run_directory`: Checks every 128-bit ECB file of a directory.
+///
+/// The supported files are the ECB files of AES-128: `ECBGFSbox128.rsp`, `ECBKeySbox128.rsp`,
+/// `ECBVarKey128.rsp`, `ECBVarTxt128.rsp`, `ECBMMT128.rsp` and `ECBMCT128.rsp`. They are not
+/// distributed with this repository; download them from the NIST CAVP website into a local
+/// fixtures directory.
+///
+/// A Monte Carlo vector chains 1000 block operations, which only completes in reasonable time with
+/// the simulated backend.
+use crate::backend::AesByte;
+use crate::error::Error;
+use crate::key_expansion::key_expansion_fhe;
+use crate::utils::hex_to_bytes;
+use crate::{aes_decrypt_block, aes_encrypt_block};
+use std::path::{Path, PathBuf};
+use tfhe::prelude::*;
+use tfhe::{ClientKey, FheUint8};
+
+/// The number of chained block operations of an AESAVS Monte Carlo vector.
+pub const MONTE_CARLO_ITERATIONS: usize = 1000;
+
+/// The section of a response file a vector belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// `[ENCRYPT]`: the plaintext encrypts to the ciphertext.
+    Encrypt,
+    /// `[DECRYPT]`: the ciphertext decrypts to the plaintext.
+    Decrypt,
+}
+
+/// A test vector of a response file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestVector {
+    /// The `COUNT` of the vector, unique within a section.
+    pub count: usize,
+    /// The section of the vector.
+    pub direction: Direction,
+    /// The AES key.
+    pub key: Vec<u8>,
+    /// The plaintext, one or more 16-byte blocks.
+    pub plaintext: Vec<u8>,
+    /// The ciphertext, as long as the plaintext.
+    pub ciphertext: Vec<u8>,
+}
+
+/// Parses the test vectors of a CAVP response file.
+///
+/// # Arguments
+/// * `text` - The content of the response file.
+///
+/// # Returns
+/// * `Vec<TestVector>` - The vectors, in file order.
+///
+/// # Behavior
+/// - Comments (`#`), blank lines and unknown fields are ignored.
+/// - Vectors before the first section header are encryption vectors.
+///
+/// # Errors
+/// This function will return an error if a line is not a `NAME = value` pair or a section header,
+/// if a field appears before the first `COUNT`, if a value is not valid, or if a vector misses its
+/// key, plaintext or ciphertext, or has texts of different lengths or not made of 16-byte blocks.
+pub fn parse_rsp(text: &str) -> Result<Vec<TestVector>, Error> {
+    let mut direction = Direction::Encrypt;
+    let mut vectors: Vec<TestVector> = vec![];
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match line {
+            "[ENCRYPT]" => direction = Direction::Encrypt,
+            "[DECRYPT]" => direction = Direction::Decrypt,
+            _ if line.starts_with('[') => {}
+            _ => {
+                let (name, value) = line.split_once('=').ok_or_else(|| {
+                    Error::InvalidInput(format!("line {}: expected NAME = value", number + 1))
+                })?;
+                let value = value.trim();
+
+                if name.trim() == "COUNT" {
+                    let count = value.parse().map_err(|_| {
+                        Error::InvalidInput(format!("line {}: invalid COUNT", number + 1))
+                    })?;
+                    vectors.push(TestVector {
+                        count,
+                        direction,
+                        key: vec![],
+                        plaintext: vec![],
+                        ciphertext: vec![],
+                    });
+                    continue;
+                }
+
+                let field = match (vectors.last_mut(), name.trim()) {
+                    (Some(vector), "KEY") => &mut vector.key,
+                    (Some(vector), "PLAINTEXT") => &mut vector.plaintext,
+                    (Some(vector), "CIPHERTEXT") => &mut vector.ciphertext,
+                    (None, "KEY" | "PLAINTEXT" | "CIPHERTEXT") => {
+                        return Err(Error::InvalidInput(format!(
+                            "line {}: field before the first COUNT",
+                            number + 1
+                        )))
+                    }
+                    _ => continue,
+                };
+                *field = hex_to_bytes(value)?;
+            }
+        }
+    }
+
+    for vector in &vectors {
+        if vector.key.is_empty() || vector.plaintext.is_empty() || vector.ciphertext.is_empty() {
+            return Err(Error::InvalidInput(format!(
+                "COUNT = {}: missing KEY, PLAINTEXT or CIPHERTEXT",
+                vector.count
+            )));
+        }
+
+        if vector.plaintext.len() != vector.ciphertext.len() || vector.plaintext.len() % 16 != 0 {
+            return Err(Error::InvalidInput(format!(
+                "COUNT = {}: PLAINTEXT and CIPHERTEXT must be the same number of 16-byte blocks",
+                vector.count
+            )));
+        }
+    }
+
+    Ok(vectors)
+}
+
+/// How the harness turns the clear bytes of the vectors into bytes of a backend and back.
+pub trait CavpBackend {
+    /// The byte backend the AES circuit runs on.
+    type Byte: AesByte;
+
+    /// Converts a clear byte of a vector into a byte of the backend.
+    fn encrypt(&self, value: u8) -> Self::Byte;
+
+    /// Converts a byte of the backend back into a clear byte.
+    fn decrypt(&self, value: &Self::Byte) -> u8;
+}
+
+/// The clear `u8` backend, which checks a whole file in milliseconds.
+pub struct Simulated;
+
+impl CavpBackend for Simulated {
+    type Byte = u8;
+
+    fn encrypt(&self, value: u8) -> u8 {
+        value
+    }
+
+    fn decrypt(&self, value: &u8) -> u8 {
+        *value
+    }
+}
+
+/// The `FheUint8` backend. The server key must be set on the current thread and the rayon pool.
+pub struct FheBackend<'a> {
+    /// The client key, used to encrypt the inputs and decrypt the outputs.
+    pub cks: &'a ClientKey,
+}
+
+impl CavpBackend for FheBackend<'_> {
+    type Byte = FheUint8;
+
+    fn encrypt(&self, value: u8) -> FheUint8 {
+        FheUint8::encrypt(value, self.cks)
+    }
+
+    fn decrypt(&self, value: &FheUint8) -> u8 {
+        value.decrypt(self.cks)
+    }
+}
+
+/// Checks a test vector with the AES circuit on `backend`.
+///
+/// # Arguments
+/// * `backend` - The backend the circuit runs on.
+/// * `vector` - The vector to check. Its key must be 16 bytes long.
+/// * `iterations` - The number of chained block operations: 1 for known-answer and multi-block
+///   vectors, `MONTE_CARLO_ITERATIONS` for Monte Carlo vectors.
+///
+/// # Returns
+/// * `bool` - Whether the circuit produced the expected text.
+///
+/// # Behavior
+/// - The key schedule is expanded with `key_expansion_fhe` on the backend.
+/// - Every 16-byte block of the input is encrypted (or decrypted) independently, as in ECB mode.
+/// - With more than one iteration, the output of each block operation is the input of the next
+///   one and only the last output is compared, as in the AESAVS Monte Carlo test. The chained
+///   blocks stay on the backend.
+///
+/// # Errors
+/// This function will return an error if the key is not 16 bytes long or if a homomorphic
+/// operation fails.
+pub fn check_vector<K: CavpBackend>(
+    backend: &K,
+    vector: &TestVector,
+    iterations: usize,
+) -> Result<bool, Error> {
+    if vector.key.len() != 16 {
+        return Err(Error::InvalidInput(format!(
+            "COUNT = {}: only 128-bit keys are supported, got {} bits",
+            vector.count,
+            vector.key.len() * 8
+        )));
+    }
+
+    let key: [K::Byte; 16] = std::array::from_fn(|i| backend.encrypt(vector.key[i]));
+    let mut expanded_key: [K::Byte; 176] = std::array::from_fn(|_| K::Byte::constant(0));
+    key_expansion_fhe(&key, &mut expanded_key)?;
+
+    let (input, expected) = match vector.direction {
+        Direction::Encrypt => (&vector.plaintext, &vector.ciphertext),
+        Direction::Decrypt => (&vector.ciphertext, &vector.plaintext),
+    };
+
+    let mut output = vec![];
+
+    for block in input.chunks(16) {
+        let mut state: Vec<K::Byte> = block.iter().map(|x| backend.encrypt(*x)).collect();
+
+        for _ in 0..iterations {
+            let mut result: [K::Byte; 16] = std::array::from_fn(|_| K::Byte::constant(0));
+            match vector.direction {
+                Direction::Encrypt => aes_encrypt_block(&state, &mut result, &expanded_key)?,
+                Direction::Decrypt => aes_decrypt_block(&state, &mut result, &expanded_key)?,
+            }
+            state = result.to_vec();
+        }
+
+        output.extend(state.iter().map(|x| backend.decrypt(x)));
+    }
+
+    Ok(output == *expected)
+}
+
+/// The results of a response file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileResult {
+    /// The response file.
+    pub path: PathBuf,
+    /// The number of vectors that passed.
+    pub passed: usize,
+    /// The `COUNT` and direction of the vectors that failed.
+    pub failed: Vec<(usize, Direction)>,
+}
+
+/// Checks the vectors of a response file.
+///
+/// # Arguments
+/// * `path` - The response file. Files whose name contains `MCT` are Monte Carlo files.
+/// * `backend` - The backend the circuit runs on.
+/// * `limit` - The maximum number of vectors to check, e.g. to keep FHE runs short.
+///
+/// # Errors
+/// This function will return an error if the file cannot be read or parsed, or if a homomorphic
+/// operation fails.
+pub fn run_file<K: CavpBackend>(
+    path: &Path,
+    backend: &K,
+    limit: Option<usize>,
+) -> Result<FileResult, Error> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| Error::Io(format!("cannot read {}", path.display()), e))?;
+    let vectors =
+        parse_rsp(&text).map_err(|e| Error::InvalidInput(format!("{}: {}", path.display(), e)))?;
+
+    let monte_carlo = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().contains("MCT"));
+    let iterations = if monte_carlo {
+        MONTE_CARLO_ITERATIONS
+    } else {
+        1
+    };
+
+    let mut result = FileResult {
+        path: path.to_path_buf(),
+        passed: 0,
+        failed: vec![],
+    };
+
+    for vector in vectors.iter().take(limit.unwrap_or(usize::MAX)) {
+        match check_vector(backend, vector, iterations)? {
+            true => result.passed += 1,
+            false => result.failed.push((vector.count, vector.direction)),
+        }
+    }
+
+    Ok(result)
+}
+
+/// Checks every AES-128 ECB response file (`ECB*128.rsp`) of a directory, in name order.
+///
+/// # Errors
+/// This function will return an error if the directory cannot be read or holds no such file, or
+/// the errors of `run_file`.
+pub fn run_directory<K: CavpBackend>(
+    dir: &Path,
+    backend: &K,
+    limit: Option<usize>,
+) -> Result<Vec<FileResult>, Error> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| Error::Io(format!("cannot read {}", dir.display()), e))?;
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name().is_some_and(|name| {
+                let name = name.to_string_lossy();
+                name.starts_with("ECB") && name.ends_with("128.rsp")
+            })
+        })
+        .collect();
+    paths.sort();
+
+    if paths.is_empty() {
+        return Err(Error::InvalidInput(format!(
+            "no ECB*128.rsp file in {}",
+            dir.display()
+        )));
+    }
+
+    paths
+        .iter()
+        .map(|path| run_file(path, backend, limit))
+        .collect()
+}
+
+#[cfg(test)]
+/// This module contains tests for the AESAVS harness.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- cavp::tests::aesavs_vectors --exact --show-output
+/// ```
+///
+/// The test also checks the response files of `$FHE_AES128_CAVP_DIR` (default `fixtures/aesavs`)
+/// with the simulated backend, when the directory exists.
+mod tests {
+    use aes::cipher::{BlockEncrypt, KeyInit};
+    use aes::Aes128;
+
+    use super::*;
+
+    /// FIPS-197 appendix C.1 and the first vectors of `ECBGFSbox128.rsp`, `ECBVarKey128.rsp` and
+    /// `ECBVarTxt128.rsp`.
+    const RSP: &str = "
+# CAVS 11.1
+# Config info for aes_values
+[ENCRYPT]
+
+COUNT = 0
+KEY = 000102030405060708090a0b0c0d0e0f
+PLAINTEXT = 00112233445566778899aabbccddeeff
+CIPHERTEXT = 69c4e0d86a7b0430d8cdb78070b4c55a
+
+COUNT = 1
+KEY = 00000000000000000000000000000000
+PLAINTEXT = f34481ec3cc627bacd5dc3fb08f273e6
+CIPHERTEXT = 0336763e966d92595a567cc9ce537f5e
+
+COUNT = 2
+KEY = 80000000000000000000000000000000
+PLAINTEXT = 00000000000000000000000000000000
+CIPHERTEXT = 0edd33d3c621e546455bd8ba1418bec8
+
+[DECRYPT]
+
+COUNT = 0
+KEY = 00000000000000000000000000000000
+CIPHERTEXT = 3ad78e726c1ec02b7ebfe92b23d9ec34
+PLAINTEXT = 80000000000000000000000000000000
+";
+
+    #[test]
+    fn aesavs_vectors() {
+        let vectors = parse_rsp(RSP).unwrap();
+        assert_eq!(vectors.len(), 4);
+        assert_eq!(vectors[3].direction, Direction::Decrypt);
+
+        for vector in &vectors {
+            assert!(check_vector(&Simulated, vector, 1).unwrap());
+        }
+
+        // A wrong ciphertext fails without an error
+        let mut wrong = vectors[0].clone();
+        wrong.ciphertext[0] ^= 1;
+        assert!(!check_vector(&Simulated, &wrong, 1).unwrap());
+
+        // A Monte Carlo vector, with the expected ciphertext chained through the `aes` crate
+        let mut monte_carlo = vectors[0].clone();
+        let cipher = Aes128::new(monte_carlo.key.as_slice().into());
+        let mut block: [u8; 16] = monte_carlo.plaintext.as_slice().try_into().unwrap();
+        for _ in 0..MONTE_CARLO_ITERATIONS {
+            cipher.encrypt_block((&mut block).into());
+        }
+        monte_carlo.ciphertext = block.to_vec();
+        assert!(check_vector(&Simulated, &monte_carlo, MONTE_CARLO_ITERATIONS).unwrap());
+
+        // Malformed files
+        assert!(matches!(parse_rsp("KEY = 00"), Err(Error::InvalidInput(_))));
+        assert!(matches!(
+            parse_rsp("COUNT = 0\nKEY = 00\nPLAINTEXT = 00"),
+            Err(Error::InvalidInput(_))
+        ));
+
+        // The NIST response files, when they are available locally
+        let dir = std::env::var("FHE_AES128_CAVP_DIR").unwrap_or("fixtures/aesavs".to_string());
+        if !Path::new(&dir).is_dir() {
+            println!("Skipping the NIST response files: {} does not exist", dir);
+            return;
+        }
+
+        for result in run_directory(Path::new(&dir), &Simulated, None).unwrap() {
+            println!(
+                "{}: {} passed, {} failed",
+                result.path.display(),
+                result.passed,
+                result.failed.len()
+            );
+            assert!(result.failed.is_empty());
+        }
+    }
+}
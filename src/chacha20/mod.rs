@@ -0,0 +1,346 @@
+/// This module implements the ChaCha20 stream cipher (RFC 8439) using Fully Homomorphic Encryption (FHE).
+/// ChaCha20 is an ARX cipher: its rounds only use 32-bit additions, rotations and XORs, which map
+/// directly onto `FheUint32` operations and need no lookup tables.
+/// It includes the following items:
+/// - `ChaChaWord`: The 32-bit word type the cipher runs on, implemented for `u32` (clear) and `FheUint32` (encrypted).
+/// - `quarter_round`: The ChaCha quarter round on four words.
+/// - `ChaCha20Stream`: The keystream generator, generic over `ChaChaWord`.
+/// - `chacha20_counter`: The initial block counter carried by a 16-byte IV.
+///
+/// The four quarter rounds of a column or diagonal round touch disjoint words, so they are
+/// computed in parallel.
+use crate::error::{check_len, Error};
+use crate::transciphering::KeystreamGenerator;
+use rayon::prelude::*;
+use tfhe::prelude::*;
+use tfhe::{FheUint32, FheUint8};
+
+/// The first four words of the state, "expand 32-byte k" in little-endian.
+const CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+/// Indices of the four quarter rounds of a column round.
+const COLUMNS: [[usize; 4]; 4] = [[0, 4, 8, 12], [1, 5, 9, 13], [2, 6, 10, 14], [3, 7, 11, 15]];
+
+/// Indices of the four quarter rounds of a diagonal round.
+const DIAGONALS: [[usize; 4]; 4] = [[0, 5, 10, 15], [1, 6, 11, 12], [2, 7, 8, 13], [3, 4, 9, 14]];
+
+/// A 32-bit word of the cipher state, either in the clear or encrypted.
+pub trait ChaChaWord: Clone + Send + Sync {
+    /// The byte type used for keys, nonces and the keystream.
+    type Byte: Clone + Send + Sync;
+
+    /// Returns the constant word `value` (a trivial encryption for FHE words).
+    fn constant(value: u32) -> Self;
+
+    /// Addition modulo 2^32.
+    fn add(&self, other: &Self) -> Self;
+
+    /// Bitwise XOR.
+    fn xor(&self, other: &Self) -> Self;
+
+    /// Rotation to the left by a public amount.
+    fn rotate(&self, n: u32) -> Self;
+
+    /// Builds a word from 4 little-endian bytes.
+    fn from_le_bytes(bytes: &[Self::Byte]) -> Self;
+
+    /// Splits a word into 4 little-endian bytes.
+    fn to_le_bytes(&self) -> [Self::Byte; 4];
+}
+
+impl ChaChaWord for u32 {
+    type Byte = u8;
+
+    fn constant(value: u32) -> Self {
+        value
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        self.wrapping_add(*other)
+    }
+
+    fn xor(&self, other: &Self) -> Self {
+        self ^ other
+    }
+
+    fn rotate(&self, n: u32) -> Self {
+        self.rotate_left(n)
+    }
+
+    fn from_le_bytes(bytes: &[u8]) -> Self {
+        u32::from_le_bytes(bytes.try_into().unwrap())
+    }
+
+    fn to_le_bytes(&self) -> [u8; 4] {
+        u32::to_le_bytes(*self)
+    }
+}
+
+impl ChaChaWord for FheUint32 {
+    type Byte = FheUint8;
+
+    fn constant(value: u32) -> Self {
+        FheUint32::encrypt_trivial(value)
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn xor(&self, other: &Self) -> Self {
+        self ^ other
+    }
+
+    fn rotate(&self, n: u32) -> Self {
+        self.rotate_left(n)
+    }
+
+    fn from_le_bytes(bytes: &[FheUint8]) -> Self {
+        bytes
+            .iter()
+            .enumerate()
+            .fold(FheUint32::encrypt_trivial(0u32), |word, (i, byte)| {
+                word | (FheUint32::cast_from(byte.clone()) << (8 * i) as u32)
+            })
+    }
+
+    fn to_le_bytes(&self) -> [FheUint8; 4] {
+        std::array::from_fn(|i| FheUint8::cast_from(self >> (8 * i) as u32))
+    }
+}
+
+/// The ChaCha quarter round on the words `[a, b, c, d]`.
+pub fn quarter_round<W: ChaChaWord>([a, b, c, d]: [W; 4]) -> [W; 4] {
+    let a = a.add(&b);
+    let d = d.xor(&a).rotate(16);
+    let c = c.add(&d);
+    let b = b.xor(&c).rotate(12);
+    let a = a.add(&b);
+    let d = d.xor(&a).rotate(8);
+    let c = c.add(&d);
+    let b = b.xor(&c).rotate(7);
+
+    [a, b, c, d]
+}
+
+/// The ChaCha20 keystream generator over clear or encrypted words.
+pub struct ChaCha20Stream<W: ChaChaWord> {
+    /// The 8 key words, state words 4 to 11.
+    key: Vec<W>,
+    /// The block counter, state word 12. It is public. `None` once the block of counter
+    /// `u32::MAX` has been produced: the counter must not wrap, or the keystream would repeat.
+    counter: Option<u32>,
+    /// The 3 nonce words, state words 13 to 15.
+    nonce: Vec<W>,
+    /// Keystream bytes produced but not yet returned.
+    buffer: Vec<W::Byte>,
+}
+
+impl<W: ChaChaWord> ChaCha20Stream<W> {
+    /// Initializes ChaCha20 with a 256-bit key, a 32-bit initial block counter and a 96-bit nonce.
+    ///
+    /// # Arguments
+    /// * `key` - The 32 key bytes, usually encrypted.
+    /// * `counter` - The counter of the first block.
+    /// * `nonce` - The 12 nonce bytes, usually public and trivially encrypted.
+    ///
+    /// # Errors
+    /// This function will return an error if the key is not 32 bytes long or the nonce is not
+    /// 12 bytes long.
+    pub fn new(key: &[W::Byte], counter: u32, nonce: &[W::Byte]) -> Result<Self, Error> {
+        check_len("ChaCha20 key", 32, key.len())?;
+        check_len("ChaCha20 nonce", 12, nonce.len())?;
+
+        Ok(Self {
+            key: key.chunks(4).map(W::from_le_bytes).collect(),
+            counter: Some(counter),
+            nonce: nonce.chunks(4).map(W::from_le_bytes).collect(),
+            buffer: vec![],
+        })
+    }
+
+    /// Computes the ChaCha20 block function for the block counter `counter`.
+    ///
+    /// # Returns
+    /// * `[W; 16]` - The 16 output words, before serialization.
+    ///
+    /// # Behavior
+    /// - Runs 10 double rounds (a column round followed by a diagonal round).
+    /// - The quarter rounds of each round are computed in parallel using `par_iter()`.
+    /// - The input state is added word by word to the result.
+    pub fn block(&self, counter: u32) -> [W; 16] {
+        let input: Vec<W> = CONSTANTS
+            .iter()
+            .map(|x| W::constant(*x))
+            .chain(self.key.iter().cloned())
+            .chain(std::iter::once(W::constant(counter)))
+            .chain(self.nonce.iter().cloned())
+            .collect();
+
+        let mut state = input.clone();
+        for _ in 0..10 {
+            for round in [COLUMNS, DIAGONALS] {
+                let results: Vec<[W; 4]> = round
+                    .par_iter()
+                    .map(|indices| quarter_round(indices.map(|i| state[i].clone())))
+                    .collect();
+
+                for (indices, words) in round.iter().zip(results) {
+                    for (i, word) in indices.iter().zip(words) {
+                        state[*i] = word;
+                    }
+                }
+            }
+        }
+
+        let output: Vec<W> = state
+            .par_iter()
+            .zip(input.par_iter())
+            .map(|(x, y)| x.add(y))
+            .collect();
+
+        std::array::from_fn(|i| output[i].clone())
+    }
+
+    /// Returns the next `len` keystream bytes.
+    ///
+    /// # Errors
+    /// This function will return an error if the keystream would need a block past the counter
+    /// `u32::MAX`, since RFC 8439 forbids reusing a counter under the same key and nonce.
+    pub fn next_bytes(&mut self, len: usize) -> Result<Vec<W::Byte>, Error> {
+        while self.buffer.len() < len {
+            let counter = self.counter.ok_or_else(|| {
+                Error::InvalidInput(
+                    "ChaCha20 block counter exhausted, the keystream would repeat".to_string(),
+                )
+            })?;
+
+            let block = self.block(counter);
+            self.buffer
+                .extend(block.iter().flat_map(|word| word.to_le_bytes()));
+            self.counter = counter.checked_add(1);
+        }
+
+        Ok(self.buffer.drain(..len).collect())
+    }
+}
+
+/// Reads the initial ChaCha20 block counter from the first 4 bytes of the IV (little-endian).
+pub fn chacha20_counter(iv: &[u8; 16]) -> u32 {
+    u32::from_le_bytes([iv[0], iv[1], iv[2], iv[3]])
+}
+
+impl KeystreamGenerator for ChaCha20Stream<FheUint32> {
+    fn next_keystream(&mut self, len: usize) -> Result<Vec<FheUint8>, Error> {
+        self.next_bytes(len)
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the ChaCha20 stream cipher.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following commands:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- chacha20::tests::chacha20_clear --exact --show-output
+/// cargo test --release --package fhe-aes128 --lib -- chacha20::tests::chacha20_fhe --exact --show-output
+/// ```
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::context::FheAesContext;
+    use crate::params::ParameterSet;
+    use crate::utils::hex_to_bytes;
+
+    /// Size of a keystream block in bytes.
+    const BLOCK_SIZE: usize = 64;
+
+    #[test]
+    fn chacha20_clear() {
+        // RFC 8439, section 2.1.1
+        assert_eq!(
+            quarter_round([0x11111111u32, 0x01020304, 0x9b8d6f43, 0x01234567]),
+            [0xea2a92f4, 0xcb1cf8ce, 0x4581472e, 0x5881c4bb]
+        );
+
+        // RFC 8439, section 2.3.2
+        let key: Vec<u8> = (0u8..32).collect();
+        let mut chacha =
+            ChaCha20Stream::<u32>::new(&key, 1, &hex_to_bytes("000000090000004a00000000").unwrap())
+                .unwrap();
+        assert_eq!(
+            chacha.next_bytes(BLOCK_SIZE).unwrap(),
+            hex_to_bytes(
+                "10f1e7e4d13b5915500fdd1fa32071c4c7d1f4c733c068030422aa9ac3d46c4e\
+                 d2826446079faa0914c2d705d98b02a2b5129cd1de164eb9cbd083e8a2503c4e"
+            )
+            .unwrap()
+        );
+
+        // RFC 8439, section 2.4.2
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let mut chacha =
+            ChaCha20Stream::<u32>::new(&key, 1, &hex_to_bytes("000000000000004a00000000").unwrap())
+                .unwrap();
+        let ciphertext: Vec<u8> = chacha
+            .next_bytes(plaintext.len())
+            .unwrap()
+            .iter()
+            .zip(plaintext)
+            .map(|(k, p)| k ^ p)
+            .collect();
+        assert_eq!(
+            ciphertext,
+            hex_to_bytes(
+                "6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0b\
+                 f91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d8\
+                 07ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab7793736\
+                 5af90bbf74a35be6b40b8eedf2785e42874d"
+            )
+            .unwrap()
+        );
+
+        // The block of counter u32::MAX is the last one: the counter does not wrap to 0
+        let mut chacha = ChaCha20Stream::<u32>::new(&key, u32::MAX, &[0; 12]).unwrap();
+        assert!(matches!(
+            chacha.next_bytes(2 * BLOCK_SIZE),
+            Err(Error::InvalidInput(_))
+        ));
+        let mut chacha = ChaCha20Stream::<u32>::new(&key, u32::MAX, &[0; 12]).unwrap();
+        assert!(chacha.next_bytes(BLOCK_SIZE).is_ok());
+        assert!(chacha.next_bytes(1).is_err());
+    }
+
+    #[test]
+    fn chacha20_fhe() {
+        let mut rng = rand::thread_rng();
+        let key: [u8; 32] = rng.gen();
+        let nonce: [u8; 12] = rng.gen();
+        let counter: u32 = rng.gen();
+
+        let (cks, context) = FheAesContext::generate(ParameterSet::Default, 0).unwrap();
+
+        context.install(|| {
+            let key_fhe: Vec<FheUint8> = key.iter().map(|x| FheUint8::encrypt(*x, &cks)).collect();
+            let nonce_fhe: Vec<FheUint8> = nonce
+                .iter()
+                .map(|x| FheUint8::encrypt_trivial(*x))
+                .collect();
+
+            let mut expected = ChaCha20Stream::<u32>::new(&key, counter, &nonce).unwrap();
+            let mut chacha =
+                ChaCha20Stream::<FheUint32>::new(&key_fhe, counter, &nonce_fhe).unwrap();
+
+            let result: Vec<u8> = chacha
+                .next_keystream(16)
+                .unwrap()
+                .iter()
+                .map(|x| x.decrypt(&cks))
+                .collect();
+            assert_eq!(result, expected.next_bytes(16).unwrap());
+        });
+    }
+}
@@ -0,0 +1,327 @@
+/// This module implements round-level checkpoints of a homomorphic AES block computation, so a
+/// computation interrupted by the preemption of its machine resumes from its last completed round
+/// instead of starting over.
+/// It includes the following items:
+/// - `RoundCheckpoint`: The encrypted AES state after a round, and the direction of the
+///   computation.
+/// - `CheckpointFile`: A checkpoint file, rewritten after every round, and the encryption and
+///   decryption of a block resuming from it.
+///
+/// The checkpoint only holds the state: resuming is only correct with the same key schedule and,
+/// for a computation that had not completed round 0, the same input block.
+use crate::artifacts::{load, save};
+use crate::error::{check_len, Error};
+use crate::params::ParameterSet;
+use crate::round_keys::KeySchedule;
+use crate::{decrypt_rounds, encrypt_rounds};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tfhe::prelude::*;
+use tfhe::FheUint8;
+
+/// The direction of a checkpointed AES computation.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Encrypt,
+    Decrypt,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Encrypt => write!(f, "encryption"),
+            Direction::Decrypt => write!(f, "decryption"),
+        }
+    }
+}
+
+/// The encrypted AES state after a round.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RoundCheckpoint {
+    /// The direction of the computation.
+    pub direction: Direction,
+    /// The last completed round, from 0 (the initial round key addition) to 10.
+    pub round: usize,
+    /// The 16 bytes of the state after that round.
+    pub state: Vec<FheUint8>,
+}
+
+/// The checkpoint file of one block computation.
+pub struct CheckpointFile {
+    path: PathBuf,
+    params: ParameterSet,
+}
+
+impl CheckpointFile {
+    /// Creates the handle of the checkpoint file at `path`, for ciphertexts of `params`. The file
+    /// itself is only written by the first checkpoint.
+    pub fn new(path: &Path, params: ParameterSet) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            params,
+        }
+    }
+
+    /// Returns the path of the checkpoint file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the last checkpoint.
+    ///
+    /// # Returns
+    /// * `Option<RoundCheckpoint>` - The checkpoint, or `None` if there is no checkpoint file.
+    ///
+    /// # Errors
+    /// This function will return an error if the file cannot be read, was produced with another
+    /// parameter set, or does not hold the state of a completed round.
+    pub fn load(&self) -> Result<Option<RoundCheckpoint>, Error> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let (params, checkpoint): (ParameterSet, RoundCheckpoint) = load(&self.path)?;
+        self.params.check("Checkpoint", params)?;
+
+        if checkpoint.round > 10 {
+            return Err(Error::Serialization(format!(
+                "checkpoint {} is after round {}, AES-128 has rounds 0 to 10",
+                self.path.display(),
+                checkpoint.round
+            )));
+        }
+        check_len("Checkpointed state", 16, checkpoint.state.len())?;
+
+        Ok(Some(checkpoint))
+    }
+
+    /// Replaces the checkpoint. The new checkpoint is written to a temporary file and renamed, so
+    /// an interruption while writing keeps the previous checkpoint.
+    ///
+    /// # Errors
+    /// This function will return an error if the checkpoint cannot be written.
+    pub fn save(&self, checkpoint: &RoundCheckpoint) -> Result<(), Error> {
+        let mut partial = self.path.clone().into_os_string();
+        partial.push(".tmp");
+        let partial = PathBuf::from(partial);
+
+        save(&partial, self.params, checkpoint)?;
+        std::fs::rename(&partial, &self.path)
+            .map_err(|e| Error::Io(format!("cannot create {}", self.path.display()), e))
+    }
+
+    /// Deletes the checkpoint file, if any.
+    ///
+    /// # Errors
+    /// This function will return an error if the file exists and cannot be deleted.
+    pub fn remove(&self) -> Result<(), Error> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::Io(
+                format!("cannot delete {}", self.path.display()),
+                e,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Encrypts a block, checkpointing the state after every round; see `run`.
+    ///
+    /// # Errors
+    /// This function will return an error if the checkpoint is of a decryption or cannot be read
+    /// or written, or if a homomorphic S-Box lookup fails.
+    pub fn encrypt_block(
+        &self,
+        input: &[FheUint8],
+        schedule: &KeySchedule<FheUint8>,
+    ) -> Result<[FheUint8; 16], Error> {
+        self.run(Direction::Encrypt, input, schedule)
+    }
+
+    /// Decrypts a block, checkpointing the state after every round; see `run`.
+    ///
+    /// # Errors
+    /// This function will return an error if the checkpoint is of an encryption or cannot be read
+    /// or written, or if a homomorphic S-Box lookup fails.
+    pub fn decrypt_block(
+        &self,
+        input: &[FheUint8],
+        schedule: &KeySchedule<FheUint8>,
+    ) -> Result<[FheUint8; 16], Error> {
+        self.run(Direction::Decrypt, input, schedule)
+    }
+
+    /// Runs an AES computation, or resumes it from the checkpoint file if there is one.
+    ///
+    /// # Behavior
+    /// Without a checkpoint, the computation starts from `input`; with one, `input` is ignored
+    /// and the computation continues after the checkpointed round. The state is checkpointed
+    /// after every round, including the last one, so the file then holds the output until it is
+    /// removed: call `remove` once the output is persisted.
+    fn run(
+        &self,
+        direction: Direction,
+        input: &[FheUint8],
+        schedule: &KeySchedule<FheUint8>,
+    ) -> Result<[FheUint8; 16], Error> {
+        let (start, state) = match self.load()? {
+            Some(checkpoint) if checkpoint.direction != direction => {
+                return Err(Error::InvalidInput(format!(
+                    "checkpoint {} is of an AES {}, not an AES {}",
+                    self.path.display(),
+                    checkpoint.direction,
+                    direction
+                )))
+            }
+            Some(checkpoint) => (checkpoint.round + 1, checkpoint.state),
+            None => {
+                check_len("AES block", 16, input.len())?;
+                (0, input.to_vec())
+            }
+        };
+
+        let on_round = |round, state: &[FheUint8]| {
+            self.save(&RoundCheckpoint {
+                direction,
+                round,
+                state: state.to_vec(),
+            })
+        };
+
+        let mut output = std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+        match direction {
+            Direction::Encrypt => {
+                let round_keys = schedule.forward()?.skip(start);
+                encrypt_rounds(state, start, &mut output, round_keys, on_round)?;
+            }
+            Direction::Decrypt => {
+                let round_keys = schedule.backward()?.skip(start);
+                decrypt_rounds(state, start, &mut output, round_keys, on_round)?;
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the round checkpoints.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- checkpoint::tests::round_checkpoints --exact --show-output
+/// ```
+mod tests {
+    use aes::cipher::{BlockEncrypt, KeyInit};
+    use aes::Aes128;
+    use rand::Rng;
+    use tfhe::prelude::*;
+
+    use super::*;
+    use crate::context::FheAesContext;
+    use crate::round_keys::ScheduleMode;
+    use crate::utils::{key_expansion_clear, TestDir};
+    use crate::{
+        aes_decrypt_block_checkpointed, aes_decrypt_block_from_round,
+        aes_encrypt_block_checkpointed, aes_encrypt_block_from_round,
+    };
+
+    #[test]
+    fn round_checkpoints() {
+        let mut rng = rand::thread_rng();
+        let key: [u8; 16] = rng.gen();
+        let input: [u8; 16] = rng.gen();
+        let expanded_key = key_expansion_clear(&key);
+
+        let mut expected = input;
+        Aes128::new((&key).into()).encrypt_block((&mut expected).into());
+
+        // Clear bytes: resuming from the state after any round gives the same output
+        let mut states = vec![];
+        let mut output = [0u8; 16];
+        aes_encrypt_block_checkpointed(&input, &mut output, &expanded_key, |round, state| {
+            states.push((round, state.to_vec()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(output, expected);
+        assert_eq!(states.len(), 11);
+
+        for (round, state) in &states {
+            let mut resumed = [0u8; 16];
+            aes_encrypt_block_from_round(state, *round, &mut resumed, &expanded_key, |_, _| Ok(()))
+                .unwrap();
+            assert_eq!(resumed, expected);
+        }
+
+        let mut states = vec![];
+        aes_decrypt_block_checkpointed(&expected, &mut output, &expanded_key, |round, state| {
+            states.push((round, state.to_vec()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(output, input);
+
+        for (round, state) in &states {
+            let mut resumed = [0u8; 16];
+            aes_decrypt_block_from_round(state, *round, &mut resumed, &expanded_key, |_, _| Ok(()))
+                .unwrap();
+            assert_eq!(resumed, input);
+        }
+        assert!(aes_encrypt_block_from_round(
+            &input,
+            11,
+            &mut output,
+            &expanded_key,
+            |_, _| Ok(())
+        )
+        .is_err());
+
+        // Encrypted bytes: an encryption preempted after round 4 resumes from its checkpoint file
+        let dir = TestDir::new("checkpoint");
+
+        let (cks, context) = FheAesContext::generate(ParameterSet::Default, 0).unwrap();
+        let input_fhe: Vec<FheUint8> = input.iter().map(|x| FheUint8::encrypt(*x, &cks)).collect();
+        let expanded_key_fhe: Box<[FheUint8; 176]> = Box::new(std::array::from_fn(|i| {
+            FheUint8::encrypt(expanded_key[i], &cks)
+        }));
+        let file = CheckpointFile::new(&dir.join("block.round"), ParameterSet::Default);
+
+        let output_fhe = context.install(|| {
+            let mut output = std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+            let preempted = aes_encrypt_block_checkpointed(
+                &input_fhe,
+                &mut output,
+                &expanded_key_fhe,
+                |round, state| {
+                    file.save(&RoundCheckpoint {
+                        direction: Direction::Encrypt,
+                        round,
+                        state: state.to_vec(),
+                    })?;
+                    match round {
+                        4 => Err(Error::Fhe("preempted".to_string())),
+                        _ => Ok(()),
+                    }
+                },
+            );
+            assert!(preempted.is_err());
+            assert_eq!(file.load().unwrap().unwrap().round, 4);
+
+            let schedule =
+                KeySchedule::from_expanded_key(expanded_key_fhe.clone(), ScheduleMode::Cached);
+            assert!(file.decrypt_block(&input_fhe, &schedule).is_err());
+            file.encrypt_block(&input_fhe, &schedule).unwrap()
+        });
+
+        let result: Vec<u8> = output_fhe.iter().map(|x| x.decrypt(&cks)).collect();
+        assert_eq!(result, expected);
+        assert_eq!(file.load().unwrap().unwrap().round, 10);
+
+        file.remove().unwrap();
+        assert!(file.load().unwrap().is_none());
+    }
+}
@@ -0,0 +1,1422 @@
+/// This module implements the CLI subcommands, one per stage of the FHE AES pipeline.
+/// Each subcommand reads its inputs from and writes its outputs to serialized artifacts (see the
+/// `artifacts` module), so the client-side and server-side stages can run on different machines.
+/// It includes the following subcommands:
+/// - `keygen` (client): Generates the client key and the server key.
+/// - `encrypt-key` (client): Encrypts a symmetric key with the client key.
+/// - `expand` (server): Expands an encrypted AES key into the encrypted key schedule.
+/// - `encrypt` / `decrypt` (server): Runs FHE AES encryption or decryption on 16-byte blocks.
+/// - `transcipher` (server): Turns a symmetric ciphertext into FHE ciphertexts of the plaintext.
+/// - `verify` (client): Decrypts any encrypted artifact and compares it with the expected bytes.
+/// - `cavp`: Checks the AES circuit against the NIST AESAVS response files.
+/// - `cost`: Estimates the homomorphic operations and the runtime of the AES circuit, without keys.
+/// - `serve` (server) / `client` (client): A long-lived server answering transciphering requests
+///   over a local socket, and the matching client.
+/// - `job` (server): Resumable encryption and transciphering jobs, persisted block by block to a
+///   work directory.
+/// - `encrypt-pattern` (client) / `search` (server): Encrypted search of AES-CTR encrypted data
+///   for an encrypted pattern.
+///
+/// `encrypt`, `decrypt` and `transcipher` stream their input and output chunk by chunk, accept `-`
+/// for stdin and stdout, and report their progress on stderr.
+///
+/// Every subcommand returns an `Error` instead of panicking; `main` turns it into an exit code.
+/// The server-side subcommands check that every input artifact was produced with the parameter
+/// set of the server key before computing.
+use crate::artifacts::{
+    create_output, load, load_context, open_input, read_chunk, save, ChunkReader, ChunkWriter,
+};
+use crate::cavp::{run_directory, FheBackend, Simulated};
+use crate::chacha20::{chacha20_counter, ChaCha20Stream};
+use crate::context::FheAesContext;
+use crate::cost::{analyze, CostModel};
+use crate::daemon::{Client, Endpoint, Server};
+use crate::error::{check_len, Error};
+use crate::jobs::{JobKind, JobQueue, JobSpec, JobState, JobStatus, WorkDir};
+use crate::key_expansion::{inv_key_expansion_fhe, key_expansion_fhe};
+use crate::key_input::{read_block, read_bytes};
+use crate::kreyvium::KreyviumStream;
+use crate::params::ParameterSet;
+use crate::registry::{KeyRegistry, TranscipherRequest};
+use crate::report::ReportFormat;
+use crate::round_keys::{KeySchedule, ScheduleMode};
+use crate::search::{search, search_aes_ctr, SearchMode};
+use crate::transciphering::{transcipher, AesCtrKeystream, Cipher, KeystreamGenerator};
+use crate::trivium::TriviumStream;
+use crate::utils::{hex_to_bytes, increment_counter, key_expansion_clear};
+use crate::{aes_decrypt_block_with_schedule, aes_encrypt_block_with_schedule};
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use clap::{Args, Subcommand, ValueEnum};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tfhe::prelude::*;
+use tfhe::{generate_keys, ClientKey, FheBool, FheUint32, FheUint8, ServerKey};
+use zeroize::Zeroizing;
+
+/// The client ID of the server key loaded by `serve` and `job` in their key registry.
+const SERVE_CLIENT: &str = "default";
+
+/// Arguments of the `keygen` subcommand.
+#[derive(Args, Debug)]
+pub struct KeygenArgs {
+    /// Where to write the client key. It must stay on the client.
+    #[arg(long, default_value = "client_key.bin")]
+    client_key: PathBuf,
+
+    /// Where to write the server key, sent to the server.
+    #[arg(long, default_value = "server_key.bin")]
+    server_key: PathBuf,
+
+    /// The TFHE parameter set of the keys, recorded in every artifact derived from them.
+    #[arg(long, value_enum, default_value_t = ParameterSet::Default)]
+    params: ParameterSet,
+}
+
+/// Arguments of the `encrypt-key` subcommand.
+#[derive(Args, Debug)]
+pub struct EncryptKeyArgs {
+    /// The client key written by `keygen`.
+    #[arg(long, default_value = "client_key.bin")]
+    client_key: PathBuf,
+
+    /// The symmetric key: hexadecimal (separators allowed), `base64:<data>`, `file:<path>`
+    /// (raw bytes), `env:<VAR>` or `prompt`. It is wiped from memory once encrypted.
+    #[arg(short, long)]
+    key: String,
+
+    /// The cipher the key belongs to, used to check the key length.
+    #[arg(short, long, value_enum, default_value_t = Cipher::Aes)]
+    cipher: Cipher,
+
+    /// Encrypt the last AES round key instead of the key, for `expand --from-last-round-key`.
+    #[arg(long)]
+    last_round_key: bool,
+
+    /// Where to write the encrypted key.
+    #[arg(short, long, default_value = "encrypted_key.bin")]
+    output: PathBuf,
+}
+
+/// Arguments of the `expand` subcommand.
+#[derive(Args, Debug)]
+pub struct ExpandArgs {
+    /// The server key written by `keygen`.
+    #[arg(long, default_value = "server_key.bin")]
+    server_key: PathBuf,
+
+    /// The number of worker threads, or 0 for one per CPU.
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// The encrypted AES key written by `encrypt-key`.
+    #[arg(long, default_value = "encrypted_key.bin")]
+    encrypted_key: PathBuf,
+
+    /// The encrypted key is the last round key, written by `encrypt-key --last-round-key`: the
+    /// schedule is expanded backwards from it.
+    #[arg(long)]
+    from_last_round_key: bool,
+
+    /// Whether to write all round keys (`cached`), or round keys 0 and 10 only (`on-the-fly`),
+    /// from which `encrypt`, `decrypt` and `transcipher` generate the others for every block.
+    #[arg(long, value_enum, default_value_t = ScheduleMode::Cached)]
+    schedule: ScheduleMode,
+
+    /// Where to write the encrypted key schedule.
+    #[arg(short, long, default_value = "expanded_key.bin")]
+    output: PathBuf,
+}
+
+/// Arguments of the `encrypt` subcommand.
+#[derive(Args, Debug)]
+pub struct EncryptArgs {
+    /// The server key written by `keygen`.
+    #[arg(long, default_value = "server_key.bin")]
+    server_key: PathBuf,
+
+    /// The number of worker threads, or 0 for one per CPU.
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// The encrypted key schedule written by `expand`.
+    #[arg(long, default_value = "expanded_key.bin")]
+    expanded_key: PathBuf,
+
+    /// The initial counter block, in any format accepted by `encrypt-key --key`.
+    #[arg(short, long)]
+    iv: String,
+
+    /// The file to encrypt in counter mode, or `-` for stdin. Without it, the counter blocks
+    /// themselves are encrypted.
+    #[arg(long)]
+    input: Option<PathBuf>,
+
+    /// The number of counter blocks to encrypt when there is no input.
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    number_of_outputs: u32,
+
+    /// The number of input bytes processed and written at once.
+    #[arg(long, default_value_t = 1024)]
+    chunk_size: usize,
+
+    /// Where to write the encrypted bytes, or `-` for stdout.
+    #[arg(short, long, default_value = "ciphertext.bin")]
+    output: PathBuf,
+}
+
+/// Arguments of the `decrypt` subcommand.
+#[derive(Args, Debug)]
+pub struct DecryptArgs {
+    /// The server key written by `keygen`.
+    #[arg(long, default_value = "server_key.bin")]
+    server_key: PathBuf,
+
+    /// The number of worker threads, or 0 for one per CPU.
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// The encrypted key schedule written by `expand`.
+    #[arg(long, default_value = "expanded_key.bin")]
+    expanded_key: PathBuf,
+
+    /// The encrypted blocks to decrypt, e.g. written by `encrypt`, or `-` for stdin.
+    #[arg(long, default_value = "ciphertext.bin")]
+    input: PathBuf,
+
+    /// Where to write the decrypted blocks, still encrypted under FHE, or `-` for stdout.
+    #[arg(short, long, default_value = "plaintext.bin")]
+    output: PathBuf,
+}
+
+/// Arguments of the `transcipher` subcommand.
+#[derive(Args, Debug)]
+pub struct TranscipherArgs {
+    /// The server key written by `keygen`.
+    #[arg(long, default_value = "server_key.bin")]
+    server_key: PathBuf,
+
+    /// The number of worker threads, or 0 for one per CPU.
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// The cipher that produced the symmetric ciphertext.
+    #[arg(short, long, value_enum, default_value_t = Cipher::Aes)]
+    cipher: Cipher,
+
+    /// The encrypted key schedule written by `expand`. Required for AES.
+    #[arg(long)]
+    expanded_key: Option<PathBuf>,
+
+    /// The encrypted key written by `encrypt-key`. Required for the stream ciphers.
+    #[arg(long)]
+    encrypted_key: Option<PathBuf>,
+
+    /// The public IV, in any format accepted by `encrypt-key --key`. For ChaCha20, the first 4 bytes are the little-endian
+    /// initial block counter and the last 12 bytes are the nonce.
+    #[arg(short, long)]
+    iv: String,
+
+    /// The raw symmetric ciphertext bytes, or `-` for stdin.
+    #[arg(long)]
+    input: PathBuf,
+
+    /// The number of input bytes processed and written at once.
+    #[arg(long, default_value_t = 1024)]
+    chunk_size: usize,
+
+    /// Where to write the plaintext bytes encrypted under FHE, or `-` for stdout.
+    #[arg(short, long, default_value = "transciphered.bin")]
+    output: PathBuf,
+}
+
+/// Arguments of the `verify` subcommand.
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// The client key written by `keygen`.
+    #[arg(long, default_value = "client_key.bin")]
+    client_key: PathBuf,
+
+    /// The encrypted artifact to decrypt, or `-` for stdin.
+    #[arg(long)]
+    input: PathBuf,
+
+    /// Where to write the decrypted bytes, or `-` for stdout. Without it, they are printed in
+    /// hexadecimal.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// The expected clear bytes in hexadecimal.
+    #[arg(long, conflicts_with_all = ["key", "iv"])]
+    expected: Option<String>,
+
+    /// The clear AES key, in any format accepted by `encrypt-key --key`. With `--iv`, the expected bytes are the AES encryption of the counter
+    /// blocks, as produced by `encrypt`.
+    #[arg(short, long, requires = "iv")]
+    key: Option<String>,
+
+    /// The initial counter block used by `encrypt`.
+    #[arg(short, long, requires = "key")]
+    iv: Option<String>,
+}
+
+/// Arguments of the `cost` subcommand.
+#[derive(Args, Debug)]
+pub struct CostArgs {
+    /// The TFHE parameter set to estimate.
+    #[arg(long, value_enum, default_value_t = ParameterSet::Default)]
+    params: ParameterSet,
+
+    /// The number of cores of the runtime estimate. Defaults to the size of the rayon pool.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    cores: Option<u32>,
+
+    /// The number of encrypted blocks.
+    #[arg(short, long, default_value_t = 1)]
+    number_of_outputs: u64,
+
+    /// The latency of one bootstrap on one core, in milliseconds. Defaults to a rough value for
+    /// the parameter set.
+    #[arg(long)]
+    pbs_latency_ms: Option<f64>,
+
+    /// Write the breakdown in the given format instead of a table.
+    #[arg(long, value_enum)]
+    report: Option<ReportFormat>,
+
+    /// Where to write the breakdown, or `-` for stdout.
+    #[arg(short, long, default_value = "-")]
+    output: PathBuf,
+}
+
+/// The backends the `cavp` subcommand can run the AES circuit on.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CavpMode {
+    /// Clear bytes: checks every file, Monte Carlo included, in well under a second.
+    Simulated,
+    /// Encrypted bytes, with freshly generated keys: minutes per vector.
+    Fhe,
+}
+
+/// Arguments of the `cavp` subcommand.
+#[derive(Args, Debug)]
+pub struct CavpArgs {
+    /// The directory holding the NIST AESAVS response files (`ECB*128.rsp`).
+    #[arg(long, default_value = "fixtures/aesavs")]
+    dir: PathBuf,
+
+    /// The backend the AES circuit runs on.
+    #[arg(long, value_enum, default_value_t = CavpMode::Simulated)]
+    mode: CavpMode,
+
+    /// Check at most this many vectors per file.
+    #[arg(long)]
+    limit: Option<usize>,
+
+    /// The TFHE parameter set of the keys, with `--mode fhe`.
+    #[arg(long, value_enum, default_value_t = ParameterSet::Default)]
+    params: ParameterSet,
+}
+
+/// Arguments of the `serve` subcommand.
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// The server key written by `keygen`.
+    #[arg(long, default_value = "server_key.bin")]
+    server_key: PathBuf,
+
+    /// The number of worker threads, or 0 for one per CPU.
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// A key schedule written by `expand`, as `<ID>=<PATH>`; repeat for every key served.
+    #[arg(long = "key", value_name = "ID=PATH", required = true)]
+    keys: Vec<String>,
+
+    /// Where to listen: `unix:<path>` (or a bare path) for a Unix domain socket, or
+    /// `tcp:<host>:<port>` on a loopback address.
+    #[arg(long, default_value = "unix:fhe-aes128.sock")]
+    listen: String,
+}
+
+/// Arguments of the `client` subcommand.
+#[derive(Args, Debug)]
+pub struct ClientArgs {
+    /// The server to connect to, as accepted by `serve --listen`.
+    #[arg(long, default_value = "unix:fhe-aes128.sock")]
+    connect: String,
+
+    /// The ID of the AES key the data was encrypted with, as given to `serve --key`.
+    #[arg(long)]
+    key_id: String,
+
+    /// The initial counter block, in any format accepted by `encrypt-key --key`.
+    #[arg(short, long)]
+    iv: String,
+
+    /// The raw AES-CTR ciphertext bytes, or `-` for stdin.
+    #[arg(long)]
+    input: PathBuf,
+
+    /// Where to write the plaintext bytes, encrypted under FHE, or `-` for stdout.
+    #[arg(short, long, default_value = "transciphered.bin")]
+    output: PathBuf,
+}
+
+/// Arguments of the `encrypt-pattern` subcommand.
+#[derive(Args, Debug)]
+pub struct EncryptPatternArgs {
+    /// The client key written by `keygen`.
+    #[arg(long, default_value = "client_key.bin")]
+    client_key: PathBuf,
+
+    /// The pattern to search for, in any format accepted by `encrypt-key --key`. It is wiped from
+    /// memory once encrypted.
+    #[arg(short, long)]
+    pattern: String,
+
+    /// Where to write the encrypted pattern.
+    #[arg(short, long, default_value = "pattern.bin")]
+    output: PathBuf,
+}
+
+/// Arguments of the `search` subcommand.
+#[derive(Args, Debug)]
+pub struct SearchArgs {
+    /// The server key written by `keygen`.
+    #[arg(long, default_value = "server_key.bin")]
+    server_key: PathBuf,
+
+    /// The number of worker threads, or 0 for one per CPU.
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// The encrypted key schedule written by `expand`. Required with `--input`.
+    #[arg(long, default_value = "expanded_key.bin")]
+    expanded_key: PathBuf,
+
+    /// The initial counter block, in any format accepted by `encrypt-key --key`. Required with
+    /// `--input`.
+    #[arg(short, long, requires = "input")]
+    iv: Option<String>,
+
+    /// The raw AES-CTR ciphertext bytes to transcipher and search, or `-` for stdin.
+    #[arg(long, conflicts_with = "transciphered", requires = "iv")]
+    input: Option<PathBuf>,
+
+    /// Data already transciphered by `transcipher`, searched without transciphering.
+    #[arg(long, required_unless_present = "input")]
+    transciphered: Option<PathBuf>,
+
+    /// The encrypted pattern written by `encrypt-pattern`.
+    #[arg(long, default_value = "pattern.bin")]
+    pattern: PathBuf,
+
+    /// What to compute: `equals` and `contains` give a 1-byte flag, `count` a 4-byte big-endian
+    /// count.
+    #[arg(short, long, value_enum, default_value_t = SearchMode::Contains)]
+    mode: SearchMode,
+
+    /// Where to write the encrypted result, to decrypt with `verify`.
+    #[arg(short, long, default_value = "search_result.bin")]
+    output: PathBuf,
+}
+
+/// Arguments of the `job` subcommand.
+#[derive(Args, Debug)]
+pub struct JobArgs {
+    /// The work directory holding the jobs and their completed blocks.
+    #[arg(long, default_value = "jobs")]
+    work_dir: PathBuf,
+
+    #[command(subcommand)]
+    action: JobAction,
+}
+
+/// The actions of the `job` subcommand.
+#[derive(Subcommand, Debug)]
+pub enum JobAction {
+    /// Start a job and run it until it completes, is cancelled or the process is stopped.
+    Submit(JobSubmitArgs),
+    /// Run the pending jobs of the work directory, e.g. after the process was stopped.
+    Resume(JobResumeArgs),
+    /// Print the progress of a job, or of every job.
+    Status {
+        /// The ID of the job; all jobs without it.
+        job_id: Option<String>,
+    },
+    /// Cancel a job, including one being run by another process.
+    Cancel {
+        /// The ID of the job.
+        job_id: String,
+    },
+    /// Write the encrypted bytes of a completed job.
+    Output {
+        /// The ID of the job.
+        job_id: String,
+
+        /// Where to write the encrypted bytes.
+        #[arg(short, long, default_value = "ciphertext.bin")]
+        output: PathBuf,
+    },
+    /// Delete a job and its completed blocks.
+    Remove {
+        /// The ID of the job.
+        job_id: String,
+    },
+}
+
+/// Arguments of `job submit`.
+#[derive(Args, Debug)]
+pub struct JobSubmitArgs {
+    /// The server key written by `keygen`.
+    #[arg(long, default_value = "server_key.bin")]
+    server_key: PathBuf,
+
+    /// The number of worker threads, or 0 for one per CPU.
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// The key schedule written by `expand`, as `<ID>=<PATH>`. The job records the ID, so
+    /// `job resume` must be given the same key under the same ID.
+    #[arg(long = "key", value_name = "ID=PATH")]
+    key: String,
+
+    /// The initial counter block, in any format accepted by `encrypt-key --key`.
+    #[arg(short, long)]
+    iv: String,
+
+    /// An AES-CTR ciphertext to transcipher. Without it, the counter blocks themselves are
+    /// encrypted.
+    #[arg(long)]
+    input: Option<PathBuf>,
+
+    /// The number of counter blocks to encrypt when there is no input.
+    #[arg(
+        short,
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with = "input"
+    )]
+    number_of_outputs: u64,
+
+    /// Where to write the encrypted bytes once the job completes. They can also be written later
+    /// with `job output`.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+/// Arguments of `job resume`.
+#[derive(Args, Debug)]
+pub struct JobResumeArgs {
+    /// The server key written by `keygen`.
+    #[arg(long, default_value = "server_key.bin")]
+    server_key: PathBuf,
+
+    /// The number of worker threads of each job, or 0 for one per CPU.
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// The number of jobs run at the same time.
+    #[arg(long, default_value_t = 1)]
+    workers: usize,
+
+    /// A key schedule written by `expand`, as `<ID>=<PATH>`; repeat for the keys of every job.
+    #[arg(long = "key", value_name = "ID=PATH", required = true)]
+    keys: Vec<String>,
+}
+
+/// Generates a new client key and server key.
+pub fn keygen(args: KeygenArgs) -> Result<(), Error> {
+    let (cks, sks) = generate_keys(args.params.config());
+
+    save(&args.client_key, args.params, &cks)?;
+    save(&args.server_key, args.params, &sks)?;
+
+    println!(
+        "Client key written to {}, server key written to {} ({} parameters)",
+        args.client_key.display(),
+        args.server_key.display(),
+        args.params
+    );
+
+    Ok(())
+}
+
+/// Encrypts a symmetric key byte by byte with the client key.
+pub fn encrypt_key(args: EncryptKeyArgs) -> Result<(), Error> {
+    let (params, cks): (ParameterSet, ClientKey) = load(&args.client_key)?;
+
+    let key = read_bytes(&args.key, "key")?;
+    check_len(
+        &format!("{:?} key", args.cipher),
+        args.cipher.key_len(),
+        key.len(),
+    )?;
+
+    let key = match args.last_round_key {
+        true if args.cipher != Cipher::Aes => {
+            return Err(Error::InvalidInput(
+                "--last-round-key is only available for AES".to_string(),
+            ))
+        }
+        // Round key 10 of the clear key expansion
+        true => {
+            let clear_key = Zeroizing::new(std::array::from_fn(|i| key[i]));
+            let expanded_key = Zeroizing::new(key_expansion_clear(&clear_key));
+            Zeroizing::new(expanded_key[160..176].to_vec())
+        }
+        false => key,
+    };
+
+    let key_fhe: Vec<FheUint8> = key.iter().map(|x| FheUint8::encrypt(*x, &cks)).collect();
+    save(&args.output, params, &key_fhe)?;
+
+    println!("Encrypted key written to {}", args.output.display());
+
+    Ok(())
+}
+
+/// Expands an encrypted AES key into the encrypted key schedule, cached or on the fly.
+pub fn expand(args: ExpandArgs) -> Result<(), Error> {
+    let context = load_context(&args.server_key, args.threads)?;
+    let params = context.params();
+
+    context.install(|| {
+        let (key_params, key_fhe): (ParameterSet, Vec<FheUint8>) = load(&args.encrypted_key)?;
+        params.check("Encrypted key", key_params)?;
+        check_len("AES key", 16, key_fhe.len())?;
+        let key_fhe: [FheUint8; 16] = std::array::from_fn(|i| key_fhe[i].clone());
+
+        let mut expanded_key: [FheUint8; 176] =
+            std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+
+        let key_expansion_time = Instant::now();
+        match args.from_last_round_key {
+            true => inv_key_expansion_fhe(&key_fhe, &mut expanded_key)?,
+            false => key_expansion_fhe(&key_fhe, &mut expanded_key)?,
+        }
+        let key_expansion_duration = key_expansion_time.elapsed().as_secs();
+
+        let schedule = KeySchedule::from_expanded_key(Box::new(expanded_key), args.schedule);
+        save(&args.output, params, &schedule.to_bytes())?;
+
+        println!(
+            "AES key expansion took {} seconds, {} schedule of {} bytes written to {}",
+            key_expansion_duration,
+            schedule.mode(),
+            schedule.stored_bytes(),
+            args.output.display()
+        );
+
+        Ok(())
+    })
+}
+
+/// Loads an encrypted AES key schedule written by `expand`, cached or on the fly.
+///
+/// # Errors
+/// This function will return an error if the file cannot be loaded, was produced with another
+/// parameter set than `params`, or holds neither 176 bytes nor 32 bytes.
+fn load_key_schedule(path: &Path, params: ParameterSet) -> Result<KeySchedule<FheUint8>, Error> {
+    let (key_params, expanded_key): (ParameterSet, Vec<FheUint8>) = load(path)?;
+    params.check("Expanded key", key_params)?;
+
+    KeySchedule::from_bytes(expanded_key)
+}
+
+/// Loads a server key and the key schedules given as `<ID>=<PATH>` into a key registry.
+///
+/// # Returns
+/// * `(ParameterSet, Arc<KeyRegistry>)` - The parameter set of the server key and the registry.
+///
+/// # Errors
+/// This function will return an error if a key is not given as `<ID>=<PATH>`, or if the server
+/// key or a key schedule cannot be loaded.
+fn load_registry(
+    server_key: &Path,
+    threads: usize,
+    keys: &[String],
+) -> Result<(ParameterSet, Arc<KeyRegistry>), Error> {
+    let (params, sks): (ParameterSet, ServerKey) = load(server_key)?;
+    let registry = Arc::new(KeyRegistry::new(keys.len()));
+    registry.add_client(SERVE_CLIENT, params, sks, threads)?;
+
+    for key in keys {
+        let (key_id, path) = key
+            .split_once('=')
+            .ok_or_else(|| Error::InvalidInput(format!("--key {:?} must be <ID>=<PATH>", key)))?;
+
+        let schedule = load_key_schedule(Path::new(path), params)?;
+        registry.insert_key(key_id, SERVE_CLIENT, schedule)?;
+    }
+
+    Ok((params, registry))
+}
+
+/// Prints the progress of a streaming command on stderr.
+///
+/// # Arguments
+/// * `processed` - The number of input bytes processed so far.
+/// * `total` - The total number of input bytes, unknown when reading from stdin.
+fn report_progress(processed: usize, total: Option<u64>) {
+    match total {
+        Some(total) if total > 0 => eprint!(
+            "\r{} / {} bytes ({}%)",
+            processed,
+            total,
+            processed as u64 * 100 / total
+        ),
+        _ => eprint!("\r{} bytes", processed),
+    }
+}
+
+/// Returns the size of the input at `path`, or `None` for stdin.
+fn input_len(path: &Path) -> Option<u64> {
+    if path == Path::new("-") {
+        return None;
+    }
+
+    std::fs::metadata(path).ok().map(|metadata| metadata.len())
+}
+
+/// XORs a clear input stream with an encrypted keystream, one chunk at a time.
+///
+/// # Arguments
+/// * `generator` - The encrypted keystream.
+/// * `input` - The clear input file, or `-` for stdin.
+/// * `output` - The chunk stream to write, or `-` for stdout.
+/// * `chunk_size` - The number of bytes read, processed and written at once.
+/// * `params` - The parameter set of the keystream, recorded in the output.
+///
+/// # Returns
+/// * `usize` - The number of bytes processed.
+///
+/// # Behavior
+/// - Only one chunk of encrypted bytes is held in memory at a time.
+/// - The progress is written to stderr after each chunk.
+///
+/// # Errors
+/// This function will return an error if `chunk_size` is 0, if the input cannot be read, if the
+/// output cannot be written or if the keystream cannot be generated.
+fn stream_keystream(
+    generator: &mut impl KeystreamGenerator,
+    input: &Path,
+    output: &Path,
+    chunk_size: usize,
+    params: ParameterSet,
+) -> Result<usize, Error> {
+    if chunk_size == 0 {
+        return Err(Error::InvalidInput(
+            "chunk size must be positive".to_string(),
+        ));
+    }
+
+    let total = input_len(input);
+    let mut reader = open_input(input)?;
+    let mut writer = ChunkWriter::create(output, params)?;
+    let mut processed = 0;
+
+    loop {
+        let chunk = read_chunk(&mut reader, chunk_size)?;
+        if chunk.is_empty() {
+            break;
+        }
+
+        writer.write_chunk(&transcipher(generator, &chunk)?)?;
+
+        processed += chunk.len();
+        report_progress(processed, total);
+    }
+    eprintln!();
+
+    Ok(processed)
+}
+
+/// Encrypts a file or stdin with FHE AES in counter mode, or, without an input,
+/// `number_of_outputs` consecutive counter blocks.
+pub fn encrypt(args: EncryptArgs) -> Result<(), Error> {
+    let context = load_context(&args.server_key, args.threads)?;
+    let params = context.params();
+
+    context.install(|| {
+        let schedule = load_key_schedule(&args.expanded_key, params)?;
+
+        let mut counter = *read_block(&args.iv, "IV")?;
+
+        let computation_time = Instant::now();
+
+        if let Some(input) = &args.input {
+            let processed = stream_keystream(
+                &mut AesCtrKeystream::new(&schedule, counter),
+                input,
+                &args.output,
+                args.chunk_size,
+                params,
+            )?;
+
+            eprintln!(
+                "AES-CTR encryption of {} bytes took {} seconds, written to {}",
+                processed,
+                computation_time.elapsed().as_secs(),
+                args.output.display()
+            );
+            return Ok(());
+        }
+
+        let mut writer = ChunkWriter::create(&args.output, params)?;
+
+        for i in 0..args.number_of_outputs {
+            // The counter is public, so it is encrypted trivially
+            let input: Vec<FheUint8> = counter
+                .iter()
+                .map(|x| FheUint8::encrypt_trivial(*x))
+                .collect();
+
+            let mut block: [FheUint8; 16] = std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+            aes_encrypt_block_with_schedule(&input, &mut block, &schedule)?;
+
+            writer.write_chunk(&block)?;
+            counter = increment_counter(&counter);
+
+            report_progress(
+                (i as usize + 1) * 16,
+                Some(args.number_of_outputs as u64 * 16),
+            );
+        }
+        eprintln!();
+
+        let computation_duration = computation_time.elapsed().as_secs();
+
+        eprintln!(
+            "AES of {} outputs took {} seconds, written to {}",
+            args.number_of_outputs,
+            computation_duration,
+            args.output.display()
+        );
+
+        Ok(())
+    })
+}
+
+/// Decrypts encrypted blocks with FHE AES; the result stays encrypted under FHE.
+pub fn decrypt(args: DecryptArgs) -> Result<(), Error> {
+    let context = load_context(&args.server_key, args.threads)?;
+    let params = context.params();
+
+    context.install(|| {
+        let schedule = load_key_schedule(&args.expanded_key, params)?;
+
+        let mut writer = ChunkWriter::create(&args.output, params)?;
+        // Chunks are not necessarily block-aligned, so the trailing bytes wait for the next chunk
+        let mut pending: Vec<FheUint8> = vec![];
+        let mut processed = 0;
+
+        let computation_time = Instant::now();
+
+        let reader = ChunkReader::open(&args.input)?;
+        params.check("Input", reader.params())?;
+
+        for chunk in reader {
+            pending.extend(chunk?);
+
+            let whole_blocks = pending.len() / 16 * 16;
+            let mut output: Vec<FheUint8> = vec![];
+
+            for block in pending[..whole_blocks].chunks(16) {
+                let mut state: [FheUint8; 16] =
+                    std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+                aes_decrypt_block_with_schedule(block, &mut state, &schedule)?;
+                output.extend(state);
+            }
+
+            pending.drain(..whole_blocks);
+            writer.write_chunk(&output)?;
+
+            processed += whole_blocks;
+            report_progress(processed, None);
+        }
+        eprintln!();
+
+        if !pending.is_empty() {
+            return Err(Error::InvalidInput(format!(
+                "input must be a whole number of blocks, {} trailing bytes",
+                pending.len()
+            )));
+        }
+
+        let computation_duration = computation_time.elapsed().as_secs();
+
+        eprintln!(
+            "AES decryption of {} outputs took {} seconds, written to {}",
+            processed / 16,
+            computation_duration,
+            args.output.display()
+        );
+
+        Ok(())
+    })
+}
+
+/// Transciphers a raw symmetric ciphertext, read from a file or stdin, into FHE ciphertexts of
+/// the plaintext.
+pub fn transcipher_file(args: TranscipherArgs) -> Result<(), Error> {
+    let context = load_context(&args.server_key, args.threads)?;
+    let params = context.params();
+
+    context.install(|| {
+        let iv = *read_block(&args.iv, "IV")?;
+
+        let key_fhe = || -> Result<Vec<FheUint8>, Error> {
+            let path = args.encrypted_key.as_ref().ok_or_else(|| {
+                Error::InvalidInput(format!("{:?} requires --encrypted-key", args.cipher))
+            })?;
+            let (key_params, key_fhe): (ParameterSet, Vec<FheUint8>) = load(path)?;
+            params.check("Encrypted key", key_params)?;
+            check_len(
+                &format!("{:?} key", args.cipher),
+                args.cipher.key_len(),
+                key_fhe.len(),
+            )?;
+            Ok(key_fhe)
+        };
+
+        // The IV is public, so it is encrypted trivially
+        let iv_fhe: Vec<FheUint8> = iv.iter().map(|x| FheUint8::encrypt_trivial(*x)).collect();
+
+        let computation_time = Instant::now();
+
+        let (input, output, chunk_size) = (&args.input, &args.output, args.chunk_size);
+        let processed = match args.cipher {
+            Cipher::Aes => {
+                let path = args.expanded_key.as_ref().ok_or_else(|| {
+                    Error::InvalidInput("AES requires --expanded-key".to_string())
+                })?;
+                let schedule = load_key_schedule(path, params)?;
+                stream_keystream(
+                    &mut AesCtrKeystream::new(&schedule, iv),
+                    input,
+                    output,
+                    chunk_size,
+                    params,
+                )?
+            }
+            Cipher::Trivium => stream_keystream(
+                &mut TriviumStream::<FheBool>::new(&key_fhe()?[..10], &iv_fhe[..10])?,
+                input,
+                output,
+                chunk_size,
+                params,
+            )?,
+            Cipher::Kreyvium => stream_keystream(
+                &mut KreyviumStream::<FheBool>::new(&key_fhe()?, &iv_fhe)?,
+                input,
+                output,
+                chunk_size,
+                params,
+            )?,
+            Cipher::Chacha20 => stream_keystream(
+                &mut ChaCha20Stream::<FheUint32>::new(
+                    &key_fhe()?,
+                    chacha20_counter(&iv),
+                    &iv_fhe[4..],
+                )?,
+                input,
+                output,
+                chunk_size,
+                params,
+            )?,
+        };
+
+        let computation_duration = computation_time.elapsed().as_secs();
+
+        eprintln!(
+            "{:?} transciphering of {} bytes took {} seconds, written to {}",
+            args.cipher,
+            processed,
+            computation_duration,
+            args.output.display()
+        );
+
+        Ok(())
+    })
+}
+
+/// Estimates the homomorphic operations and the runtime of a key expansion and some block
+/// encryptions, without keys (see the `cost` module).
+///
+/// # Errors
+/// This function will return an error if the output cannot be written.
+pub fn cost(args: CostArgs) -> Result<(), Error> {
+    let cores = args
+        .cores
+        .map_or_else(rayon::current_num_threads, |x| x as usize);
+
+    let mut model = CostModel::new(args.params, cores);
+    if let Some(latency) = args.pbs_latency_ms {
+        model = model.with_pbs_latency(latency);
+    }
+
+    let breakdown = analyze(&model, args.number_of_outputs)?;
+
+    let mut writer = create_output(&args.output)?;
+    breakdown
+        .write(args.report, &mut writer)
+        .map_err(|e| Error::Io("cannot write cost breakdown".to_string(), e))
+}
+
+/// Checks the AES circuit against the NIST AESAVS response files of a directory.
+///
+/// # Errors
+/// This function will return an error if the files cannot be read or parsed, or if a vector fails.
+pub fn cavp(args: CavpArgs) -> Result<(), Error> {
+    let results = match args.mode {
+        CavpMode::Simulated => run_directory(&args.dir, &Simulated, args.limit)?,
+        CavpMode::Fhe => {
+            let (cks, context) = FheAesContext::generate(args.params, 0)?;
+
+            context.install(|| run_directory(&args.dir, &FheBackend { cks: &cks }, args.limit))?
+        }
+    };
+
+    let mut failed = 0;
+    for result in &results {
+        println!(
+            "{}: {} passed, {} failed",
+            result.path.display(),
+            result.passed,
+            result.failed.len()
+        );
+        for (count, direction) in &result.failed {
+            println!("  COUNT = {} ({:?}) failed", count, direction);
+        }
+        failed += result.failed.len();
+    }
+
+    if failed > 0 {
+        return Err(Error::Verification(format!(
+            "{} AESAVS vectors failed",
+            failed
+        )));
+    }
+
+    Ok(())
+}
+
+/// Decrypts an encrypted artifact with the client key and checks it against the expected bytes.
+///
+/// # Errors
+/// This function will return an error if an artifact cannot be read, if the output cannot be
+/// written, or if the decrypted bytes differ from the expected bytes.
+pub fn verify(args: VerifyArgs) -> Result<(), Error> {
+    let (params, cks): (ParameterSet, ClientKey) = load(&args.client_key)?;
+
+    let mut writer = args.output.as_deref().map(create_output).transpose()?;
+    let mut result: Vec<u8> = vec![];
+
+    let reader = ChunkReader::open(&args.input)?;
+    params.check("Input", reader.params())?;
+
+    for chunk in reader {
+        let clear: Vec<u8> = chunk?.iter().map(|x| x.decrypt(&cks)).collect();
+
+        if let Some(writer) = writer.as_mut() {
+            writer
+                .write_all(&clear)
+                .map_err(|e| Error::Io("cannot write output".to_string(), e))?;
+        }
+        result.extend(clear);
+    }
+
+    let expected: Option<Vec<u8>> = match (&args.expected, &args.key, &args.iv) {
+        (Some(expected), _, _) => Some(hex_to_bytes(expected)?),
+        (None, Some(key), Some(iv)) => {
+            let aes_cipher = Aes128::new((&*read_block(key, "key")?).into());
+            let mut counter = *read_block(iv, "IV")?;
+            let mut expected = vec![];
+
+            for _ in 0..result.len().div_ceil(16) {
+                let mut block = counter;
+                aes_cipher.encrypt_block((&mut block).into());
+                expected.extend(block);
+                counter = increment_counter(&counter);
+            }
+
+            expected.truncate(result.len());
+            Some(expected)
+        }
+        _ => None,
+    };
+
+    // The decrypted bytes are printed in hexadecimal when they are not written to an output
+    if writer.is_none() {
+        for x in &result {
+            print!("{:02x}", x);
+        }
+        println!();
+    }
+
+    if let Some(expected) = expected {
+        if result != expected {
+            return Err(Error::Verification(
+                "decrypted bytes do not match the expected bytes".to_string(),
+            ));
+        }
+        eprintln!("Verification of {} bytes succeeded", result.len());
+    }
+
+    Ok(())
+}
+
+/// Serves AES-CTR transciphering requests on a socket until the process is stopped, with the
+/// server key and key schedules loaded once (see the `daemon` module).
+///
+/// # Errors
+/// This function will return an error if the keys cannot be loaded or the socket cannot be bound.
+pub fn serve(args: ServeArgs) -> Result<(), Error> {
+    let endpoint: Endpoint = args.listen.parse()?;
+
+    let (params, registry) = load_registry(&args.server_key, args.threads, &args.keys)?;
+
+    let server = Server::bind(&endpoint, registry)?;
+    eprintln!(
+        "Serving {} keys on {} ({} parameters)",
+        args.keys.len(),
+        server.endpoint(),
+        params
+    );
+
+    server.run()
+}
+
+/// Sends an AES-CTR ciphertext to a `serve` process and writes the transciphered bytes.
+///
+/// # Errors
+/// This function will return an error if the input cannot be read, the server cannot be reached
+/// or fails to transcipher the data, or the output cannot be written.
+pub fn client(args: ClientArgs) -> Result<(), Error> {
+    let endpoint: Endpoint = args.connect.parse()?;
+    let iv = *read_block(&args.iv, "IV")?;
+
+    let mut ciphertext = vec![];
+    open_input(&args.input)?
+        .read_to_end(&mut ciphertext)
+        .map_err(|e| Error::Io(format!("cannot read {}", args.input.display()), e))?;
+
+    let computation_time = Instant::now();
+
+    let mut client = Client::connect(&endpoint)?;
+    let (params, bytes) = client.transcipher(TranscipherRequest {
+        key_id: args.key_id,
+        iv,
+        ciphertext,
+    })?;
+
+    save(&args.output, params, &bytes)?;
+
+    eprintln!(
+        "Transciphering of {} bytes by {} took {} seconds, written to {}",
+        bytes.len(),
+        endpoint,
+        computation_time.elapsed().as_secs(),
+        args.output.display()
+    );
+
+    Ok(())
+}
+
+/// Runs, resumes, inspects or cancels the resumable jobs of a work directory.
+///
+/// # Errors
+/// This function will return an error if the work directory, the keys or a job cannot be read,
+/// if a job fails, or if the output cannot be written.
+pub fn job(args: JobArgs) -> Result<(), Error> {
+    let work_dir = WorkDir::open(&args.work_dir)?;
+
+    match args.action {
+        JobAction::Submit(submit) => job_submit(work_dir, submit),
+        JobAction::Resume(resume) => job_resume(work_dir, resume),
+        JobAction::Status { job_id } => {
+            let job_ids = match job_id {
+                Some(job_id) => vec![job_id],
+                None => work_dir.job_ids()?,
+            };
+
+            for job_id in job_ids {
+                println!("{}: {}", job_id, work_dir.status(&job_id)?);
+            }
+            Ok(())
+        }
+        JobAction::Cancel { job_id } => {
+            match work_dir.cancel(&job_id)? {
+                true => eprintln!("Job {} cancelled", job_id),
+                false => eprintln!("Job {} is already completed", job_id),
+            }
+            Ok(())
+        }
+        JobAction::Output { job_id, output } => {
+            let (params, bytes) = work_dir.output(&job_id)?;
+            save(&output, params, &bytes)?;
+
+            eprintln!(
+                "{} bytes of job {} written to {}",
+                bytes.len(),
+                job_id,
+                output.display()
+            );
+            Ok(())
+        }
+        JobAction::Remove { job_id } => work_dir.remove(&job_id),
+    }
+}
+
+/// Starts a job and waits for it, printing its progress on stderr.
+fn job_submit(work_dir: WorkDir, args: JobSubmitArgs) -> Result<(), Error> {
+    let (_, registry) = load_registry(&args.server_key, args.threads, &[args.key.clone()])?;
+    let key_id = args.key.split('=').next().unwrap_or_default().to_string();
+    let iv = *read_block(&args.iv, "IV")?;
+
+    let kind = match &args.input {
+        Some(input) => {
+            let mut ciphertext = vec![];
+            open_input(input)?
+                .read_to_end(&mut ciphertext)
+                .map_err(|e| Error::Io(format!("cannot read {}", input.display()), e))?;
+            JobKind::Transcipher { ciphertext }
+        }
+        None => JobKind::Encrypt {
+            blocks: args.number_of_outputs,
+        },
+    };
+
+    let queue = JobQueue::new(work_dir, registry, 1)?;
+    let job_id = queue.submit(JobSpec { key_id, iv, kind })?;
+    eprintln!(
+        "Job {} started in {}; if interrupted, continue it with `job resume`",
+        job_id,
+        queue.work_dir().path().display()
+    );
+
+    wait_for_job(&queue, &job_id)?;
+
+    if let Some(output) = &args.output {
+        let (params, bytes) = queue.work_dir().output(&job_id)?;
+        save(output, params, &bytes)?;
+        eprintln!("{} bytes written to {}", bytes.len(), output.display());
+    }
+
+    Ok(())
+}
+
+/// Resumes the pending jobs of the work directory and waits for them, printing their progress on
+/// stderr.
+fn job_resume(work_dir: WorkDir, args: JobResumeArgs) -> Result<(), Error> {
+    let (_, registry) = load_registry(&args.server_key, args.threads, &args.keys)?;
+
+    let queue = JobQueue::new(work_dir, registry, args.workers)?;
+    let job_ids = queue.resume()?;
+    eprintln!("Resuming {} jobs", job_ids.len());
+
+    for job_id in job_ids {
+        wait_for_job(&queue, &job_id)?;
+    }
+
+    Ok(())
+}
+
+/// Waits for a job of `queue`, printing its progress on stderr.
+///
+/// # Errors
+/// This function will return an error if the job fails.
+fn wait_for_job(queue: &JobQueue, job_id: &str) -> Result<JobStatus, Error> {
+    let computation_time = Instant::now();
+    let status = queue.wait(job_id, |status| eprint!("\rJob {}: {}", job_id, status))?;
+    eprintln!();
+
+    match &status.state {
+        JobState::Failed(message) => Err(Error::Fhe(format!("job {} failed: {}", job_id, message))),
+        JobState::Cancelled => Err(Error::InvalidInput(format!("job {} was cancelled", job_id))),
+        _ => {
+            eprintln!(
+                "Job {} took {} seconds",
+                job_id,
+                computation_time.elapsed().as_secs()
+            );
+            Ok(status)
+        }
+    }
+}
+
+/// Encrypts a search pattern byte by byte with the client key.
+pub fn encrypt_pattern(args: EncryptPatternArgs) -> Result<(), Error> {
+    let (params, cks): (ParameterSet, ClientKey) = load(&args.client_key)?;
+
+    let pattern = read_bytes(&args.pattern, "pattern")?;
+    if pattern.is_empty() {
+        return Err(Error::InvalidInput(
+            "the search pattern must not be empty".to_string(),
+        ));
+    }
+
+    let pattern_fhe: Vec<FheUint8> = pattern
+        .iter()
+        .map(|x| FheUint8::encrypt(*x, &cks))
+        .collect();
+    save(&args.output, params, &pattern_fhe)?;
+
+    println!(
+        "Encrypted pattern of {} bytes written to {}",
+        pattern_fhe.len(),
+        args.output.display()
+    );
+
+    Ok(())
+}
+
+/// Searches AES-CTR encrypted or transciphered data for an encrypted pattern; the result stays
+/// encrypted under FHE.
+///
+/// # Errors
+/// This function will return an error if an input cannot be loaded or was produced with another
+/// parameter set than the server key, if the pattern is empty, or if a homomorphic operation
+/// fails.
+pub fn search_file(args: SearchArgs) -> Result<(), Error> {
+    let context = load_context(&args.server_key, args.threads)?;
+    let params = context.params();
+
+    context.install(|| {
+        let (pattern_params, pattern): (ParameterSet, Vec<FheUint8>) = load(&args.pattern)?;
+        params.check("Pattern", pattern_params)?;
+
+        let computation_time = Instant::now();
+
+        let (len, result) = match (&args.input, &args.transciphered) {
+            (Some(input), _) => {
+                let schedule = load_key_schedule(&args.expanded_key, params)?;
+                let iv = *read_block(args.iv.as_deref().unwrap_or_default(), "IV")?;
+
+                let mut ciphertext = vec![];
+                open_input(input)?
+                    .read_to_end(&mut ciphertext)
+                    .map_err(|e| Error::Io(format!("cannot read {}", input.display()), e))?;
+
+                let result = search_aes_ctr(&schedule, iv, &ciphertext, &pattern, args.mode)?;
+                (ciphertext.len(), result)
+            }
+            (None, Some(transciphered)) => {
+                // `transcipher` writes a stream of chunks, searched as a whole
+                let reader = ChunkReader::open(transciphered)?;
+                params.check("Transciphered data", reader.params())?;
+
+                let mut data: Vec<FheUint8> = vec![];
+                for chunk in reader {
+                    data.extend(chunk?);
+                }
+
+                (data.len(), search(&data, &pattern, args.mode)?)
+            }
+            (None, None) => {
+                return Err(Error::InvalidInput(
+                    "either --input or --transciphered is required".to_string(),
+                ))
+            }
+        };
+
+        save(&args.output, params, &result.to_bytes())?;
+
+        eprintln!(
+            "Search ({}) of a {}-byte pattern in {} bytes took {} seconds, written to {}",
+            args.mode,
+            pattern.len(),
+            len,
+            computation_time.elapsed().as_secs(),
+            args.output.display()
+        );
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+/// This module contains tests for the subcommands.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- commands::tests::search_transciphered_chunks --exact --show-output
+/// ```
+mod tests {
+    use super::*;
+    use crate::utils::{hex_to_u8_array, TestDir};
+
+    #[test]
+    fn search_transciphered_chunks() {
+        let dir = TestDir::new("commands");
+        let (server_key, expanded_key) = (dir.join("server_key.bin"), dir.join("expanded_key.bin"));
+        let (ciphertext, transciphered) =
+            (dir.join("ciphertext.bin"), dir.join("transciphered.bin"));
+        let (pattern, result) = (dir.join("pattern.bin"), dir.join("search_result.bin"));
+
+        let key = hex_to_u8_array("000102030405060708090a0b0c0d0e0f").unwrap();
+        let iv = "00112233445566778899aabbccddeeff";
+
+        let (cks, sks) = generate_keys(ParameterSet::Default.config());
+        save(&server_key, ParameterSet::Default, &sks).unwrap();
+
+        let expanded_key_fhe: Vec<FheUint8> = key_expansion_clear(&key)
+            .iter()
+            .map(|x| FheUint8::encrypt(*x, &cks))
+            .collect();
+        save(&expanded_key, ParameterSet::Default, &expanded_key_fhe).unwrap();
+
+        // AES-CTR in the clear
+        let mut data = b"id=4711;id=4711".to_vec();
+        let mut block = hex_to_u8_array(iv).unwrap();
+        Aes128::new((&key).into()).encrypt_block((&mut block).into());
+        data.iter_mut().zip(block).for_each(|(c, k)| *c ^= k);
+        std::fs::write(&ciphertext, &data).unwrap();
+
+        // Chunks of 4 bytes, so that both occurrences of the pattern span two chunks
+        transcipher_file(TranscipherArgs {
+            server_key: server_key.clone(),
+            threads: 0,
+            cipher: Cipher::Aes,
+            expanded_key: Some(expanded_key.clone()),
+            encrypted_key: None,
+            iv: iv.to_string(),
+            input: ciphertext,
+            chunk_size: 4,
+            output: transciphered.clone(),
+        })
+        .unwrap();
+        assert_eq!(ChunkReader::open(&transciphered).unwrap().count(), 4);
+
+        let pattern_fhe: Vec<FheUint8> = b"4711"
+            .iter()
+            .map(|x| FheUint8::encrypt(*x, &cks))
+            .collect();
+        save(&pattern, ParameterSet::Default, &pattern_fhe).unwrap();
+
+        search_file(SearchArgs {
+            server_key,
+            threads: 0,
+            expanded_key,
+            iv: None,
+            input: None,
+            transciphered: Some(transciphered),
+            pattern,
+            mode: SearchMode::Count,
+            output: result.clone(),
+        })
+        .unwrap();
+
+        let (_, count): (ParameterSet, Vec<FheUint8>) = load(&result).unwrap();
+        let count: Vec<u8> = count.iter().map(|x| x.decrypt(&cks)).collect();
+        assert_eq!(count, [0, 0, 0, 2]);
+    }
+}
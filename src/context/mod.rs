@@ -0,0 +1,204 @@
+/// This module defines the execution context of the homomorphic computations.
+/// It includes the following items:
+/// - `FheAesContext`: A TFHE server key and a dedicated rayon thread pool whose workers all hold it.
+/// - `FheAesContext::install`: Runs any computation inside the context.
+/// - `FheAesContext::key_expansion` / `key_schedule` / `encrypt_block` / `decrypt_block` /
+///   `transcipher`: The public AES APIs, run inside the context.
+///
+/// The `tfhe` server key is thread-local. Broadcasting it to the global rayon pool only covers the
+/// threads that exist at that time, not the threads of other pools, and cannot hold two server
+/// keys at once. A context installs its server key in every worker of its own pool when the worker
+/// starts, so every thread a computation in `install` can run on holds the right key, whatever
+/// other pools or contexts the process uses.
+use crate::backend::AesByte;
+use crate::error::Error;
+use crate::key_expansion::key_expansion_fhe;
+use crate::params::ParameterSet;
+use crate::round_keys::{KeySchedule, ScheduleMode};
+use crate::transciphering::{transcipher, KeystreamGenerator};
+use crate::{aes_decrypt_block_with_schedule, aes_encrypt_block_with_schedule};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use tfhe::{set_server_key, ClientKey, FheUint8, ServerKey};
+
+/// A TFHE server key with a dedicated thread pool whose workers all hold it.
+pub struct FheAesContext {
+    params: ParameterSet,
+    pool: ThreadPool,
+}
+
+impl FheAesContext {
+    /// Builds a thread pool of `threads` workers, each installing `server_key` when it starts.
+    ///
+    /// # Arguments
+    /// * `params` - The parameter set the server key was generated with.
+    /// * `server_key` - The TFHE server key.
+    /// * `threads` - The number of worker threads, or 0 for the rayon default (the number of CPUs,
+    ///   or `RAYON_NUM_THREADS`).
+    ///
+    /// # Errors
+    /// This function will return an error if the thread pool cannot be built.
+    pub fn new(params: ParameterSet, server_key: ServerKey, threads: usize) -> Result<Self, Error> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|index| format!("fhe-aes128-{}", index))
+            .start_handler(move |_| set_server_key(server_key.clone()))
+            .build()
+            .map_err(|e| Error::Fhe(format!("cannot build the thread pool: {}", e)))?;
+
+        Ok(Self { params, pool })
+    }
+
+    /// Generates a new client key and a context for its server key.
+    ///
+    /// # Errors
+    /// This function will return an error if the thread pool cannot be built.
+    pub fn generate(params: ParameterSet, threads: usize) -> Result<(ClientKey, Self), Error> {
+        let (cks, sks) = tfhe::generate_keys(params.config());
+
+        Ok((cks, Self::new(params, sks, threads)?))
+    }
+
+    /// Returns the parameter set of the server key.
+    pub fn params(&self) -> ParameterSet {
+        self.params
+    }
+
+    /// Returns the number of worker threads.
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Runs `op` on a worker of the context, where the server key is installed; the parallel
+    /// iterators and `rayon::join`s of `op` run on the workers of the same pool.
+    pub fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        self.pool.install(op)
+    }
+
+    /// Expands an encrypted AES key, see `key_expansion_fhe`.
+    ///
+    /// # Errors
+    /// This function will return an error if a homomorphic S-Box lookup fails.
+    pub fn key_expansion(&self, key: &[FheUint8; 16]) -> Result<Box<[FheUint8; 176]>, Error> {
+        self.install(|| {
+            let mut expanded_key = Box::new(std::array::from_fn(|_| FheUint8::constant(0u8)));
+            key_expansion_fhe(key, &mut expanded_key)?;
+
+            Ok(expanded_key)
+        })
+    }
+
+    /// Builds the cached or on-the-fly key schedule of an encrypted AES key, see `KeySchedule::new`.
+    ///
+    /// # Errors
+    /// This function will return an error if a homomorphic S-Box lookup fails.
+    pub fn key_schedule(
+        &self,
+        key: &[FheUint8; 16],
+        mode: ScheduleMode,
+    ) -> Result<KeySchedule<FheUint8>, Error> {
+        self.install(|| KeySchedule::new(key, mode))
+    }
+
+    /// Encrypts a block, see `aes_encrypt_block_with_schedule`.
+    ///
+    /// # Errors
+    /// This function will return an error if a homomorphic S-Box lookup fails.
+    pub fn encrypt_block(
+        &self,
+        input: &[FheUint8],
+        schedule: &KeySchedule<FheUint8>,
+    ) -> Result<[FheUint8; 16], Error> {
+        self.install(|| {
+            let mut output = std::array::from_fn(|_| FheUint8::constant(0u8));
+            aes_encrypt_block_with_schedule(input, &mut output, schedule)?;
+
+            Ok(output)
+        })
+    }
+
+    /// Decrypts a block, see `aes_decrypt_block_with_schedule`.
+    ///
+    /// # Errors
+    /// This function will return an error if a homomorphic S-Box lookup fails.
+    pub fn decrypt_block(
+        &self,
+        input: &[FheUint8],
+        schedule: &KeySchedule<FheUint8>,
+    ) -> Result<[FheUint8; 16], Error> {
+        self.install(|| {
+            let mut output = std::array::from_fn(|_| FheUint8::constant(0u8));
+            aes_decrypt_block_with_schedule(input, &mut output, schedule)?;
+
+            Ok(output)
+        })
+    }
+
+    /// Transciphers a symmetric ciphertext, see `transcipher`.
+    ///
+    /// # Errors
+    /// This function will return an error if a homomorphic operation fails.
+    pub fn transcipher(
+        &self,
+        generator: &mut (impl KeystreamGenerator + Send),
+        ciphertext: &[u8],
+    ) -> Result<Vec<FheUint8>, Error> {
+        self.install(|| transcipher(generator, ciphertext))
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the execution context.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- context::tests::contexts --exact --show-output
+/// ```
+mod tests {
+    use aes::cipher::{BlockEncrypt, KeyInit};
+    use aes::Aes128;
+    use rand::Rng;
+    use rayon::prelude::*;
+    use tfhe::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn contexts() {
+        let mut rng = rand::thread_rng();
+        let keys: [[u8; 16]; 2] = rng.gen();
+        let input: [u8; 16] = rng.gen();
+
+        // Two contexts with different server keys, used at the same time from threads of neither
+        let (cks_a, context_a) = FheAesContext::generate(ParameterSet::Default, 2).unwrap();
+        let (cks_b, context_b) = FheAesContext::generate(ParameterSet::Default, 3).unwrap();
+        assert_eq!(context_a.threads(), 2);
+        assert_eq!(context_b.threads(), 3);
+
+        let results: Vec<[u8; 16]> = [(&cks_a, &context_a), (&cks_b, &context_b)]
+            .par_iter()
+            .zip(keys.par_iter())
+            .map(|((cks, context), key)| {
+                let key_fhe: [FheUint8; 16] =
+                    std::array::from_fn(|i| FheUint8::encrypt(key[i], *cks));
+                let input_fhe: Vec<FheUint8> =
+                    input.iter().map(|x| FheUint8::encrypt(*x, *cks)).collect();
+
+                let schedule = context
+                    .key_schedule(&key_fhe, ScheduleMode::Cached)
+                    .unwrap();
+                let output = context.encrypt_block(&input_fhe, &schedule).unwrap();
+
+                std::array::from_fn(|i| output[i].decrypt(cks))
+            })
+            .collect();
+
+        for (key, result) in keys.iter().zip(results) {
+            let mut expected = input;
+            Aes128::new(key.into()).encrypt_block((&mut expected).into());
+            assert_eq!(result, expected);
+        }
+    }
+}
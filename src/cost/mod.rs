@@ -0,0 +1,508 @@
+/// This module implements a cost model of the FHE AES circuit, to tell which stage dominates the
+/// runtime without profiling a homomorphic run.
+/// The circuit runs on the counting backend (see the `backend` module), and the counted byte
+/// operations are turned into programmable bootstraps (PBS), keyswitches, scalar operations and
+/// clones for a TFHE parameter set, then into an estimated runtime for a number of cores.
+/// It includes the following items:
+/// - `Cost`: The homomorphic operations of a computation.
+/// - `CostModel`: Converts byte operations into `Cost`s and estimated runtimes.
+/// - `CallCost` / `RoundCost`: The cost of one call of a round function, and of one AES round.
+/// - `CostBreakdown`: The per-call, per-round, per-block and total cost of an AES run.
+/// - `analyze`: Computes the `CostBreakdown` of a run.
+///
+/// The PBS counts are approximations of the `tfhe` integer algorithms on clean ciphertexts, and
+/// the runtime estimate assumes that each call spreads its bootstraps over the available cores
+/// with no other overhead. They are meant to compare stages and parameter sets, not to replace
+/// measurements.
+use crate::backend::{AesByte, CountedByte, OpCounter, OpCounts};
+use crate::decryption::inv_mix_columns;
+use crate::encryption::{add_blocks, gal_mul, mix_columns, shift_rows, sub_bytes};
+use crate::error::Error;
+use crate::key_expansion::key_expansion_fhe;
+use crate::params::ParameterSet;
+use crate::report::ReportFormat;
+use serde::Serialize;
+use std::io::Write;
+use std::ops::{Add, AddAssign, Mul};
+use std::sync::Arc;
+
+/// The homomorphic operations of a computation.
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Cost {
+    /// Programmable bootstraps.
+    pub pbs: u64,
+    /// Keyswitches, one before each bootstrap with the KS-PBS parameter sets.
+    pub keyswitches: u64,
+    /// Operations with a clear operand (XORs, ANDs and shifts by constants).
+    pub scalar_ops: u64,
+    /// Copies of ciphertexts.
+    pub clones: u64,
+}
+
+impl Add for Cost {
+    type Output = Cost;
+
+    fn add(self, rhs: Cost) -> Cost {
+        Cost {
+            pbs: self.pbs + rhs.pbs,
+            keyswitches: self.keyswitches + rhs.keyswitches,
+            scalar_ops: self.scalar_ops + rhs.scalar_ops,
+            clones: self.clones + rhs.clones,
+        }
+    }
+}
+
+impl AddAssign for Cost {
+    fn add_assign(&mut self, rhs: Cost) {
+        *self = *self + rhs;
+    }
+}
+
+impl Mul<u64> for Cost {
+    type Output = Cost;
+
+    fn mul(self, rhs: u64) -> Cost {
+        Cost {
+            pbs: self.pbs * rhs,
+            keyswitches: self.keyswitches * rhs,
+            scalar_ops: self.scalar_ops * rhs,
+            clones: self.clones * rhs,
+        }
+    }
+}
+
+/// Converts counted byte operations into homomorphic operations and estimated runtimes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostModel {
+    /// The parameter set of the keys.
+    params: ParameterSet,
+    /// The number of cores the bootstraps are spread over.
+    cores: usize,
+    /// The latency of one bootstrap on one core, in milliseconds.
+    pbs_latency_ms: f64,
+}
+
+impl CostModel {
+    /// Creates the cost model of `params` on `cores` cores, with the default bootstrap latency of
+    /// the parameter set.
+    pub fn new(params: ParameterSet, cores: usize) -> Self {
+        // Rough single-core latencies on a recent x86-64 CPU; calibrate with `with_pbs_latency`
+        let pbs_latency_ms = match params {
+            ParameterSet::Default => 8.0,
+            ParameterSet::Message1Carry1 => 3.0,
+            ParameterSet::Message3Carry3 => 60.0,
+            ParameterSet::MultiBit => 12.0,
+        };
+
+        Self {
+            params,
+            cores: cores.max(1),
+            pbs_latency_ms,
+        }
+    }
+
+    /// Replaces the latency of one bootstrap on one core, e.g. with a measured value.
+    pub fn with_pbs_latency(self, pbs_latency_ms: f64) -> Self {
+        Self {
+            pbs_latency_ms,
+            ..self
+        }
+    }
+
+    /// Returns the number of message bits of a shortint block.
+    fn block_bits(&self) -> u64 {
+        match self.params {
+            ParameterSet::Message1Carry1 => 1,
+            ParameterSet::Message3Carry3 => 3,
+            _ => 2,
+        }
+    }
+
+    /// Returns the number of shortint blocks of an `FheUint8`.
+    pub fn blocks(&self) -> u64 {
+        8u64.div_ceil(self.block_bits())
+    }
+
+    /// Converts counted byte operations into homomorphic operations.
+    ///
+    /// # Behavior
+    /// With `n` blocks of `m` message bits per byte:
+    /// - A XOR of two bytes bootstraps every block: `n` PBS.
+    /// - A XOR with a constant also cleans every block: `n` PBS and one scalar operation.
+    /// - `xtime` is a scalar AND (`n`), a comparison with 0 (`n - 1`), a shift (`n`), a scalar XOR
+    ///   (`n`) and a selection (`2n`): `6n - 1` PBS and three scalar operations.
+    /// - A lookup among 256 values compares every block with its `2^m` values (`n * 2^m`),
+    ///   combines them into the 256 input values (256) and aggregates the selected outputs into
+    ///   each output block (`n * 256 / 2^m`).
+    /// - Every bootstrap starts with a keyswitch.
+    pub fn cost(&self, counts: &OpCounts) -> Cost {
+        let n = self.blocks();
+        let values = 1u64 << self.block_bits();
+
+        let xor = n;
+        let scalar_xor = n;
+        let xtime = 6 * n - 1;
+        let lookup = n * values + 256 + n * 256u64.div_ceil(values);
+
+        let pbs = counts.xor * xor
+            + counts.scalar_xor * scalar_xor
+            + counts.xtime * xtime
+            + counts.lookup * lookup;
+
+        Cost {
+            pbs,
+            keyswitches: pbs,
+            scalar_ops: counts.scalar_xor + 3 * counts.xtime,
+            clones: counts.clone,
+        }
+    }
+
+    /// Estimates the runtime of a call, in milliseconds.
+    ///
+    /// # Arguments
+    /// * `cost` - The operations of the call.
+    /// * `bytes` - The number of bytes the call processes in parallel.
+    ///
+    /// # Behavior
+    /// - The bootstraps are spread over the cores, up to one core per block of the processed bytes.
+    pub fn estimated_ms(&self, cost: &Cost, bytes: u64) -> f64 {
+        let lanes = (bytes * self.blocks()).clamp(1, self.cores as u64);
+
+        cost.pbs.div_ceil(lanes) as f64 * self.pbs_latency_ms
+    }
+
+    /// Returns the cost and the estimated runtime of a call.
+    fn call(&self, name: String, counts: &OpCounts, bytes: u64) -> CallCost {
+        let cost = self.cost(counts);
+
+        CallCost {
+            name,
+            estimated_ms: self.estimated_ms(&cost, bytes),
+            cost,
+        }
+    }
+}
+
+/// The cost of one call of a round function.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CallCost {
+    /// The called function, e.g. `sub_bytes` or `gal_mul(x, 0x03)`.
+    pub name: String,
+    /// The operations of the call.
+    pub cost: Cost,
+    /// The estimated runtime of the call, in milliseconds.
+    pub estimated_ms: f64,
+}
+
+/// The cost of one AES encryption round of a block.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RoundCost {
+    /// The round, 0 for the initial round key addition.
+    pub round: usize,
+    /// The operations of the round.
+    pub cost: Cost,
+    /// The estimated runtime of the round, in milliseconds.
+    pub estimated_ms: f64,
+}
+
+/// The cost of an AES run: one key expansion followed by the encryption of some blocks.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CostBreakdown {
+    /// The TFHE parameter set of the keys.
+    pub params: String,
+    /// The number of cores of the runtime estimates.
+    pub cores: usize,
+    /// The latency of one bootstrap on one core, in milliseconds.
+    pub pbs_latency_ms: f64,
+    /// The number of encrypted blocks.
+    pub number_of_outputs: u64,
+    /// The cost of one call of each round function, and of the key expansion.
+    pub calls: Vec<CallCost>,
+    /// The cost of each round of a block encryption.
+    pub rounds: Vec<RoundCost>,
+    /// The cost of the key expansion.
+    pub key_expansion: Cost,
+    /// The estimated runtime of the key expansion, in milliseconds.
+    pub key_expansion_ms: f64,
+    /// The cost of a block encryption.
+    pub block: Cost,
+    /// The estimated runtime of a block encryption, in milliseconds.
+    pub block_ms: f64,
+    /// The cost of the key expansion and of every block.
+    pub total: Cost,
+    /// The estimated runtime of the whole run, in milliseconds.
+    pub total_ms: f64,
+}
+
+/// Computes the cost of a key expansion followed by the encryption of `number_of_outputs` blocks.
+///
+/// # Arguments
+/// * `model` - The cost model of the parameter set and core count.
+/// * `number_of_outputs` - The number of encrypted blocks.
+///
+/// # Returns
+/// * `CostBreakdown` - The per-call, per-round, per-block and total costs.
+///
+/// # Behavior
+/// - The circuit runs on the counting backend, whose operation counts do not depend on the key or
+///   the data, so no keys are needed.
+/// - Blocks are encrypted one after the other, like the `run` and `encrypt` subcommands do.
+///
+/// # Errors
+/// This function will return an error if the counting circuit fails, which does not happen with
+/// the clear lookup tables.
+pub fn analyze(model: &CostModel, number_of_outputs: u64) -> Result<CostBreakdown, Error> {
+    let counter = Arc::new(OpCounter::default());
+    let state = || -> Vec<CountedByte> {
+        (0..16)
+            .map(|i| CountedByte::new(i as u8, &counter))
+            .collect()
+    };
+
+    // Key expansion
+    let key: [CountedByte; 16] = std::array::from_fn(|i| CountedByte::new(i as u8, &counter));
+    let mut expanded_key: [CountedByte; 176] = std::array::from_fn(|_| CountedByte::constant(0));
+    counter.take();
+    key_expansion_fhe(&key, &mut expanded_key)?;
+    let key_expansion = model.call("key_expansion_fhe".to_string(), &counter.take(), 4);
+
+    // One call of each round function
+    let mut calls = vec![];
+    let mut bytes = state();
+    counter.take();
+
+    add_blocks(&mut bytes, &expanded_key[16..32]);
+    calls.push(model.call("add_blocks".to_string(), &counter.take(), 16));
+    sub_bytes(&mut bytes)?;
+    calls.push(model.call("sub_bytes".to_string(), &counter.take(), 16));
+    shift_rows(&mut bytes);
+    calls.push(model.call("shift_rows".to_string(), &counter.take(), 16));
+    mix_columns(&mut bytes);
+    calls.push(model.call("mix_columns".to_string(), &counter.take(), 16));
+    inv_mix_columns(&mut bytes);
+    calls.push(model.call("inv_mix_columns".to_string(), &counter.take(), 16));
+
+    for constant in [0x02, 0x03, 0x09, 0x0b, 0x0d, 0x0e] {
+        let byte = bytes[0].clone();
+        counter.take();
+        gal_mul(byte, constant);
+        calls.push(model.call(format!("gal_mul(x, {:#04x})", constant), &counter.take(), 1));
+    }
+
+    calls.push(key_expansion.clone());
+
+    // Every round of a block encryption, as in `aes_encrypt_block`, each stage estimated on its own
+    let stage = |counts: OpCounts| {
+        let cost = model.cost(&counts);
+        (cost, model.estimated_ms(&cost, 16))
+    };
+
+    let mut rounds = vec![];
+    let mut block = state();
+    counter.take();
+
+    add_blocks(&mut block, &expanded_key[0..16]);
+    let (cost, estimated_ms) = stage(counter.take());
+    rounds.push(RoundCost {
+        round: 0,
+        cost,
+        estimated_ms,
+    });
+
+    for round in 1..=10 {
+        let mut stages = vec![];
+
+        sub_bytes(&mut block)?;
+        stages.push(stage(counter.take()));
+        shift_rows(&mut block);
+        stages.push(stage(counter.take()));
+        // The final round has no MixColumns
+        if round < 10 {
+            mix_columns(&mut block);
+            stages.push(stage(counter.take()));
+        }
+        add_blocks(&mut block, &expanded_key[round * 16..(round + 1) * 16]);
+        stages.push(stage(counter.take()));
+
+        rounds.push(RoundCost {
+            round,
+            cost: stages.iter().fold(Cost::default(), |sum, x| sum + x.0),
+            estimated_ms: stages.iter().map(|x| x.1).sum(),
+        });
+    }
+
+    let block = rounds.iter().fold(Cost::default(), |sum, x| sum + x.cost);
+    let block_ms: f64 = rounds.iter().map(|x| x.estimated_ms).sum();
+
+    Ok(CostBreakdown {
+        params: model.params.to_string(),
+        cores: model.cores,
+        pbs_latency_ms: model.pbs_latency_ms,
+        number_of_outputs,
+        calls,
+        rounds,
+        key_expansion: key_expansion.cost,
+        key_expansion_ms: key_expansion.estimated_ms,
+        block,
+        block_ms,
+        total: key_expansion.cost + block * number_of_outputs,
+        total_ms: key_expansion.estimated_ms + block_ms * number_of_outputs as f64,
+    })
+}
+
+impl CostBreakdown {
+    /// Writes the breakdown to `writer`, as a table when `format` is `None`.
+    ///
+    /// # Behavior
+    /// - The CSV format has one row per call, per round, for the block and for the total.
+    ///
+    /// # Errors
+    /// This function will return an error if writing fails.
+    pub fn write(
+        &self,
+        format: Option<ReportFormat>,
+        writer: &mut impl Write,
+    ) -> std::io::Result<()> {
+        let rows = self
+            .calls
+            .iter()
+            .map(|x| ("call", x.name.clone(), &x.cost, x.estimated_ms))
+            .chain(
+                self.rounds
+                    .iter()
+                    .map(|x| ("round", x.round.to_string(), &x.cost, x.estimated_ms)),
+            )
+            .chain([
+                (
+                    "block",
+                    "aes_encrypt_block".to_string(),
+                    &self.block,
+                    self.block_ms,
+                ),
+                (
+                    "total",
+                    format!("{} blocks", self.number_of_outputs),
+                    &self.total,
+                    self.total_ms,
+                ),
+            ]);
+
+        match format {
+            Some(ReportFormat::Json) => {
+                serde_json::to_writer_pretty(&mut *writer, self)?;
+                writeln!(writer)
+            }
+            Some(ReportFormat::Csv) => {
+                writeln!(
+                    writer,
+                    "params,cores,pbs_latency_ms,kind,name,pbs,keyswitches,scalar_ops,clones,estimated_ms"
+                )?;
+
+                for (kind, name, cost, estimated_ms) in rows {
+                    writeln!(
+                        writer,
+                        "{},{},{},{},{},{},{},{},{},{}",
+                        self.params,
+                        self.cores,
+                        self.pbs_latency_ms,
+                        kind,
+                        name,
+                        cost.pbs,
+                        cost.keyswitches,
+                        cost.scalar_ops,
+                        cost.clones,
+                        estimated_ms
+                    )?;
+                }
+
+                Ok(())
+            }
+            None => {
+                writeln!(
+                    writer,
+                    "Cost model: {} parameters, {} cores, {} ms per PBS",
+                    self.params, self.cores, self.pbs_latency_ms
+                )?;
+                writeln!(
+                    writer,
+                    "{:<6} {:<20} {:>10} {:>10} {:>8} {:>12}",
+                    "kind", "name", "pbs", "scalar_ops", "clones", "estimated_ms"
+                )?;
+
+                for (kind, name, cost, estimated_ms) in rows {
+                    writeln!(
+                        writer,
+                        "{:<6} {:<20} {:>10} {:>10} {:>8} {:>12.1}",
+                        kind, name, cost.pbs, cost.scalar_ops, cost.clones, estimated_ms
+                    )?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the cost model.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- cost::tests::cost_breakdown --exact --show-output
+/// ```
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cost_breakdown() {
+        let model = CostModel::new(ParameterSet::Default, 16);
+        let breakdown = analyze(&model, 3).unwrap();
+
+        // One lookup costs 16 + 256 + 256 PBS with 4 blocks of 2 bits
+        let lookup = OpCounts {
+            lookup: 1,
+            ..OpCounts::default()
+        };
+        assert_eq!(model.blocks(), 4);
+        assert_eq!(model.cost(&lookup).pbs, 528);
+
+        // The initial round key addition, 9 full rounds and a final round without MixColumns
+        assert_eq!(breakdown.rounds.len(), 11);
+        assert_eq!(breakdown.rounds[0].cost.pbs, 16 * 4);
+        assert!(breakdown.rounds[10].cost.pbs < breakdown.rounds[9].cost.pbs);
+        assert_eq!(
+            breakdown.block.pbs,
+            breakdown.rounds.iter().map(|x| x.cost.pbs).sum::<u64>()
+        );
+        assert_eq!(breakdown.block.keyswitches, breakdown.block.pbs);
+        assert_eq!(
+            breakdown.total,
+            breakdown.key_expansion + breakdown.block * 3
+        );
+
+        let shift_rows = breakdown
+            .calls
+            .iter()
+            .find(|x| x.name == "shift_rows")
+            .unwrap();
+        assert_eq!(shift_rows.cost.pbs, 0);
+        assert_eq!(shift_rows.estimated_ms, 0.0);
+
+        // More cores shorten the estimate, down to one core per block of the state
+        let single_core = analyze(&CostModel::new(ParameterSet::Default, 1), 3).unwrap();
+        assert_eq!(single_core.total, breakdown.total);
+        assert!(single_core.total_ms > breakdown.total_ms);
+
+        let mut csv = vec![];
+        breakdown.write(Some(ReportFormat::Csv), &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 1 + breakdown.calls.len() + 11 + 2);
+
+        let mut table = vec![];
+        breakdown.write(None, &mut table).unwrap();
+        println!("{}", String::from_utf8(table).unwrap());
+    }
+}
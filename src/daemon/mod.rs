@@ -0,0 +1,606 @@
+/// This module runs the FHE server as a long-lived process, answering transciphering requests over
+/// a Unix domain socket or a localhost TCP connection.
+/// It includes the following items:
+/// - `Endpoint`: The address the server listens on, `unix:<path>` or `tcp:<host>:<port>`.
+/// - `Request` / `Response`: The messages exchanged, as length-prefixed `bincode` frames.
+/// - `Server`: Binds an endpoint and answers the requests with a `KeyRegistry`.
+/// - `Client`: Connects to a server and sends requests.
+///
+/// Every frame is a 4-byte big-endian length followed by the `bincode` serialization of a message.
+/// A connection carries any number of requests, each answered in order; the server handles every
+/// connection on its own thread, at most `MAX_CONNECTIONS` at once, and the registry runs each
+/// request in the context of its key's client. The server key and the key schedules are loaded
+/// once, when the server starts.
+use crate::error::Error;
+use crate::params::ParameterSet;
+use crate::registry::{KeyRegistry, TranscipherRequest};
+use crate::transciphering::AesCtrKeystream;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+use tfhe::FheUint8;
+
+/// The largest request frame the server reads. A request holds a key ID, an IV and the clear
+/// AES-CTR ciphertext: 1 MiB is already 65536 blocks to transcipher homomorphically.
+pub const MAX_REQUEST_LEN: u32 = 1 << 20;
+
+/// The largest response frame the client reads. A response holds FHE ciphertexts, thousands of
+/// times larger than the bytes they encrypt.
+pub const MAX_RESPONSE_LEN: u32 = 1 << 30;
+
+/// The largest number of connections the server handles at once; further clients wait in the
+/// listener's backlog until a connection closes.
+pub const MAX_CONNECTIONS: usize = 32;
+
+/// The address of a server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// A Unix domain socket at the given path.
+    Unix(PathBuf),
+    /// A TCP socket on a loopback address.
+    Tcp(SocketAddr),
+}
+
+impl FromStr for Endpoint {
+    type Err = Error;
+
+    /// Parses `unix:<path>`, `tcp:<host>:<port>`, or a bare path as a Unix socket.
+    ///
+    /// # Errors
+    /// This function will return an error if the TCP address cannot be resolved or is not a
+    /// loopback address: the requests are not encrypted, so the server only serves local clients.
+    fn from_str(value: &str) -> Result<Self, Error> {
+        let address = match value.strip_prefix("tcp:") {
+            Some(address) => address,
+            None => {
+                let path = value.strip_prefix("unix:").unwrap_or(value);
+                return Ok(Endpoint::Unix(PathBuf::from(path)));
+            }
+        };
+
+        let address = address
+            .to_socket_addrs()
+            .map_err(|e| Error::InvalidInput(format!("cannot resolve {:?}: {}", address, e)))?
+            .next()
+            .ok_or_else(|| Error::InvalidInput(format!("cannot resolve {:?}", address)))?;
+
+        if !address.ip().is_loopback() {
+            return Err(Error::InvalidInput(format!(
+                "{} is not a loopback address; only local clients are served",
+                address
+            )));
+        }
+
+        Ok(Endpoint::Tcp(address))
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+            Endpoint::Tcp(address) => write!(f, "tcp:{}", address),
+        }
+    }
+}
+
+/// A request to the server.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Request {
+    /// Transcipher an AES-CTR ciphertext with a registered key.
+    Transcipher(TranscipherRequest),
+    /// List the registered key IDs.
+    Keys,
+}
+
+/// The answer to a request.
+#[derive(Serialize, Deserialize)]
+pub enum Response {
+    /// The transciphered bytes, encrypted under the FHE key of the key's client.
+    Transciphered {
+        /// The parameter set of the FHE ciphertexts.
+        params: ParameterSet,
+        /// The plaintext bytes, encrypted under FHE.
+        bytes: Vec<FheUint8>,
+    },
+    /// The registered key IDs.
+    Keys(Vec<String>),
+    /// The request failed.
+    Error {
+        /// The exit code of the error, see `Error::exit_code`.
+        code: i32,
+        /// The error message.
+        message: String,
+    },
+}
+
+/// Writes `message` as a length-prefixed frame of at most `max_len` bytes and flushes the writer.
+///
+/// # Errors
+/// This function will return an error if the message cannot be serialized, is longer than
+/// `max_len`, or cannot be written.
+pub fn write_frame<T: Serialize>(
+    writer: &mut impl Write,
+    message: &T,
+    max_len: u32,
+) -> Result<(), Error> {
+    let bytes = bincode::serialize(message)
+        .map_err(|e| Error::Serialization(format!("cannot serialize frame: {}", e)))?;
+    let len = u32::try_from(bytes.len())
+        .ok()
+        .filter(|len| *len <= max_len)
+        .ok_or_else(|| Error::InvalidInput(format!("frame of {} bytes too long", bytes.len())))?;
+
+    writer
+        .write_all(&len.to_be_bytes())
+        .and_then(|_| writer.write_all(&bytes))
+        .and_then(|_| writer.flush())
+        .map_err(|e| Error::Io("cannot write frame".to_string(), e))
+}
+
+/// Reads a length-prefixed frame of at most `max_len` bytes.
+///
+/// # Returns
+/// * `Option<T>` - The message, or `None` if the connection was closed before a new frame.
+///
+/// # Behavior
+/// The body is read as it arrives rather than into a buffer of the announced length, so a peer
+/// announcing a long frame without sending it does not make the reader allocate it.
+///
+/// # Errors
+/// This function will return an error if the frame is truncated, longer than `max_len` or cannot
+/// be deserialized.
+pub fn read_frame<T: DeserializeOwned>(
+    reader: &mut impl Read,
+    max_len: u32,
+) -> Result<Option<T>, Error> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(Error::Io("cannot read frame".to_string(), e)),
+    }
+
+    let len = u32::from_be_bytes(len);
+    if len > max_len {
+        return Err(Error::InvalidInput(format!(
+            "frame of {} bytes too long",
+            len
+        )));
+    }
+
+    let mut bytes = vec![];
+    reader
+        .take(u64::from(len))
+        .read_to_end(&mut bytes)
+        .map_err(|e| Error::Io("cannot read frame".to_string(), e))?;
+    if bytes.len() != len as usize {
+        return Err(Error::Io(
+            "cannot read frame".to_string(),
+            std::io::Error::new(ErrorKind::UnexpectedEof, "truncated frame"),
+        ));
+    }
+
+    bincode::deserialize(&bytes)
+        .map(Some)
+        .map_err(|e| Error::Serialization(format!("cannot deserialize frame: {}", e)))
+}
+
+/// Rebuilds an error received from the server, keeping its kind.
+fn remote_error(code: i32, message: String) -> Error {
+    match code {
+        3 => Error::ParameterMismatch(message),
+        4 => Error::Io("server".to_string(), std::io::Error::other(message)),
+        5 => Error::Serialization(message),
+        6 => Error::Fhe(message),
+        7 => Error::Verification(message),
+        _ => Error::InvalidInput(message),
+    }
+}
+
+/// A connected socket of either kind.
+enum Stream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl Stream {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        match self {
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Unix(stream) => stream.read(buf),
+            Stream::Tcp(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Unix(stream) => stream.write(buf),
+            Stream::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Unix(stream) => stream.flush(),
+            Stream::Tcp(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A listening socket of either kind.
+enum Listener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+/// A server answering transciphering requests with the keys of a registry.
+pub struct Server {
+    listener: Listener,
+    endpoint: Endpoint,
+    registry: Arc<KeyRegistry>,
+}
+
+impl Server {
+    /// Listens on `endpoint`.
+    ///
+    /// # Behavior
+    /// A stale Unix socket file left by a previous server is replaced; any other existing file is
+    /// left untouched. A TCP port 0 is replaced by a free port, see `endpoint`.
+    ///
+    /// # Errors
+    /// This function will return an error if the Unix socket path exists and is not a socket, or if
+    /// the socket cannot be bound.
+    pub fn bind(endpoint: &Endpoint, registry: Arc<KeyRegistry>) -> Result<Self, Error> {
+        let bind_error = |e| Error::Io(format!("cannot listen on {}", endpoint), e);
+
+        let (listener, endpoint) = match endpoint {
+            Endpoint::Unix(path) => {
+                if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                    if !metadata.file_type().is_socket() {
+                        return Err(Error::InvalidInput(format!(
+                            "{} exists and is not a socket",
+                            path.display()
+                        )));
+                    }
+
+                    if UnixStream::connect(path).is_err() {
+                        std::fs::remove_file(path).map_err(bind_error)?;
+                    }
+                }
+
+                let listener = UnixListener::bind(path).map_err(bind_error)?;
+                (Listener::Unix(listener), endpoint.clone())
+            }
+            Endpoint::Tcp(address) => {
+                let listener = TcpListener::bind(address).map_err(bind_error)?;
+                let address = listener.local_addr().map_err(bind_error)?;
+                (Listener::Tcp(listener), Endpoint::Tcp(address))
+            }
+        };
+
+        Ok(Self {
+            listener,
+            endpoint,
+            registry,
+        })
+    }
+
+    /// Returns the endpoint the server listens on.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Accepts connections until the listener fails, each handled on its own thread.
+    ///
+    /// # Behavior
+    /// Once `MAX_CONNECTIONS` connections are open, the next one is only accepted when one of
+    /// them closes.
+    ///
+    /// # Errors
+    /// This function will return an error if a connection cannot be accepted.
+    pub fn run(&self) -> Result<(), Error> {
+        let open = Arc::new((Mutex::new(0usize), Condvar::new()));
+
+        loop {
+            let slot = {
+                let (count, closed) = &*open;
+                let mut count = closed
+                    .wait_while(count.lock().expect("connection lock poisoned"), |x| {
+                        *x >= MAX_CONNECTIONS
+                    })
+                    .expect("connection lock poisoned");
+                *count += 1;
+
+                ConnectionSlot(open.clone())
+            };
+
+            let stream = match &self.listener {
+                Listener::Unix(listener) => listener.accept().map(|(x, _)| Stream::Unix(x)),
+                Listener::Tcp(listener) => listener.accept().map(|(x, _)| Stream::Tcp(x)),
+            }
+            .map_err(|e| Error::Io(format!("cannot accept on {}", self.endpoint), e))?;
+
+            let registry = self.registry.clone();
+            std::thread::spawn(move || {
+                let _slot = slot;
+                if let Err(e) = serve_connection(stream, &registry) {
+                    eprintln!("Connection closed: {}", e);
+                }
+            });
+        }
+    }
+}
+
+/// One of the `MAX_CONNECTIONS` open connections of a server, given back when dropped, even if
+/// the connection thread panics.
+struct ConnectionSlot(Arc<(Mutex<usize>, Condvar)>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let (count, closed) = &*self.0;
+        *count.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+        closed.notify_one();
+    }
+}
+
+/// Answers the requests of a connection until the client closes it.
+///
+/// # Errors
+/// This function will return an error if a frame cannot be read or written. A failed request is
+/// answered with `Response::Error` instead.
+fn serve_connection(stream: Stream, registry: &KeyRegistry) -> Result<(), Error> {
+    let mut writer = BufWriter::new(
+        stream
+            .try_clone()
+            .map_err(|e| Error::Io("cannot clone connection".to_string(), e))?,
+    );
+    let mut reader = BufReader::new(stream);
+
+    while let Some(request) = read_frame::<Request>(&mut reader, MAX_REQUEST_LEN)? {
+        let response = match request {
+            Request::Transcipher(request) => {
+                registry
+                    .get(&request.key_id)
+                    .and_then(|(client, schedule)| {
+                        let mut generator = AesCtrKeystream::new(&schedule, request.iv);
+                        let bytes = client.transcipher(&mut generator, &request.ciphertext)?;
+
+                        Ok(Response::Transciphered {
+                            params: client.params(),
+                            bytes,
+                        })
+                    })
+            }
+            Request::Keys => registry.key_ids().map(Response::Keys),
+        };
+
+        let response = response.unwrap_or_else(|e| Response::Error {
+            code: e.exit_code(),
+            message: e.to_string(),
+        });
+        write_frame(&mut writer, &response, MAX_RESPONSE_LEN)?;
+    }
+
+    Ok(())
+}
+
+/// A connection to a server.
+pub struct Client {
+    reader: BufReader<Stream>,
+    writer: BufWriter<Stream>,
+}
+
+impl Client {
+    /// Connects to the server at `endpoint`.
+    ///
+    /// # Errors
+    /// This function will return an error if the connection fails.
+    pub fn connect(endpoint: &Endpoint) -> Result<Self, Error> {
+        let connect_error = |e| Error::Io(format!("cannot connect to {}", endpoint), e);
+
+        let stream = match endpoint {
+            Endpoint::Unix(path) => UnixStream::connect(path).map(Stream::Unix),
+            Endpoint::Tcp(address) => TcpStream::connect(address).map(Stream::Tcp),
+        }
+        .map_err(connect_error)?;
+
+        Ok(Self {
+            writer: BufWriter::new(stream.try_clone().map_err(connect_error)?),
+            reader: BufReader::new(stream),
+        })
+    }
+
+    /// Sends a request and waits for its response.
+    ///
+    /// # Errors
+    /// This function will return an error if the connection fails, or the error of the request.
+    fn call(&mut self, request: &Request) -> Result<Response, Error> {
+        write_frame(&mut self.writer, request, MAX_REQUEST_LEN)?;
+
+        match read_frame(&mut self.reader, MAX_RESPONSE_LEN)? {
+            Some(Response::Error { code, message }) => Err(remote_error(code, message)),
+            Some(response) => Ok(response),
+            None => Err(Error::Io(
+                "server".to_string(),
+                std::io::Error::new(ErrorKind::UnexpectedEof, "connection closed"),
+            )),
+        }
+    }
+
+    /// Transciphers an AES-CTR ciphertext with a key registered on the server.
+    ///
+    /// # Returns
+    /// * `(ParameterSet, Vec<FheUint8>)` - The parameter set of the FHE ciphertexts, and the
+    ///   plaintext bytes encrypted under FHE.
+    ///
+    /// # Errors
+    /// This function will return an error if the connection fails or the server cannot
+    /// transcipher the data, e.g. for an unknown key.
+    pub fn transcipher(
+        &mut self,
+        request: TranscipherRequest,
+    ) -> Result<(ParameterSet, Vec<FheUint8>), Error> {
+        match self.call(&Request::Transcipher(request))? {
+            Response::Transciphered { params, bytes } => Ok((params, bytes)),
+            _ => Err(Error::Serialization("unexpected response".to_string())),
+        }
+    }
+
+    /// Returns the key IDs registered on the server.
+    ///
+    /// # Errors
+    /// This function will return an error if the connection fails.
+    pub fn keys(&mut self) -> Result<Vec<String>, Error> {
+        match self.call(&Request::Keys)? {
+            Response::Keys(keys) => Ok(keys),
+            _ => Err(Error::Serialization("unexpected response".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the transciphering daemon.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- daemon::tests::local_daemon --exact --show-output
+/// ```
+mod tests {
+    use aes::cipher::{BlockEncrypt, KeyInit};
+    use aes::Aes128;
+    use rand::Rng;
+    use tfhe::generate_keys;
+    use tfhe::prelude::*;
+
+    use super::*;
+    use crate::round_keys::{KeySchedule, ScheduleMode};
+    use crate::utils::{increment_counter, key_expansion_clear, TestDir};
+
+    #[test]
+    fn frame_limits() {
+        let mut frame = vec![];
+        write_frame(&mut frame, &Request::Keys, MAX_REQUEST_LEN).unwrap();
+        assert!(matches!(
+            read_frame::<Request>(&mut frame.as_slice(), MAX_REQUEST_LEN),
+            Ok(Some(Request::Keys))
+        ));
+
+        // A header announcing more than the limit, or more than what follows, is refused
+        let header = (MAX_REQUEST_LEN + 1).to_be_bytes();
+        assert!(matches!(
+            read_frame::<Request>(&mut header.as_slice(), MAX_REQUEST_LEN),
+            Err(Error::InvalidInput(_))
+        ));
+        let header = MAX_REQUEST_LEN.to_be_bytes();
+        assert!(matches!(
+            read_frame::<Request>(&mut header.as_slice(), MAX_REQUEST_LEN),
+            Err(Error::Io(..))
+        ));
+    }
+
+    #[test]
+    fn local_daemon() {
+        let mut rng = rand::thread_rng();
+        let key: [u8; 16] = rng.gen();
+        let iv: [u8; 16] = rng.gen();
+        let plaintext: Vec<u8> = (0..20).map(|_| rng.gen()).collect();
+
+        // AES-CTR in the clear, on two blocks
+        let cipher = Aes128::new((&key).into());
+        let mut keystream = vec![];
+        for counter in [iv, increment_counter(&iv)] {
+            let mut block = counter;
+            cipher.encrypt_block((&mut block).into());
+            keystream.extend(block);
+        }
+        let ciphertext: Vec<u8> = plaintext
+            .iter()
+            .zip(&keystream)
+            .map(|(p, k)| p ^ k)
+            .collect();
+
+        // A registry with one client and one key
+        let (cks, sks) = generate_keys(ParameterSet::Default.config());
+        let registry = Arc::new(KeyRegistry::new(1));
+        registry
+            .add_client("default", ParameterSet::Default, sks, 0)
+            .unwrap();
+
+        let expanded_key = key_expansion_clear(&key);
+        let expanded_key_fhe = Box::new(std::array::from_fn(|i| {
+            FheUint8::encrypt(expanded_key[i], &cks)
+        }));
+        let schedule = KeySchedule::from_expanded_key(expanded_key_fhe, ScheduleMode::Cached);
+        registry.insert_key("key", "default", schedule).unwrap();
+
+        // The same server on a Unix socket and on a localhost TCP port
+        let dir = TestDir::new("daemon");
+        let socket = dir.join("daemon.sock");
+        let endpoints = [
+            Endpoint::from_str(&format!("unix:{}", socket.display())).unwrap(),
+            Endpoint::from_str("tcp:127.0.0.1:0").unwrap(),
+        ];
+        assert!(Endpoint::from_str("tcp:192.0.2.1:7000").is_err());
+
+        // A path that is not a socket is never replaced
+        let file = dir.join("report.json");
+        std::fs::write(&file, b"{}").unwrap();
+        let bound = Server::bind(&Endpoint::Unix(file.clone()), registry.clone());
+        assert!(matches!(bound, Err(Error::InvalidInput(_))));
+        assert_eq!(std::fs::read(&file).unwrap(), b"{}");
+
+        let endpoints: Vec<Endpoint> = endpoints
+            .iter()
+            .map(|endpoint| {
+                let server = Server::bind(endpoint, registry.clone()).unwrap();
+                let endpoint = server.endpoint().clone();
+                std::thread::spawn(move || server.run());
+                endpoint
+            })
+            .collect();
+
+        let mut unix_client = Client::connect(&endpoints[0]).unwrap();
+        let (params, bytes) = unix_client
+            .transcipher(TranscipherRequest {
+                key_id: "key".to_string(),
+                iv,
+                ciphertext,
+            })
+            .unwrap();
+        assert_eq!(params, ParameterSet::Default);
+
+        let decrypted: Vec<u8> = bytes.iter().map(|x| x.decrypt(&cks)).collect();
+        assert_eq!(decrypted, plaintext);
+
+        // The errors of a request are returned to the client, which can go on
+        let mut tcp_client = Client::connect(&endpoints[1]).unwrap();
+        let unknown = tcp_client.transcipher(TranscipherRequest {
+            key_id: "missing".to_string(),
+            iv,
+            ciphertext: vec![0],
+        });
+        assert!(matches!(unknown, Err(Error::InvalidInput(_))));
+        assert_eq!(tcp_client.keys().unwrap(), vec!["key"]);
+    }
+}
@@ -5,13 +5,11 @@
 /// - `inv_mix_columns`: Reverses the column mixing using Galois Field multiplication.
 ///
 /// Each operation is parallelized for efficiency using the Rayon library and utilizes FHE to ensure the privacy of the data during decryption.
+use crate::backend::AesByte;
 use crate::encryption::gal_mul;
+use crate::error::Error;
 use crate::utils::INV_SBOX;
 use rayon::prelude::*;
-use std::thread;
-use std::time::Duration;
-use tfhe::prelude::*;
-use tfhe::{FheUint8, MatchValues};
 
 /// Performs the inverse SubBytes transformation in AES decryption using Fully Homomorphic Encryption (FHE).
 ///
@@ -25,22 +23,21 @@ use tfhe::{FheUint8, MatchValues};
 /// - Each byte in the `state` vector is replaced by its corresponding value in the inverse S-Box (`INV_SBOX`).
 /// - The substitution is performed using a **lookup table** stored in `INV_SBOX`.
 /// - Processing is **parallelized** for efficiency using `par_iter_mut()`.
-pub fn inv_sub_bytes(state: &mut Vec<FheUint8>) {
-    // Create a mapping of byte values (0-255) to their corresponding inverse S-Box values.
-    let match_vector: Vec<(u8, u8)> = (0u8..=255u8)
-        .map(|x| (x, INV_SBOX[x as usize])) // Map input byte to its inverse S-Box value
-        .collect();
-
-    // Initialize the match values structure for lookup
-    let match_values = MatchValues::new(match_vector).unwrap();
+///
+/// # Errors
+/// This function will return an error if the homomorphic lookup fails.
+pub fn inv_sub_bytes<B: AesByte>(state: &mut Vec<B>) -> Result<(), Error> {
+    // Build the lookup table of byte values (0-255) to their corresponding inverse S-Box values.
+    let table = B::table(&INV_SBOX)?;
 
     // Apply inverse S-Box substitution to each byte in the state in parallel
     state
         .par_iter_mut() // Use a parallel iterator for efficiency
-        .enumerate() // Include index for potential debugging/logging
-        .for_each(|(index, i)| {
-            (*i, _) = i.match_value(&match_values).unwrap(); // Perform secure lookup and update value
-        });
+        .try_for_each(|i| {
+            // Perform secure lookup and update value
+            *i = i.lookup(&table)?;
+            Ok(())
+        })
 }
 
 /// Performs the inverse ShiftRows transformation in AES decryption using Fully Homomorphic Encryption (FHE).
@@ -57,8 +54,8 @@ pub fn inv_sub_bytes(state: &mut Vec<FheUint8>) {
 /// - The **third row** shifts **two positions to the right**.
 /// - The **fourth row** shifts **three positions to the right**.
 /// - Processing is **parallelized** for efficiency using [`par_iter_mut()`].
-pub fn inv_shift_rows(state: &mut Vec<FheUint8>) {
-    let temp: Vec<FheUint8> = state.clone(); // Create a copy of the current state for reference
+pub fn inv_shift_rows<B: AesByte>(state: &mut Vec<B>) {
+    let temp: Vec<B> = state.clone(); // Create a copy of the current state for reference
 
     // Apply inverse row shifts in parallel
     state.par_iter_mut().enumerate().for_each(|(i, elem)| {
@@ -116,7 +113,7 @@ pub fn inv_shift_rows(state: &mut Vec<FheUint8>) {
 /// - The function will panic if `state` does not have exactly 16 elements.
 /// - The `_ => unreachable!()` branch ensures that the match statement only
 ///   works for valid indices within the range [0, 15].
-pub fn inv_mix_columns(state: &mut Vec<FheUint8>) {
+pub fn inv_mix_columns<B: AesByte>(state: &mut Vec<B>) {
     let temp = state.clone();
 
     state.par_iter_mut().enumerate().for_each(|(i, elem)| {
@@ -0,0 +1,504 @@
+/// This module implements the NIST SP 800-90A CTR_DRBG (AES-128) using Fully Homomorphic Encryption (FHE).
+/// The internal `Key` and `V` of the generator only ever exist as encrypted bytes (FheUint8), so the
+/// server evaluating the generator never learns the random bytes it produces.
+/// It includes functions for performing the following operations:
+/// - `instantiate` / `instantiate_df`: Seeds a new generator, without or with the derivation function.
+/// - `reseed`: Mixes fresh entropy into the internal state.
+/// - `generate`: Produces encrypted random bytes and updates the internal state.
+///
+/// With the derivation function, inputs of any length are compressed by `Block_Cipher_df`, which
+/// is built on the CBC-MAC from the `prf` module (BCC in the specification). Without it, the
+/// entropy input must be exactly `seedlen` bits and is XORed with the other inputs directly.
+use crate::aes_encrypt_block;
+use crate::error::Error;
+use crate::key_expansion::key_expansion_fhe;
+use crate::prf::cbc_mac;
+use tfhe::prelude::*;
+use tfhe::{FheBool, FheUint8};
+
+/// Length of the AES-128 key in bytes (keylen).
+const KEY_LEN: usize = 16;
+
+/// Length of the AES block in bytes (outlen).
+const BLOCK_LEN: usize = 16;
+
+/// Length of the seed material in bytes (seedlen = keylen + outlen).
+const SEED_LEN: usize = KEY_LEN + BLOCK_LEN;
+
+/// Maximum number of `generate` calls between two reseeds (reseed_interval).
+const RESEED_INTERVAL: u64 = 1 << 48;
+
+/// Maximum number of bytes returned by a single `generate` call (max_number_of_bits_per_request).
+const MAX_BYTES_PER_REQUEST: usize = 1 << 16;
+
+/// An AES-128 CTR_DRBG whose internal state is encrypted under FHE.
+pub struct CtrDrbg {
+    /// The encrypted AES key (Key).
+    key: [FheUint8; KEY_LEN],
+    /// The expanded schedule of `key`, refreshed on every update.
+    expanded_key: [FheUint8; 176],
+    /// The encrypted counter block (V).
+    v: [FheUint8; BLOCK_LEN],
+    /// Number of `generate` calls since the last (re)seeding.
+    reseed_counter: u64,
+    /// Whether the inputs go through the block cipher derivation function.
+    derivation_function: bool,
+}
+
+impl CtrDrbg {
+    /// Instantiates a CTR_DRBG without a derivation function.
+    ///
+    /// # Arguments
+    /// * `entropy_input` - Exactly 32 encrypted bytes of full-entropy input.
+    /// * `personalization_string` - Up to 32 encrypted bytes, zero-padded to the seed length.
+    ///
+    /// # Errors
+    /// This function will return an error if the input lengths do not match the specification.
+    pub fn instantiate(
+        entropy_input: &[FheUint8],
+        personalization_string: &[FheUint8],
+    ) -> Result<Self, Error> {
+        if entropy_input.len() != SEED_LEN {
+            return Err(Error::ParameterMismatch(
+                "entropy input must be 32 bytes long without a derivation function".to_string(),
+            ));
+        }
+
+        let seed_material = xor_padded(entropy_input, personalization_string)?;
+
+        Self::from_seed_material(&seed_material, false)
+    }
+
+    /// Instantiates a CTR_DRBG with the block cipher derivation function.
+    ///
+    /// # Arguments
+    /// * `entropy_input` - At least 16 encrypted bytes of entropy input.
+    /// * `nonce` - The encrypted nonce.
+    /// * `personalization_string` - The encrypted personalization string, possibly empty.
+    ///
+    /// # Errors
+    /// This function will return an error if the entropy input is shorter than the security strength.
+    pub fn instantiate_df(
+        entropy_input: &[FheUint8],
+        nonce: &[FheUint8],
+        personalization_string: &[FheUint8],
+    ) -> Result<Self, Error> {
+        if entropy_input.len() < KEY_LEN {
+            return Err(Error::ParameterMismatch(
+                "entropy input must be at least 16 bytes long".to_string(),
+            ));
+        }
+
+        let seed_material = block_cipher_df(
+            &[entropy_input, nonce, personalization_string].concat(),
+            SEED_LEN,
+        )?;
+
+        Self::from_seed_material(&seed_material, true)
+    }
+
+    /// Reseeds the generator with fresh entropy.
+    ///
+    /// # Arguments
+    /// * `entropy_input` - The encrypted entropy input, with the same length rules as instantiation.
+    /// * `additional_input` - The encrypted additional input, possibly empty.
+    ///
+    /// # Errors
+    /// This function will return an error if the input lengths do not match the specification.
+    pub fn reseed(
+        &mut self,
+        entropy_input: &[FheUint8],
+        additional_input: &[FheUint8],
+    ) -> Result<(), Error> {
+        let seed_material = if self.derivation_function {
+            if entropy_input.len() < KEY_LEN {
+                return Err(Error::ParameterMismatch(
+                    "entropy input must be at least 16 bytes long".to_string(),
+                ));
+            }
+            block_cipher_df(&[entropy_input, additional_input].concat(), SEED_LEN)?
+        } else {
+            if entropy_input.len() != SEED_LEN {
+                return Err(Error::ParameterMismatch(
+                    "entropy input must be 32 bytes long without a derivation function".to_string(),
+                ));
+            }
+            xor_padded(entropy_input, additional_input)?
+        };
+
+        self.update(Some(&seed_material))?;
+        self.reseed_counter = 1;
+
+        Ok(())
+    }
+
+    /// Generates `requested_bytes` encrypted random bytes.
+    ///
+    /// # Arguments
+    /// * `requested_bytes` - The number of bytes to return, at most 65536.
+    /// * `additional_input` - The encrypted additional input, possibly empty.
+    ///
+    /// # Returns
+    /// * `Vec<FheUint8>` - The encrypted random bytes.
+    ///
+    /// # Errors
+    /// This function will return an `Error::InvalidInput` if a reseed is required or more than
+    /// 65536 bytes are requested.
+    pub fn generate(
+        &mut self,
+        requested_bytes: usize,
+        additional_input: &[FheUint8],
+    ) -> Result<Vec<FheUint8>, Error> {
+        if requested_bytes > MAX_BYTES_PER_REQUEST {
+            return Err(Error::InvalidInput(
+                "at most 65536 bytes can be generated per request".to_string(),
+            ));
+        }
+        if self.reseed_counter > RESEED_INTERVAL {
+            return Err(Error::InvalidInput("reseed required".to_string()));
+        }
+
+        // Without additional input, the final update uses an all-zero block, which is a no-op XOR
+        let additional_input = if additional_input.is_empty() {
+            None
+        } else if self.derivation_function {
+            Some(block_cipher_df(additional_input, SEED_LEN)?)
+        } else {
+            Some(xor_padded(&[], additional_input)?)
+        };
+
+        if let Some(additional_input) = &additional_input {
+            self.update(Some(additional_input))?;
+        }
+
+        let mut output = Vec::with_capacity(requested_bytes);
+        let mut block: [FheUint8; BLOCK_LEN] =
+            std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+
+        while output.len() < requested_bytes {
+            increment_block(&mut self.v);
+            aes_encrypt_block(&self.v, &mut block, &self.expanded_key)?;
+            output.extend_from_slice(&block);
+        }
+        output.truncate(requested_bytes);
+
+        self.update(additional_input.as_deref())?;
+        self.reseed_counter += 1;
+
+        Ok(output)
+    }
+
+    /// Builds the initial state from Key = 0, V = 0 and the given seed material.
+    fn from_seed_material(
+        seed_material: &[FheUint8],
+        derivation_function: bool,
+    ) -> Result<Self, Error> {
+        let key: [FheUint8; KEY_LEN] = std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+        let mut expanded_key: [FheUint8; 176] =
+            std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+        key_expansion_fhe(&key, &mut expanded_key)?;
+
+        let mut drbg = Self {
+            key,
+            expanded_key,
+            v: std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8)),
+            reseed_counter: 1,
+            derivation_function,
+        };
+        drbg.update(Some(seed_material))?;
+
+        Ok(drbg)
+    }
+
+    /// The CTR_DRBG_Update function: derives a new Key and V from the current state.
+    ///
+    /// `provided_data` must be 32 bytes long; `None` stands for the all-zero string.
+    fn update(&mut self, provided_data: Option<&[FheUint8]>) -> Result<(), Error> {
+        let mut temp: Vec<FheUint8> = Vec::with_capacity(SEED_LEN);
+        let mut block: [FheUint8; BLOCK_LEN] =
+            std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+
+        while temp.len() < SEED_LEN {
+            increment_block(&mut self.v);
+            aes_encrypt_block(&self.v, &mut block, &self.expanded_key)?;
+            temp.extend_from_slice(&block);
+        }
+
+        if let Some(provided_data) = provided_data {
+            for (t, p) in temp.iter_mut().zip(provided_data.iter()) {
+                *t ^= p;
+            }
+        }
+
+        self.key.clone_from_slice(&temp[..KEY_LEN]);
+        self.v.clone_from_slice(&temp[KEY_LEN..SEED_LEN]);
+        key_expansion_fhe(&self.key, &mut self.expanded_key)
+    }
+}
+
+/// Increments an encrypted 128-bit big-endian counter block by one, modulo 2^128.
+///
+/// # Behavior
+/// - The carry is kept as an encrypted boolean and propagated from the last byte to the first.
+pub fn increment_block(block: &mut [FheUint8; BLOCK_LEN]) {
+    let mut carry = FheBool::encrypt_trivial(true);
+
+    for byte in block.iter_mut().rev() {
+        *byte += FheUint8::cast_from(carry.clone());
+        carry &= byte.eq(0u8); // The carry continues only if the byte wrapped around to 0
+    }
+}
+
+/// XORs `input` into `data` zero-padded to the seed length (no derivation function).
+fn xor_padded(data: &[FheUint8], input: &[FheUint8]) -> Result<Vec<FheUint8>, Error> {
+    if input.len() > SEED_LEN || data.len() > SEED_LEN {
+        return Err(Error::ParameterMismatch(
+            "inputs must be at most 32 bytes long without a derivation function".to_string(),
+        ));
+    }
+
+    Ok((0..SEED_LEN)
+        .map(|i| match (data.get(i), input.get(i)) {
+            (Some(d), Some(x)) => d ^ x,
+            (Some(d), None) => d.clone(),
+            (None, Some(x)) => x.clone(),
+            (None, None) => FheUint8::encrypt_trivial(0u8),
+        })
+        .collect())
+}
+
+/// The Block_Cipher_df derivation function: compresses `input` into `output_len` encrypted bytes.
+///
+/// # Behavior
+/// - The lengths prefixed to the input are public and encoded as trivial encryptions.
+/// - BCC is the CBC-MAC from the `prf` module, keyed with the fixed key 0x00010203...0F.
+fn block_cipher_df(input: &[FheUint8], output_len: usize) -> Result<Vec<FheUint8>, Error> {
+    let trivial = |bytes: &[u8]| -> Vec<FheUint8> {
+        bytes
+            .iter()
+            .map(|byte| FheUint8::encrypt_trivial(*byte))
+            .collect()
+    };
+
+    // S = L || N || input_string || 0x80, zero-padded to a multiple of the block length
+    let mut s = trivial(&(input.len() as u32).to_be_bytes());
+    s.extend(trivial(&(output_len as u32).to_be_bytes()));
+    s.extend_from_slice(input);
+    s.push(FheUint8::encrypt_trivial(0x80u8));
+    while s.len() % BLOCK_LEN != 0 {
+        s.push(FheUint8::encrypt_trivial(0u8));
+    }
+
+    let df_key: [FheUint8; KEY_LEN] = std::array::from_fn(|i| FheUint8::encrypt_trivial(i as u8));
+    let mut expanded_key: [FheUint8; 176] = std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+    key_expansion_fhe(&df_key, &mut expanded_key)?;
+
+    let mut temp: Vec<FheUint8> = Vec::with_capacity(SEED_LEN);
+    let mut i = 0u32;
+    while temp.len() < SEED_LEN {
+        // IV = i || 0^(outlen - 32), followed by the blocks of S
+        let mut iv = trivial(&i.to_be_bytes());
+        iv.resize(BLOCK_LEN, FheUint8::encrypt_trivial(0u8));
+
+        let mut blocks = vec![iv];
+        blocks.extend(s.chunks(BLOCK_LEN).map(|chunk| chunk.to_vec()));
+
+        temp.extend_from_slice(&cbc_mac(&blocks, &expanded_key)?);
+        i += 1;
+    }
+
+    let key: [FheUint8; KEY_LEN] = std::array::from_fn(|i| temp[i].clone());
+    key_expansion_fhe(&key, &mut expanded_key)?;
+
+    let mut x: [FheUint8; BLOCK_LEN] = std::array::from_fn(|i| temp[KEY_LEN + i].clone());
+    let mut output = Vec::with_capacity(output_len);
+    while output.len() < output_len {
+        let input = x.to_vec();
+        aes_encrypt_block(&input, &mut x, &expanded_key)?;
+        output.extend_from_slice(&x);
+    }
+    output.truncate(output_len);
+
+    Ok(output)
+}
+
+#[cfg(test)]
+/// This module contains tests for the FHE CTR_DRBG.
+///
+/// # Functions
+///
+/// - `ctr_drbg_df_cavp`: Checks the generator with the derivation function against a CAVP `CTR_DRBG.rsp` vector.
+///
+/// - `ctr_drbg_no_df_cavp`: Checks the generator without derivation function against a CAVP `CTR_DRBG.rsp` vector.
+///
+/// - `ctr_drbg_no_df`: Checks the generator without derivation function against a clear-text CTR_DRBG.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following commands:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- drbg::tests::ctr_drbg_df_cavp --exact --show-output
+/// cargo test --release --package fhe-aes128 --lib -- drbg::tests::ctr_drbg_no_df_cavp --exact --show-output
+/// cargo test --release --package fhe-aes128 --lib -- drbg::tests::ctr_drbg_no_df --exact --show-output
+/// ```
+mod tests {
+    use aes::cipher::{BlockEncrypt, KeyInit};
+    use aes::Aes128;
+    use rand::Rng;
+    use tfhe::ClientKey;
+
+    use super::*;
+    use crate::context::FheAesContext;
+    use crate::params::ParameterSet;
+    use crate::utils::hex_to_bytes;
+
+    fn encrypt_bytes(bytes: &[u8], cks: &ClientKey) -> Vec<FheUint8> {
+        bytes.iter().map(|x| FheUint8::encrypt(*x, cks)).collect()
+    }
+
+    fn setup() -> (ClientKey, FheAesContext) {
+        FheAesContext::generate(ParameterSet::Default, 0).unwrap()
+    }
+
+    #[test]
+    fn ctr_drbg_df_cavp() {
+        // CTR_DRBG.rsp, [AES-128 use df], [PredictionResistance = True], COUNT = 0
+        let entropy_input = hex_to_bytes("92898f31fa1cff6d182f260643dff818").unwrap();
+        let nonce = hex_to_bytes("c2a4d972c3b9b697").unwrap();
+        let personalization_string = hex_to_bytes("ea65ee60264e7eb60e8268c4373c5c0b").unwrap();
+        let additional_input = [
+            hex_to_bytes("1a40fae3cc6c7ca0f8daba59236dad1d").unwrap(),
+            hex_to_bytes("9f72766cc746e5ed2e532012bc59318c").unwrap(),
+        ];
+        let entropy_input_pr = [
+            hex_to_bytes("20728a06f86f8dd441e272b7c42ce810").unwrap(),
+            hex_to_bytes("3db0f094f305503317863e2208f7a501").unwrap(),
+        ];
+        let returned_bits = hex_to_bytes(
+            "5a3539870f4d22a40924ee71c96fac720ad6f08882d0832873ec3f93d8ab4523\
+             f07eac45145e939fb1d676433db6e80888f6da89087742fe1af43fc423c51f68",
+        )
+        .unwrap();
+
+        let (cks, context) = setup();
+
+        context.install(|| {
+            let mut drbg = CtrDrbg::instantiate_df(
+                &encrypt_bytes(&entropy_input, &cks),
+                &encrypt_bytes(&nonce, &cks),
+                &encrypt_bytes(&personalization_string, &cks),
+            )
+            .unwrap();
+
+            // With prediction resistance, every generate call is preceded by a reseed
+            let mut output = vec![];
+            for (entropy, additional) in entropy_input_pr.iter().zip(additional_input.iter()) {
+                drbg.reseed(
+                    &encrypt_bytes(entropy, &cks),
+                    &encrypt_bytes(additional, &cks),
+                )
+                .unwrap();
+                output = drbg.generate(returned_bits.len(), &[]).unwrap();
+            }
+
+            let result: Vec<u8> = output.iter().map(|x| x.decrypt(&cks)).collect();
+            assert_eq!(result, returned_bits);
+        });
+    }
+
+    #[test]
+    fn ctr_drbg_no_df_cavp() {
+        // CTR_DRBG.rsp, [AES-128 no df], [PredictionResistance = False], COUNT = 0
+        let entropy_input =
+            hex_to_bytes("ed1e7f21ef66ea5d8e2a85b9337245445b71d6393a4eecb0e63c193d0f72f9a9")
+                .unwrap();
+        let entropy_input_reseed =
+            hex_to_bytes("303fb519f0a4e17d6df0b6426aa0ecb2a36079bd48be47ad2a8dbfe48da3efad")
+                .unwrap();
+        let returned_bits = hex_to_bytes(
+            "f80111d08e874672f32f42997133a5210f7a9375e22cea70587f9cfafebe0f6a\
+             6aa2eb68e7dd9164536d53fa020fcab20f54caddfab7d6d91e5ffec1dfd8deaa",
+        )
+        .unwrap();
+
+        let (cks, context) = setup();
+
+        context.install(|| {
+            let mut drbg = CtrDrbg::instantiate(&encrypt_bytes(&entropy_input, &cks), &[]).unwrap();
+            drbg.reseed(&encrypt_bytes(&entropy_input_reseed, &cks), &[])
+                .unwrap();
+
+            // The vector returns the output of the second generate call
+            drbg.generate(returned_bits.len(), &[]).unwrap();
+            let output = drbg.generate(returned_bits.len(), &[]).unwrap();
+
+            let result: Vec<u8> = output.iter().map(|x| x.decrypt(&cks)).collect();
+            assert_eq!(result, returned_bits);
+        });
+    }
+
+    /// Clear-text CTR_DRBG without derivation function, using the standard AES128 crate.
+    struct ClearCtrDrbg {
+        key: [u8; 16],
+        v: [u8; 16],
+    }
+
+    impl ClearCtrDrbg {
+        fn update(&mut self, provided_data: &[u8; 32]) {
+            let aes_cipher = Aes128::new((&self.key).into());
+            let mut temp = [0u8; 32];
+            for chunk in temp.chunks_mut(16) {
+                self.v = (u128::from_be_bytes(self.v).wrapping_add(1)).to_be_bytes();
+                chunk.copy_from_slice(&self.v);
+                aes_cipher.encrypt_block(chunk.into());
+            }
+            for (t, p) in temp.iter_mut().zip(provided_data.iter()) {
+                *t ^= p;
+            }
+            self.key.copy_from_slice(&temp[..16]);
+            self.v.copy_from_slice(&temp[16..]);
+        }
+
+        fn generate(&mut self, requested_bytes: usize, additional_input: &[u8; 32]) -> Vec<u8> {
+            self.update(additional_input);
+            let aes_cipher = Aes128::new((&self.key).into());
+            let mut output = vec![];
+            while output.len() < requested_bytes {
+                self.v = (u128::from_be_bytes(self.v).wrapping_add(1)).to_be_bytes();
+                let mut block = self.v;
+                aes_cipher.encrypt_block((&mut block).into());
+                output.extend_from_slice(&block);
+            }
+            self.update(additional_input);
+            output.truncate(requested_bytes);
+            output
+        }
+    }
+
+    #[test]
+    fn ctr_drbg_no_df() {
+        let mut rng = rand::thread_rng();
+        let entropy_input: [u8; 32] = rng.gen();
+        let additional_input: [u8; 32] = rng.gen();
+
+        let mut expected = ClearCtrDrbg {
+            key: [0u8; 16],
+            v: [0u8; 16],
+        };
+        expected.update(&entropy_input);
+        let expected_output = expected.generate(20, &additional_input);
+
+        let (cks, context) = setup();
+
+        context.install(|| {
+            let mut drbg = CtrDrbg::instantiate(&encrypt_bytes(&entropy_input, &cks), &[]).unwrap();
+            let output = drbg
+                .generate(20, &encrypt_bytes(&additional_input, &cks))
+                .unwrap();
+
+            let result: Vec<u8> = output.iter().map(|x| x.decrypt(&cks)).collect();
+            assert_eq!(result, expected_output);
+        });
+    }
+}
@@ -4,13 +4,13 @@
 /// - `sub_bytes`: Substitution of bytes using the AES S-Box for the SubBytes transformation.
 /// - `shift_rows`: Shifting rows of the AES state matrix for the ShiftRows transformation.
 /// - `mix_columns`: Mixing columns of the AES state matrix using Galois Field multiplication for the MixColumns transformation.
+///
 /// Each transformation is implemented with parallelism for performance optimization, utilizing the Rayon library and FHE techniques.
+/// The transformations are generic over the byte backend (see the `backend` module), so they also run on clear bytes.
+use crate::backend::AesByte;
+use crate::error::Error;
 use crate::SBOX;
 use rayon::prelude::*;
-use std::thread;
-use std::time::Duration;
-use tfhe::prelude::*;
-use tfhe::{FheUint8, MatchValues};
 
 /// Performs an element-wise XOR operation between two blocks of encrypted bytes (state and b).
 /// This is typically used in AES encryption for the AddRoundKey step.
@@ -22,7 +22,7 @@ use tfhe::{FheUint8, MatchValues};
 /// # Behavior
 /// - Each byte in `state` is XORed with the corresponding byte in `b`.
 /// - Uses parallel iteration [`par_iter_mut`] for performance optimization.
-pub fn add_blocks(state: &mut Vec<FheUint8>, b: &[FheUint8]) {
+pub fn add_blocks<B: AesByte>(state: &mut Vec<B>, b: &[B]) {
     state
         .par_iter_mut() // Iterate over `state` in parallel for performance
         .enumerate() // Keep track of index to access corresponding `b` element
@@ -38,25 +38,24 @@ pub fn add_blocks(state: &mut Vec<FheUint8>, b: &[FheUint8]) {
 /// * `state` - A mutable reference to a vector of encrypted bytes [FheUint8] representing the AES state.
 ///
 /// # Behavior
-/// - Constructs a matching vector from the AES [S-Box].
-/// - Uses [`MatchValues`] to perform a secure substitution of each byte.
+/// - Builds the lookup table of the AES [S-Box] with [`AesByte::table`].
+/// - Uses [`AesByte::lookup`] to perform a secure substitution of each byte.
 /// - Processes elements in parallel for efficiency.
-pub fn sub_bytes(state: &mut Vec<FheUint8>) {
-    // Create a matching vector using the AES S-Box for byte substitution
-    let match_vector: Vec<(u8, u8)> = (0u8..=255u8)
-        .map(|x| (x, SBOX[x as usize])) // Map each byte to its S-Box substitution
-        .collect();
-
-    // Initialize match values for secure lookup
-    let match_values = MatchValues::new(match_vector).unwrap();
+///
+/// # Errors
+/// This function will return an error if the homomorphic lookup fails.
+pub fn sub_bytes<B: AesByte>(state: &mut Vec<B>) -> Result<(), Error> {
+    // Build the S-Box lookup table for byte substitution
+    let table = B::table(&SBOX)?;
 
     // Apply S-Box substitution in parallel
     state
         .par_iter_mut() // Parallel iterator for efficient processing
-        .enumerate() // Include index for potential debugging
-        .for_each(|(index, i)| {
-            (*i, _) = i.match_value(&match_values).unwrap(); // Substitute byte using S-Box mapping
-        });
+        .try_for_each(|i| {
+            // Substitute byte using S-Box mapping
+            *i = i.lookup(&table)?;
+            Ok(())
+        })
 }
 
 /// Performs the ShiftRows transformation in AES encryption using Fully Homomorphic Encryption (FHE).
@@ -71,9 +70,9 @@ pub fn sub_bytes(state: &mut Vec<FheUint8>) {
 /// - The third row shifts left by 2 positions.
 /// - The fourth row shifts left by 3 positions.
 /// - This transformation is performed in parallel for efficiency.
-pub fn shift_rows(state: &mut Vec<FheUint8>) {
+pub fn shift_rows<B: AesByte>(state: &mut Vec<B>) {
     // Create a temporary copy of the state to use for reordering
-    let temp: Vec<FheUint8> = state.clone();
+    let temp: Vec<B> = state.clone();
 
     // Apply ShiftRows transformation in parallel
     state.par_iter_mut().enumerate().for_each(|(i, elem)| {
@@ -118,18 +117,15 @@ pub fn shift_rows(state: &mut Vec<FheUint8>) {
 /// * `b` - A plaintext byte (u8), the second operand.
 ///
 /// # Returns
-/// * `FheUint8` - The result of the multiplication in GF(2^8).
-pub fn gal_mul(a: FheUint8, b: u8) -> FheUint8 {
+/// * `B` - The result of the multiplication in GF(2^8).
+pub fn gal_mul<B: AesByte>(a: B, b: u8) -> B {
     // Initialize the result as 0 in GF(2^8)
-    let mut result: FheUint8 = FheUint8::encrypt_trivial(0u8);
+    let mut result = B::constant(0u8);
 
     // Copy inputs to mutable variables for processing
     let mut a = a;
     let mut b = b;
 
-    // AES uses the irreducible polynomial x^8 + x^4 + x^3 + x + 1 (0x1b) for field reduction
-    const IRREDUCIBLE_POLY: u8 = 0x1b;
-
     // Process each bit of `b`, using a multiplication-by-x approach
     while b != 0 {
         // If the least significant bit of `b` is 1, add `a` to the result using XOR (addition in GF(2^8))
@@ -137,14 +133,8 @@ pub fn gal_mul(a: FheUint8, b: u8) -> FheUint8 {
             result ^= a.clone();
         }
 
-        // Check if the highest bit (x^7) of `a` is set before shifting
-        let high_bit_set = (a.clone() & 0x80).ne(0); // Checks if the highest bit is 1
-
-        // Multiply `a` by x (left shift in GF(2^8))
-        a <<= 1u8;
-
-        // If `a` overflowed (i.e., its high bit was set), reduce it modulo the irreducible polynomial
-        a = (high_bit_set).if_then_else(&(a.clone() ^ IRREDUCIBLE_POLY), &a);
+        // Multiply `a` by x, reducing it modulo the irreducible polynomial if it overflowed
+        a = a.xtime();
 
         // Move to the next bit in `b` (divide by x)
         b >>= 1;
@@ -173,7 +163,7 @@ pub fn gal_mul(a: FheUint8, b: u8) -> FheUint8 {
 /// ```
 /// - Multiplications in [GF(2^8)] are performed using [`gal_mul()`].
 /// - Processing is parallelized for efficiency.
-pub fn mix_columns(state: &mut Vec<FheUint8>) {
+pub fn mix_columns<B: AesByte>(state: &mut Vec<B>) {
     // Create a copy of the state to prevent overwriting values prematurely
     let temp = state.clone();
 
@@ -0,0 +1,114 @@
+/// This module defines the error type shared by the whole crate.
+/// Every fallible public function returns `Result<T, Error>`, and the CLI turns an error into a
+/// message on stderr and a process exit code that depends on the kind of error.
+/// It includes the following items:
+/// - `Error`: The crate-wide error enum.
+/// - `Error::exit_code`: The exit code of the CLI for each kind of error.
+use std::fmt;
+
+/// The errors that can occur in the crate.
+#[derive(Debug)]
+pub enum Error {
+    /// An input (hexadecimal string, command line value, file contents) could not be parsed.
+    InvalidInput(String),
+    /// A key, IV, nonce or other parameter does not have the length or value the cipher expects.
+    ParameterMismatch(String),
+    /// A file or stream could not be read or written.
+    Io(String, std::io::Error),
+    /// A key, ciphertext or report could not be serialized or deserialized.
+    Serialization(String),
+    /// A homomorphic operation failed.
+    Fhe(String),
+    /// A decrypted result does not match the clear reference.
+    Verification(String),
+}
+
+impl Error {
+    /// Returns the exit code of the CLI for this error.
+    ///
+    /// Exit code 2 is also used by `clap` for invalid command line arguments.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidInput(_) => 2,
+            Error::ParameterMismatch(_) => 3,
+            Error::Io(..) => 4,
+            Error::Serialization(_) => 5,
+            Error::Fhe(_) => 6,
+            Error::Verification(_) => 7,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidInput(message) => write!(f, "invalid input: {}", message),
+            Error::ParameterMismatch(message) => write!(f, "parameter mismatch: {}", message),
+            Error::Io(context, source) => write!(f, "{}: {}", context, source),
+            Error::Serialization(message) => write!(f, "serialization error: {}", message),
+            Error::Fhe(message) => write!(f, "FHE operation failed: {}", message),
+            Error::Verification(message) => write!(f, "verification failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Returns an error if `actual` differs from the `expected` length of `what`.
+///
+/// # Arguments
+/// * `what` - The name of the checked value, e.g. "Trivium key".
+/// * `expected` - The expected length in bytes.
+/// * `actual` - The actual length in bytes.
+pub fn check_len(what: &str, expected: usize, actual: usize) -> Result<(), Error> {
+    if expected != actual {
+        return Err(Error::ParameterMismatch(format!(
+            "{} must be {} bytes long, got {}",
+            what, expected, actual
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+/// This module contains tests for the crate-wide error type.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- error::tests::invalid_inputs --exact --show-output
+/// ```
+mod tests {
+    use crate::utils::{hex_to_bytes, hex_to_u8_array};
+
+    use super::*;
+
+    #[test]
+    fn invalid_inputs() {
+        // A 31-character key, a non-hexadecimal character and an odd number of digits
+        let too_short = hex_to_u8_array("000102030405060708090a0b0c0d0e0").unwrap_err();
+        assert!(matches!(too_short, Error::InvalidInput(_)));
+        assert_eq!(too_short.exit_code(), 2);
+
+        assert!(matches!(hex_to_bytes("0g"), Err(Error::InvalidInput(_))));
+        assert!(matches!(hex_to_bytes("000"), Err(Error::InvalidInput(_))));
+
+        let mismatch = check_len("Trivium key", 10, 16).unwrap_err();
+        assert_eq!(mismatch.exit_code(), 3);
+        assert_eq!(
+            mismatch.to_string(),
+            "parameter mismatch: Trivium key must be 10 bytes long, got 16"
+        );
+        assert!(check_len("Trivium key", 10, 10).is_ok());
+    }
+}
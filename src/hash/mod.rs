@@ -0,0 +1,244 @@
+/// This module implements block-cipher-based hash functions using AES with Fully Homomorphic Encryption (FHE).
+/// It provides a homomorphic hash for encrypted data integrity checks without evaluating SHA under FHE.
+/// It includes functions for performing the following operations:
+/// - `md_pad`: Merkle–Damgård padding with length strengthening.
+/// - `mmo_compress` / `mmo_hash`: Matyas–Meyer–Oseas, H_i = E_{H_{i-1}}(m_i) ^ m_i.
+/// - `davies_meyer_compress` / `davies_meyer_hash`: Davies–Meyer, H_i = E_{m_i}(H_{i-1}) ^ H_{i-1}.
+/// - `mmo_hash_clear` / `davies_meyer_hash_clear`: The same constructions in the clear, for verification.
+///
+/// In both constructions the AES key changes with every message block (the chaining value for MMO,
+/// the message block for Davies–Meyer), so each compression runs a full `key_expansion_fhe`.
+/// AES-128 has equal key and block sizes, so the chaining value is used as a key without conversion.
+use crate::aes_encrypt_block;
+use crate::encryption::add_blocks;
+use crate::error::{check_len, Error};
+use crate::key_expansion::key_expansion_fhe;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use std::convert::Infallible;
+use tfhe::prelude::*;
+use tfhe::FheUint8;
+
+/// Size of an AES block in bytes.
+const BLOCK_SIZE: usize = 16;
+
+/// Initial chaining value H_0 shared by both constructions.
+pub const INITIAL_VALUE: [u8; 16] = [
+    0x67, 0x45, 0x23, 0x01, 0xef, 0xcd, 0xab, 0x89, 0x98, 0xba, 0xdc, 0xfe, 0x10, 0x32, 0x54, 0x76,
+];
+
+/// Applies Merkle–Damgård padding to a message and splits it into 16-byte blocks.
+///
+/// # Arguments
+/// * `message` - The bytes to pad, either encrypted or in the clear.
+/// * `encode` - Converts a constant byte into the element type of `message` (e.g. a trivial encryption).
+///
+/// # Behavior
+/// - A single 0x80 byte is appended, followed by zeros until the length is 8 modulo 16.
+/// - The message length in bits is appended as a big-endian 64-bit integer.
+/// - The message length is public, so the padding bytes are constants.
+pub fn md_pad<T: Clone>(message: &[T], encode: impl Fn(u8) -> T) -> Vec<Vec<T>> {
+    let mut padded = message.to_vec();
+    padded.push(encode(0x80u8));
+    while padded.len() % BLOCK_SIZE != BLOCK_SIZE - 8 {
+        padded.push(encode(0u8));
+    }
+    padded.extend(
+        ((message.len() as u64) * 8)
+            .to_be_bytes()
+            .iter()
+            .map(|byte| encode(*byte)),
+    );
+
+    padded
+        .chunks(BLOCK_SIZE)
+        .map(|chunk| chunk.to_vec())
+        .collect()
+}
+
+/// Iterates a compression function over the padded message, starting from [`INITIAL_VALUE`].
+/// The clear constructions never fail and use `Infallible` as the error type.
+fn merkle_damgard<T: Clone, E>(
+    message: &[T],
+    encode: impl Fn(u8) -> T,
+    compress: impl Fn(&[T; 16], &[T]) -> Result<[T; 16], E>,
+) -> Result<[T; 16], E> {
+    let mut chaining: [T; 16] = std::array::from_fn(|i| encode(INITIAL_VALUE[i]));
+
+    for block in md_pad(message, &encode) {
+        chaining = compress(&chaining, &block)?;
+    }
+
+    Ok(chaining)
+}
+
+/// Encrypts `block` under `key` with FHE, expanding the key first.
+fn encrypt_with_key(key: &[FheUint8; 16], block: &[FheUint8]) -> Result<[FheUint8; 16], Error> {
+    let mut expanded_key: [FheUint8; 176] = std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+    key_expansion_fhe(key, &mut expanded_key)?;
+
+    let mut output: [FheUint8; 16] = std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+    aes_encrypt_block(block, &mut output, &expanded_key)?;
+
+    Ok(output)
+}
+
+/// The Matyas–Meyer–Oseas compression function using FHE.
+///
+/// # Arguments
+/// * `chaining` - The encrypted chaining value H_{i-1}, used as the AES key.
+/// * `block` - The encrypted 16-byte message block m_i.
+///
+/// # Returns
+/// * `[FheUint8; 16]` - The encrypted chaining value H_i = E_{H_{i-1}}(m_i) ^ m_i.
+///
+/// # Errors
+/// This function will return an error if `block` is not 16 bytes long or if the homomorphic
+/// AES encryption fails.
+pub fn mmo_compress(
+    chaining: &[FheUint8; 16],
+    block: &[FheUint8],
+) -> Result<[FheUint8; 16], Error> {
+    check_len("Matyas–Meyer–Oseas message block", BLOCK_SIZE, block.len())?;
+
+    let mut state = encrypt_with_key(chaining, block)?.to_vec();
+    add_blocks(&mut state, block);
+
+    Ok(std::array::from_fn(|i| state[i].clone()))
+}
+
+/// The Davies–Meyer compression function using FHE.
+///
+/// # Arguments
+/// * `chaining` - The encrypted chaining value H_{i-1}, used as the AES plaintext.
+/// * `block` - The encrypted 16-byte message block m_i, used as the AES key.
+///
+/// # Returns
+/// * `[FheUint8; 16]` - The encrypted chaining value H_i = E_{m_i}(H_{i-1}) ^ H_{i-1}.
+///
+/// # Errors
+/// This function will return an error if `block` is not 16 bytes long or if the homomorphic
+/// AES encryption fails.
+pub fn davies_meyer_compress(
+    chaining: &[FheUint8; 16],
+    block: &[FheUint8],
+) -> Result<[FheUint8; 16], Error> {
+    check_len("Davies–Meyer message block", BLOCK_SIZE, block.len())?;
+
+    let key: [FheUint8; 16] = std::array::from_fn(|i| block[i].clone());
+    let mut state = encrypt_with_key(&key, chaining)?.to_vec();
+    add_blocks(&mut state, chaining);
+
+    Ok(std::array::from_fn(|i| state[i].clone()))
+}
+
+/// Hashes an encrypted message with the Matyas–Meyer–Oseas construction.
+///
+/// # Errors
+/// This function will return an error if a homomorphic AES encryption fails.
+pub fn mmo_hash(message: &[FheUint8]) -> Result<[FheUint8; 16], Error> {
+    merkle_damgard(message, FheUint8::encrypt_trivial, mmo_compress)
+}
+
+/// Hashes an encrypted message with the Davies–Meyer construction.
+///
+/// # Errors
+/// This function will return an error if a homomorphic AES encryption fails.
+pub fn davies_meyer_hash(message: &[FheUint8]) -> Result<[FheUint8; 16], Error> {
+    merkle_damgard(message, FheUint8::encrypt_trivial, davies_meyer_compress)
+}
+
+/// Computes the same hash as [`mmo_hash`] in the clear using the standard AES128 crate.
+pub fn mmo_hash_clear(message: &[u8]) -> [u8; 16] {
+    merkle_damgard(
+        message,
+        |byte| byte,
+        |chaining, block| {
+            let mut state: [u8; 16] = std::array::from_fn(|i| block[i]);
+            Aes128::new(chaining.into()).encrypt_block((&mut state).into());
+            Ok::<_, Infallible>(std::array::from_fn(|i| state[i] ^ block[i]))
+        },
+    )
+    .unwrap_or_else(|never| match never {})
+}
+
+/// Computes the same hash as [`davies_meyer_hash`] in the clear using the standard AES128 crate.
+pub fn davies_meyer_hash_clear(message: &[u8]) -> [u8; 16] {
+    merkle_damgard(
+        message,
+        |byte| byte,
+        |chaining, block| {
+            let mut state = *chaining;
+            Aes128::new(block.into()).encrypt_block((&mut state).into());
+            Ok::<_, Infallible>(std::array::from_fn(|i| state[i] ^ chaining[i]))
+        },
+    )
+    .unwrap_or_else(|never| match never {})
+}
+
+#[cfg(test)]
+/// This module contains tests for the AES-based hash constructions.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following commands:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- hash::tests::md_padding --exact --show-output
+/// cargo test --release --package fhe-aes128 --lib -- hash::tests::hash_constructions --exact --show-output
+/// ```
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::context::FheAesContext;
+    use crate::params::ParameterSet;
+
+    #[test]
+    fn md_padding() {
+        let blocks = md_pad(&[0xaau8; 7], |byte| byte);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0][7], 0x80);
+        assert_eq!(blocks[0][15], 56);
+
+        // Once the 0x80 byte overlaps the length field, an extra block is needed
+        let blocks = md_pad(&[0xaau8; 8], |byte| byte);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0][8], 0x80);
+        assert!(blocks[1][..8].iter().all(|byte| *byte == 0));
+        assert_eq!(blocks[1][14..], [0, 64]);
+
+        assert_eq!(md_pad(&[] as &[u8], |byte| byte).len(), 1);
+        assert_ne!(mmo_hash_clear(b"abc"), mmo_hash_clear(b"abc\x80"));
+        assert_ne!(mmo_hash_clear(b"abc"), davies_meyer_hash_clear(b"abc"));
+    }
+
+    #[test]
+    fn hash_constructions() {
+        let mut rng = rand::thread_rng();
+        let message: Vec<u8> = (0..rng.gen_range(1..=20)).map(|_| rng.gen()).collect();
+
+        let (cks, context) = FheAesContext::generate(ParameterSet::Default, 0).unwrap();
+
+        context.install(|| {
+            let message_fhe: Vec<FheUint8> = message
+                .iter()
+                .map(|x| FheUint8::encrypt(*x, &cks))
+                .collect();
+
+            let mmo: Vec<u8> = mmo_hash(&message_fhe)
+                .unwrap()
+                .iter()
+                .map(|x| x.decrypt(&cks))
+                .collect();
+            assert_eq!(mmo, mmo_hash_clear(&message));
+
+            let davies_meyer: Vec<u8> = davies_meyer_hash(&message_fhe)
+                .unwrap()
+                .iter()
+                .map(|x| x.decrypt(&cks))
+                .collect();
+            assert_eq!(davies_meyer, davies_meyer_hash_clear(&message));
+        });
+    }
+}
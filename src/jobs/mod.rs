@@ -0,0 +1,846 @@
+/// This module implements resumable encryption and transciphering jobs. A job is split into
+/// block-level tasks whose results are persisted as soon as they complete.
+/// It includes the following items:
+/// - `JobSpec` / `JobKind`: What a job computes with a registered key: FHE AES of counter blocks,
+///   or the transciphering of an AES-CTR ciphertext.
+/// - `WorkDir`: The directory of the jobs, holding their specifications, completed blocks and
+///   cancellation markers.
+/// - `JobQueue`: Worker threads running the jobs in the contexts of a `KeyRegistry`, with
+///   progress reporting, cancellation and resumption of the unfinished jobs.
+/// - `JobStatus` / `JobState`: The progress of a job.
+///
+/// Every job has a directory `<work dir>/<job ID>` holding:
+/// - `job.bin`: its specification;
+/// - `block-<index>.bin`: one file per completed block;
+/// - `block-<index>.round`: the round checkpoint of the block in progress (see the `checkpoint`
+///   module), so a block interrupted mid-computation resumes from its last completed round;
+/// - `cancelled`: present once the job is cancelled.
+///
+/// A block is written to a temporary file and renamed once complete. A process killed while
+/// writing a block therefore leaves no partial block behind, and resuming the job recomputes only
+/// the blocks without a file.
+use crate::artifacts::{load, save};
+use crate::checkpoint::CheckpointFile;
+use crate::error::Error;
+use crate::params::ParameterSet;
+use crate::registry::KeyRegistry;
+use crate::round_keys::KeySchedule;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use tfhe::prelude::*;
+use tfhe::FheUint8;
+
+/// The file holding the specification of a job.
+const SPEC_FILE: &str = "job.bin";
+
+/// The file marking a job as cancelled.
+const CANCELLED_FILE: &str = "cancelled";
+
+/// What a job computes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum JobKind {
+    /// Encrypts `blocks` counter blocks starting from the IV, like `encrypt` without input.
+    Encrypt { blocks: u64 },
+    /// Transciphers an AES-CTR ciphertext into FHE ciphertexts of the plaintext.
+    Transcipher { ciphertext: Vec<u8> },
+}
+
+/// A job: a computation with a key of the registry, split into 16-byte blocks.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobSpec {
+    /// The ID of the registered key.
+    pub key_id: String,
+    /// The initial counter block.
+    pub iv: [u8; 16],
+    /// What the job computes.
+    pub kind: JobKind,
+}
+
+impl JobSpec {
+    /// Returns the number of blocks of the job.
+    pub fn blocks(&self) -> u64 {
+        match &self.kind {
+            JobKind::Encrypt { blocks } => *blocks,
+            JobKind::Transcipher { ciphertext } => ciphertext.len().div_ceil(16) as u64,
+        }
+    }
+
+    /// Computes block `index` of the job, checkpointing the AES state after every round. Must run
+    /// in the context of the key's client.
+    ///
+    /// # Errors
+    /// This function will return an error if a homomorphic operation fails or the checkpoint
+    /// cannot be read or written.
+    fn compute_block(
+        &self,
+        index: u64,
+        schedule: &KeySchedule<FheUint8>,
+        checkpoint: &CheckpointFile,
+    ) -> Result<Vec<FheUint8>, Error> {
+        // The counter is public, so it is encrypted trivially
+        let counter: Vec<FheUint8> = counter_block(&self.iv, index)
+            .iter()
+            .map(|x| FheUint8::encrypt_trivial(*x))
+            .collect();
+        let keystream = checkpoint.encrypt_block(&counter, schedule)?;
+
+        match &self.kind {
+            JobKind::Encrypt { .. } => Ok(keystream.to_vec()),
+            JobKind::Transcipher { ciphertext } => {
+                let start = index as usize * 16;
+                let end = (start + 16).min(ciphertext.len());
+
+                Ok(keystream
+                    .into_par_iter()
+                    .zip(ciphertext[start..end].par_iter())
+                    .map(|(k, c)| k ^ *c)
+                    .collect())
+            }
+        }
+    }
+}
+
+/// Returns the counter block `index` blocks after `iv`, i.e. `increment_counter` applied `index`
+/// times.
+fn counter_block(iv: &[u8; 16], index: u64) -> [u8; 16] {
+    u128::from_be_bytes(*iv)
+        .wrapping_add(index as u128)
+        .to_be_bytes()
+}
+
+/// The state of a job.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobState {
+    /// Waiting for a worker, or interrupted and waiting to be resumed.
+    Pending,
+    /// Being computed by a worker.
+    Running,
+    /// Every block is computed.
+    Completed,
+    /// Cancelled; the completed blocks are kept, but the job is never resumed.
+    Cancelled,
+    /// Stopped by an error; resuming the job retries the failed block.
+    Failed(String),
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobState::Pending => write!(f, "pending"),
+            JobState::Running => write!(f, "running"),
+            JobState::Completed => write!(f, "completed"),
+            JobState::Cancelled => write!(f, "cancelled"),
+            JobState::Failed(message) => write!(f, "failed: {}", message),
+        }
+    }
+}
+
+/// The progress of a job.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobStatus {
+    pub state: JobState,
+    pub completed_blocks: u64,
+    pub total_blocks: u64,
+}
+
+impl JobStatus {
+    /// Returns whether the job will not make any more progress in this process.
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self.state,
+            JobState::Completed | JobState::Cancelled | JobState::Failed(_)
+        )
+    }
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, {} / {} blocks ({}%)",
+            self.state,
+            self.completed_blocks,
+            self.total_blocks,
+            self.completed_blocks * 100 / self.total_blocks.max(1)
+        )
+    }
+}
+
+/// The work directory of the jobs. It only touches files, so a process without keys can inspect,
+/// cancel or collect the jobs of another process.
+#[derive(Debug, Clone)]
+pub struct WorkDir {
+    dir: PathBuf,
+}
+
+impl WorkDir {
+    /// Opens the work directory at `dir`, creating it if needed.
+    ///
+    /// # Errors
+    /// This function will return an error if the directory cannot be created.
+    pub fn open(dir: &Path) -> Result<Self, Error> {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| Error::Io(format!("cannot create {}", dir.display()), e))?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Returns the path of the work directory.
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Creates the directory of a new job and writes its specification.
+    ///
+    /// # Arguments
+    /// * `params` - The parameter set of the key's client.
+    /// * `spec` - The specification of the job.
+    ///
+    /// # Returns
+    /// * `String` - The ID of the new job.
+    ///
+    /// # Errors
+    /// This function will return an error if the job directory or its specification cannot be
+    /// written.
+    pub fn create(&self, params: ParameterSet, spec: &JobSpec) -> Result<String, Error> {
+        let (job_id, dir) = loop {
+            let job_id = format!("{:016x}", rand::random::<u64>());
+            let dir = self.dir.join(&job_id);
+            if !dir.exists() {
+                break (job_id, dir);
+            }
+        };
+
+        std::fs::create_dir(&dir)
+            .map_err(|e| Error::Io(format!("cannot create {}", dir.display()), e))?;
+        save(&dir.join(SPEC_FILE), params, spec)?;
+
+        Ok(job_id)
+    }
+
+    /// Reads the specification of a job.
+    ///
+    /// # Returns
+    /// * `(ParameterSet, JobSpec)` - The parameter set of the job's key and the specification.
+    ///
+    /// # Errors
+    /// This function will return an error if the job is unknown or its specification cannot be
+    /// read.
+    pub fn spec(&self, job_id: &str) -> Result<(ParameterSet, JobSpec), Error> {
+        load(&self.job_dir(job_id)?.join(SPEC_FILE))
+    }
+
+    /// Returns the IDs of the jobs of the work directory.
+    ///
+    /// # Errors
+    /// This function will return an error if the directory cannot be listed.
+    pub fn job_ids(&self) -> Result<Vec<String>, Error> {
+        let entries = std::fs::read_dir(&self.dir)
+            .map_err(|e| Error::Io(format!("cannot list {}", self.dir.display()), e))?;
+        let mut ids = vec![];
+
+        for entry in entries {
+            let path = entry
+                .map_err(|e| Error::Io(format!("cannot list {}", self.dir.display()), e))?
+                .path();
+            if path.join(SPEC_FILE).exists() {
+                ids.extend(path.file_name().and_then(|x| x.to_str()).map(String::from));
+            }
+        }
+        ids.sort();
+
+        Ok(ids)
+    }
+
+    /// Returns the status of a job from its files: completed once every block is written,
+    /// otherwise cancelled or pending.
+    ///
+    /// # Errors
+    /// This function will return an error if the job is unknown or its specification cannot be
+    /// read.
+    pub fn status(&self, job_id: &str) -> Result<JobStatus, Error> {
+        let (_, spec) = self.spec(job_id)?;
+        let total_blocks = spec.blocks();
+        let completed_blocks = (0..total_blocks)
+            .filter(|index| self.block_path(job_id, *index).exists())
+            .count() as u64;
+
+        let state = if completed_blocks == total_blocks {
+            JobState::Completed
+        } else if self.is_cancelled(job_id) {
+            JobState::Cancelled
+        } else {
+            JobState::Pending
+        };
+
+        Ok(JobStatus {
+            state,
+            completed_blocks,
+            total_blocks,
+        })
+    }
+
+    /// Cancels a job. A worker running it, in this process or another one, stops before its next
+    /// block.
+    ///
+    /// # Returns
+    /// * `bool` - Whether the job was cancelled, i.e. was not already completed.
+    ///
+    /// # Errors
+    /// This function will return an error if the job is unknown or the marker cannot be written.
+    pub fn cancel(&self, job_id: &str) -> Result<bool, Error> {
+        if self.status(job_id)?.state == JobState::Completed {
+            return Ok(false);
+        }
+
+        let path = self.job_dir(job_id)?.join(CANCELLED_FILE);
+        std::fs::write(&path, b"")
+            .map_err(|e| Error::Io(format!("cannot create {}", path.display()), e))?;
+
+        Ok(true)
+    }
+
+    /// Returns whether a job was cancelled.
+    pub fn is_cancelled(&self, job_id: &str) -> bool {
+        self.job_dir(job_id)
+            .is_ok_and(|dir| dir.join(CANCELLED_FILE).exists())
+    }
+
+    /// Concatenates the blocks of a completed job.
+    ///
+    /// # Returns
+    /// * `(ParameterSet, Vec<FheUint8>)` - The parameter set of the job's key and the encrypted
+    ///   bytes.
+    ///
+    /// # Errors
+    /// This function will return an error if the job is not completed or a block cannot be read.
+    pub fn output(&self, job_id: &str) -> Result<(ParameterSet, Vec<FheUint8>), Error> {
+        let status = self.status(job_id)?;
+        if status.state != JobState::Completed {
+            return Err(Error::InvalidInput(format!(
+                "job {} is not completed: {}",
+                job_id, status
+            )));
+        }
+
+        let (params, _) = self.spec(job_id)?;
+        let mut bytes = vec![];
+
+        for index in 0..status.total_blocks {
+            let path = self.block_path(job_id, index);
+            let (block_params, block): (ParameterSet, Vec<FheUint8>) = load(&path)?;
+            params.check(&format!("Block {}", path.display()), block_params)?;
+            bytes.extend(block);
+        }
+
+        Ok((params, bytes))
+    }
+
+    /// Deletes the directory of a job.
+    ///
+    /// # Errors
+    /// This function will return an error if the job is unknown or its directory cannot be
+    /// deleted.
+    pub fn remove(&self, job_id: &str) -> Result<(), Error> {
+        let dir = self.job_dir(job_id)?;
+
+        std::fs::remove_dir_all(&dir)
+            .map_err(|e| Error::Io(format!("cannot delete {}", dir.display()), e))
+    }
+
+    /// Writes a completed block of a job, atomically.
+    fn write_block(
+        &self,
+        job_id: &str,
+        index: u64,
+        params: ParameterSet,
+        block: &[FheUint8],
+    ) -> Result<(), Error> {
+        let path = self.block_path(job_id, index);
+        let partial = path.with_extension("tmp");
+
+        save(&partial, params, &block)?;
+        std::fs::rename(&partial, &path)
+            .map_err(|e| Error::Io(format!("cannot create {}", path.display()), e))
+    }
+
+    /// Returns the file of a block of a job.
+    fn block_path(&self, job_id: &str, index: u64) -> PathBuf {
+        self.dir.join(job_id).join(format!("block-{}.bin", index))
+    }
+
+    /// Returns the directory of an existing job.
+    fn job_dir(&self, job_id: &str) -> Result<PathBuf, Error> {
+        let dir = self.dir.join(job_id);
+        let valid = !job_id.is_empty() && job_id.chars().all(|c| c.is_ascii_hexdigit());
+
+        match valid && dir.join(SPEC_FILE).exists() {
+            true => Ok(dir),
+            false => Err(Error::InvalidInput(format!("unknown job {:?}", job_id))),
+        }
+    }
+}
+
+/// A job known to the queue.
+struct Job {
+    id: String,
+    params: ParameterSet,
+    spec: JobSpec,
+    cancelled: AtomicBool,
+    /// The state and the number of completed blocks, notified at every change.
+    progress: Mutex<(JobState, u64)>,
+    changed: Condvar,
+}
+
+impl Job {
+    /// Returns the status of the job from its locked progress.
+    fn status(&self, progress: &(JobState, u64)) -> JobStatus {
+        JobStatus {
+            state: progress.0.clone(),
+            completed_blocks: progress.1,
+            total_blocks: self.spec.blocks(),
+        }
+    }
+
+    /// Updates the progress of the job and wakes up its waiters.
+    fn update(&self, update: impl FnOnce(&mut (JobState, u64))) {
+        update(&mut self.progress.lock().expect("job lock poisoned"));
+        self.changed.notify_all();
+    }
+}
+
+/// The state shared by the queue and its workers.
+struct Shared {
+    work_dir: WorkDir,
+    registry: Arc<KeyRegistry>,
+    /// Set when the queue is dropped: the workers stop before their next block, leaving the job
+    /// pending.
+    shutdown: AtomicBool,
+}
+
+/// Worker threads running the jobs of a work directory, each in the context of its key's client.
+///
+/// # Behavior
+/// A worker computes the blocks of a job one after the other, each block using the whole thread
+/// pool of the client's context, and persists every block once computed. Dropping the queue stops
+/// the workers after their current block; the interrupted jobs stay pending and are picked up by
+/// `resume` in a later process.
+pub struct JobQueue {
+    shared: Arc<Shared>,
+    jobs: Mutex<HashMap<String, Arc<Job>>>,
+    sender: Mutex<Option<Sender<Arc<Job>>>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl JobQueue {
+    /// Starts `workers` worker threads running the jobs of `work_dir` with the keys of `registry`.
+    ///
+    /// # Arguments
+    /// * `work_dir` - The work directory the jobs are persisted to.
+    /// * `registry` - The registry holding the keys the jobs refer to.
+    /// * `workers` - The number of jobs run at the same time, at least 1.
+    ///
+    /// # Errors
+    /// This function will return an error if a worker thread cannot be spawned.
+    pub fn new(
+        work_dir: WorkDir,
+        registry: Arc<KeyRegistry>,
+        workers: usize,
+    ) -> Result<Self, Error> {
+        let shared = Arc::new(Shared {
+            work_dir,
+            registry,
+            shutdown: AtomicBool::new(false),
+        });
+        let (sender, receiver) = channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..workers.max(1))
+            .map(|i| {
+                let (shared, receiver) = (shared.clone(), receiver.clone());
+                std::thread::Builder::new()
+                    .name(format!("fhe-aes128-job-{}", i))
+                    .spawn(move || work(&shared, &receiver))
+                    .map_err(|e| Error::Io("cannot spawn a job worker".to_string(), e))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(Self {
+            shared,
+            jobs: Mutex::new(HashMap::new()),
+            sender: Mutex::new(Some(sender)),
+            workers,
+        })
+    }
+
+    /// Returns the work directory of the queue.
+    pub fn work_dir(&self) -> &WorkDir {
+        &self.shared.work_dir
+    }
+
+    /// Persists a new job and queues it.
+    ///
+    /// # Returns
+    /// * `String` - The ID of the job.
+    ///
+    /// # Errors
+    /// This function will return an error if the job has no block, if its key is unknown, or if
+    /// it cannot be persisted.
+    pub fn submit(&self, spec: JobSpec) -> Result<String, Error> {
+        if spec.blocks() == 0 {
+            return Err(Error::InvalidInput(
+                "a job must have at least one block".to_string(),
+            ));
+        }
+
+        let (client, _) = self.shared.registry.get(&spec.key_id)?;
+        let job_id = self.shared.work_dir.create(client.params(), &spec)?;
+        self.enqueue(&job_id, client.params(), spec, 0);
+
+        Ok(job_id)
+    }
+
+    /// Queues the pending jobs of the work directory that the queue does not know yet, e.g. the
+    /// jobs interrupted by the end of a previous process, and requeues the jobs of the queue that
+    /// failed. Their completed blocks are not recomputed.
+    ///
+    /// # Returns
+    /// * `Vec<String>` - The IDs of the resumed jobs.
+    ///
+    /// # Errors
+    /// This function will return an error if the work directory or a job specification cannot be
+    /// read.
+    pub fn resume(&self) -> Result<Vec<String>, Error> {
+        let mut resumed = vec![];
+
+        for job_id in self.shared.work_dir.job_ids()? {
+            // A known job is left to its worker, unless it failed
+            if let Some(job) = self.job(&job_id) {
+                let progress = job.progress.lock().expect("job lock poisoned");
+                if !matches!(progress.0, JobState::Failed(_)) {
+                    continue;
+                }
+            }
+
+            let status = self.shared.work_dir.status(&job_id)?;
+            if status.state == JobState::Pending {
+                let (params, spec) = self.shared.work_dir.spec(&job_id)?;
+                self.enqueue(&job_id, params, spec, status.completed_blocks);
+                resumed.push(job_id);
+            }
+        }
+
+        Ok(resumed)
+    }
+
+    /// Returns the status of a job, from the queue if it knows the job, otherwise from the work
+    /// directory.
+    ///
+    /// # Errors
+    /// This function will return an error if the job is unknown.
+    pub fn status(&self, job_id: &str) -> Result<JobStatus, Error> {
+        match self.job(job_id) {
+            Some(job) => Ok(job.status(&job.progress.lock().expect("job lock poisoned"))),
+            None => self.shared.work_dir.status(job_id),
+        }
+    }
+
+    /// Cancels a job, see `WorkDir::cancel`.
+    ///
+    /// # Returns
+    /// * `bool` - Whether the job was cancelled, i.e. was not already completed.
+    ///
+    /// # Errors
+    /// This function will return an error if the job is unknown or the marker cannot be written.
+    pub fn cancel(&self, job_id: &str) -> Result<bool, Error> {
+        let cancelled = self.shared.work_dir.cancel(job_id)?;
+
+        if let Some(job) = self.job(job_id) {
+            job.cancelled.store(cancelled, Ordering::SeqCst);
+        }
+
+        Ok(cancelled)
+    }
+
+    /// Waits until a job of the queue is completed, cancelled or failed.
+    ///
+    /// # Arguments
+    /// * `job_id` - The ID of the job.
+    /// * `progress` - Called with the status of the job at every completed block.
+    ///
+    /// # Returns
+    /// * `JobStatus` - The final status of the job.
+    ///
+    /// # Errors
+    /// This function will return an error if the job is unknown.
+    pub fn wait(
+        &self,
+        job_id: &str,
+        mut progress: impl FnMut(&JobStatus),
+    ) -> Result<JobStatus, Error> {
+        let job = match self.job(job_id) {
+            Some(job) => job,
+            None => return self.shared.work_dir.status(job_id),
+        };
+
+        let mut state = job.progress.lock().expect("job lock poisoned");
+        let mut reported = None;
+
+        loop {
+            let status = job.status(&state);
+            if reported.as_ref() != Some(&status) {
+                progress(&status);
+            }
+            if status.is_finished() {
+                return Ok(status);
+            }
+
+            reported = Some(status);
+            state = job.changed.wait(state).expect("job lock poisoned");
+        }
+    }
+
+    /// Returns a job known to the queue.
+    fn job(&self, job_id: &str) -> Option<Arc<Job>> {
+        self.jobs
+            .lock()
+            .expect("job lock poisoned")
+            .get(job_id)
+            .cloned()
+    }
+
+    /// Adds a job to the queue.
+    fn enqueue(&self, job_id: &str, params: ParameterSet, spec: JobSpec, completed_blocks: u64) {
+        let job = Arc::new(Job {
+            id: job_id.to_string(),
+            params,
+            spec,
+            cancelled: AtomicBool::new(false),
+            progress: Mutex::new((JobState::Pending, completed_blocks)),
+            changed: Condvar::new(),
+        });
+
+        self.jobs
+            .lock()
+            .expect("job lock poisoned")
+            .insert(job_id.to_string(), job.clone());
+
+        if let Some(sender) = self.sender.lock().expect("job lock poisoned").as_ref() {
+            // The workers only stop once the sender is dropped
+            let _ = sender.send(job);
+        }
+    }
+}
+
+impl Drop for JobQueue {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        self.sender.lock().expect("job lock poisoned").take();
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// The loop of a worker thread: runs the queued jobs until the queue is dropped.
+fn work(shared: &Shared, receiver: &Mutex<Receiver<Arc<Job>>>) {
+    loop {
+        let job = match receiver.lock().expect("job lock poisoned").recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        if shared.shutdown.load(Ordering::SeqCst) {
+            return;
+        }
+
+        job.update(|progress| progress.0 = JobState::Running);
+        let state = run_job(shared, &job).unwrap_or_else(|e| JobState::Failed(e.to_string()));
+        job.update(|progress| progress.0 = state);
+    }
+}
+
+/// Computes the missing blocks of a job, in order.
+///
+/// # Returns
+/// * `JobState` - `Completed`, `Cancelled`, or `Pending` if the queue was dropped.
+///
+/// # Errors
+/// This function will return an error if the key is unknown or was produced with other parameters
+/// than the job, if a homomorphic operation fails, or if a block cannot be written.
+fn run_job(shared: &Shared, job: &Job) -> Result<JobState, Error> {
+    let (client, schedule) = shared.registry.get(&job.spec.key_id)?;
+    client
+        .params()
+        .check(&format!("Job {}", job.id), job.params)?;
+
+    for index in 0..job.spec.blocks() {
+        if shared.work_dir.block_path(&job.id, index).exists() {
+            continue;
+        }
+        if job.cancelled.load(Ordering::SeqCst) || shared.work_dir.is_cancelled(&job.id) {
+            return Ok(JobState::Cancelled);
+        }
+        if shared.shutdown.load(Ordering::SeqCst) {
+            return Ok(JobState::Pending);
+        }
+
+        let checkpoint = CheckpointFile::new(
+            &shared
+                .work_dir
+                .block_path(&job.id, index)
+                .with_extension("round"),
+            job.params,
+        );
+        let block = client.install(|| job.spec.compute_block(index, &schedule, &checkpoint))?;
+        shared
+            .work_dir
+            .write_block(&job.id, index, job.params, &block)?;
+        checkpoint.remove()?;
+
+        job.update(|progress| progress.1 += 1);
+    }
+
+    Ok(JobState::Completed)
+}
+
+#[cfg(test)]
+/// This module contains tests for the job queue.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- jobs::tests::resumable_jobs --exact --show-output
+/// ```
+mod tests {
+    use aes::cipher::{BlockEncrypt, KeyInit};
+    use aes::Aes128;
+    use rand::Rng;
+    use std::time::Duration;
+    use tfhe::prelude::*;
+    use tfhe::{generate_keys, ConfigBuilder};
+
+    use super::*;
+    use crate::round_keys::ScheduleMode;
+    use crate::utils::{increment_counter, key_expansion_clear, TestDir};
+
+    #[test]
+    fn resumable_jobs() {
+        let mut rng = rand::thread_rng();
+        let dir = TestDir::new("jobs");
+
+        let key: [u8; 16] = rng.gen();
+        let iv: [u8; 16] = rng.gen();
+        let plaintext: Vec<u8> = (0..40).map(|_| rng.gen()).collect();
+
+        // AES-CTR in the clear
+        let cipher = Aes128::new((&key).into());
+        let mut counter = iv;
+        let mut ciphertext = plaintext.clone();
+        for chunk in ciphertext.chunks_mut(16) {
+            let mut block = counter;
+            cipher.encrypt_block((&mut block).into());
+            chunk.iter_mut().zip(block).for_each(|(c, k)| *c ^= k);
+            counter = increment_counter(&counter);
+        }
+
+        let (cks, sks) = generate_keys(ConfigBuilder::default().build());
+        let expanded_key = key_expansion_clear(&key);
+        let expanded_key_fhe = Box::new(std::array::from_fn(|i| {
+            FheUint8::encrypt(expanded_key[i], &cks)
+        }));
+
+        let registry = Arc::new(KeyRegistry::new(1));
+        registry
+            .add_client("client", ParameterSet::Default, sks, 0)
+            .unwrap();
+        registry
+            .insert_key(
+                "key",
+                "client",
+                KeySchedule::from_expanded_key(expanded_key_fhe, ScheduleMode::Cached),
+            )
+            .unwrap();
+
+        // A single worker: the second job waits for the first one and is cancelled before starting
+        let queue = JobQueue::new(WorkDir::open(&dir).unwrap(), registry.clone(), 1).unwrap();
+        let transcipher_id = queue
+            .submit(JobSpec {
+                key_id: "key".to_string(),
+                iv,
+                kind: JobKind::Transcipher {
+                    ciphertext: ciphertext.clone(),
+                },
+            })
+            .unwrap();
+        let encrypt_id = queue
+            .submit(JobSpec {
+                key_id: "key".to_string(),
+                iv,
+                kind: JobKind::Encrypt { blocks: 2 },
+            })
+            .unwrap();
+        assert!(queue.cancel(&encrypt_id).unwrap());
+
+        // Stop the process after the first block of the transciphering job
+        while queue.status(&transcipher_id).unwrap().completed_blocks == 0 {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        let work_dir = queue.work_dir().clone();
+        drop(queue);
+
+        let status = work_dir.status(&transcipher_id).unwrap();
+        assert_eq!(status.state, JobState::Pending);
+        assert!(status.completed_blocks > 0 && status.completed_blocks < 3);
+        assert_eq!(
+            work_dir.status(&encrypt_id).unwrap(),
+            JobStatus {
+                state: JobState::Cancelled,
+                completed_blocks: 0,
+                total_blocks: 2
+            }
+        );
+        assert!(work_dir.output(&transcipher_id).is_err());
+
+        // A new queue resumes the interrupted job only; without its key, the job fails
+        let (_, schedule) = registry.get("key").unwrap();
+        registry.remove_key("key").unwrap();
+
+        let queue = JobQueue::new(work_dir.clone(), registry.clone(), 1).unwrap();
+        assert_eq!(queue.resume().unwrap(), vec![transcipher_id.clone()]);
+        let status = queue.wait(&transcipher_id, |_| {}).unwrap();
+        assert!(matches!(status.state, JobState::Failed(_)));
+
+        // Resuming again retries the failed job, from its last completed block
+        let schedule = KeySchedule::from_bytes(schedule.to_bytes()).unwrap();
+        registry.insert_key("key", "client", schedule).unwrap();
+        assert_eq!(queue.resume().unwrap(), vec![transcipher_id.clone()]);
+
+        let mut reports = vec![];
+        let status = queue
+            .wait(&transcipher_id, |status| {
+                reports.push(status.completed_blocks)
+            })
+            .unwrap();
+        assert_eq!(status.state, JobState::Completed);
+        assert_eq!(reports.last(), Some(&3));
+
+        let (_, output) = work_dir.output(&transcipher_id).unwrap();
+        let decrypted: Vec<u8> = output.iter().map(|x| x.decrypt(&cks)).collect();
+        assert_eq!(decrypted, plaintext);
+
+        work_dir.remove(&transcipher_id).unwrap();
+        assert_eq!(work_dir.job_ids().unwrap(), vec![encrypt_id]);
+    }
+}
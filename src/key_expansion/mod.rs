@@ -1,29 +1,24 @@
 /// This module implements AES key expansion using Fully Homomorphic Encryption (FHE).
-/// It defines the necessary constants, such as round constants (RCON), and utilizes encrypted bytes
+/// It uses the round constants (RCON) of the `utils` module and utilizes encrypted bytes
 /// (FheUint8) to perform the AES key expansion securely. The key expansion process applies operations
 /// like cyclic shifting, S-Box substitution, and XOR with round constants while keeping all computations
 /// encrypted, ensuring the privacy of the key throughout the process. The code also leverages parallelism
 /// using the Rayon library to speed up S-Box substitutions.
 ///
-/// The `key_expansion_fhe` function performs the AES key expansion and encrypts the expanded key for secure use in AES encryption.
-use crate::{get_match_values, SBOX};
-use rayon::prelude::*;
-use std::time::Instant;
-use tfhe::{prelude::FheTrivialEncrypt, FheUint, FheUint8, FheUint8Id};
-
-/// Round constants (RCON) used in AES key expansion.
-/// These constants are used in the key schedule core function to introduce non-linearity
-/// and ensure key uniqueness across different rounds.
+/// The `inv_key_expansion_fhe` function runs the expansion backwards, from the last round key.
 ///
-/// The values follow the AES key expansion specification:
-/// - RCON[0] is unused (0x00).
-/// - RCON[1] to RCON[10] correspond to the first 10 rounds of AES key expansion.
-/// - Each value is derived from powers of 2 in the finite field GF(2^8).
+/// The `verify_key_schedule` function decrypts an expanded key and checks it against the clear key
+/// expansion of `utils`, reporting the first mismatching word as a `ScheduleMismatch`.
 ///
-/// These constants help in the generation of round keys, ensuring cryptographic security.
-const R_CONSTANTS: [u8; 11] = [
-    0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1B, 0x36,
-];
+/// The `key_expansion_fhe` function is generic over the byte backend (see the `backend` module) and performs the AES key expansion and encrypts the expanded key for secure use in AES encryption.
+use crate::backend::AesByte;
+use crate::error::Error;
+use crate::utils::{key_expansion_clear, R_CONSTANTS};
+use crate::SBOX;
+use rayon::prelude::*;
+use std::fmt;
+use tfhe::prelude::*;
+use tfhe::{ClientKey, FheUint8};
 
 /// Expands a 128-bit AES key (16 bytes) into a 176-byte expanded key using Fully Homomorphic Encryption (FHE).
 /// This function follows the AES key expansion process while applying FHE operations on encrypted bytes.
@@ -31,25 +26,23 @@ const R_CONSTANTS: [u8; 11] = [
 /// # Arguments
 /// * `key` - A reference to an array of 16 encrypted bytes (FheUint8) representing the initial AES key.
 /// * `expanded_key` - A mutable reference to an array of 176 encrypted bytes to store the expanded key.
-pub fn key_expansion_fhe(key: &[FheUint8; 16], expanded_key: &mut [FheUint8; 176]) {
-    // Start measuring time for key expansion
-    let key_expansion_time = Instant::now();
-
+///
+/// # Errors
+/// This function will return an error if a homomorphic S-Box lookup fails.
+pub fn key_expansion_fhe<B: AesByte>(
+    key: &[B; 16],
+    expanded_key: &mut [B; 176],
+) -> Result<(), Error> {
     // Copy the initial 16-byte key to the beginning of the expanded key array
     expanded_key[0..16].clone_from_slice(&key[..]);
 
     let mut i = 16usize; // Track the current index in expanded_key
 
     // Temporary storage for processing 4 bytes at a time
-    let mut temp: [FheUint8; 4] = [
-        FheUint8::encrypt_trivial(0u8),
-        FheUint8::encrypt_trivial(0u8),
-        FheUint8::encrypt_trivial(0u8),
-        FheUint8::encrypt_trivial(0u8),
-    ];
+    let mut temp: [B; 4] = std::array::from_fn(|_| B::constant(0u8));
 
-    // Retrieve the precomputed match values used for S-Box substitution
-    let match_values = get_match_values();
+    // Build the lookup table used for S-Box substitution
+    let table = B::table(&SBOX)?;
 
     // Continue expanding the key until we reach 176 bytes
     while i < 176 {
@@ -62,10 +55,10 @@ pub fn key_expansion_fhe(key: &[FheUint8; 16], expanded_key: &mut [FheUint8; 176
             temp.rotate_left(1);
 
             // Apply S-Box substitution to each byte in parallel
-            temp.par_iter_mut().for_each(|byte| {
-                let (result, _): (FheUint8, _) = byte.match_value(&match_values).unwrap();
-                *byte = result;
-            });
+            temp.par_iter_mut().try_for_each(|byte| {
+                *byte = byte.lookup(&table)?;
+                Ok::<(), Error>(())
+            })?;
 
             // XOR the first byte with the round constant (RC)
             temp[0] ^= R_CONSTANTS[i / 16];
@@ -80,7 +73,181 @@ pub fn key_expansion_fhe(key: &[FheUint8; 16], expanded_key: &mut [FheUint8; 176
         i += 4;
     }
 
-    // Calculate and print the total time taken for key expansion
-    let key_expansion_duration = key_expansion_time.elapsed().as_secs();
-    println!("AES key expansion took: {} seconds", key_expansion_duration);
+    Ok(())
+}
+
+/// Reconstructs the 176-byte expanded key from the last round key, running the AES key expansion
+/// backwards with Fully Homomorphic Encryption (FHE).
+/// This lets a decryption-only server store round key 10 alone: the output has the layout of
+/// `key_expansion_fhe`, so `aes_decrypt_block` uses it unchanged, and its first 16 bytes are the
+/// original AES key.
+///
+/// # Arguments
+/// * `last_round_key` - A reference to an array of 16 encrypted bytes holding round key 10.
+/// * `expanded_key` - A mutable reference to an array of 176 encrypted bytes to store the expanded key.
+///
+/// # Behavior
+/// - The forward expansion computes `w[i] = w[i - 4] ^ T(w[i - 1])`, where `T` is the key schedule
+///   core for the first word of a round key and the identity otherwise. Going backwards from
+///   round key 10, `w[i - 4] = w[i] ^ T(w[i - 1])`, where `w[i - 1]` is always already known.
+/// - The cost is the same as the forward expansion: 40 S-Box lookups.
+///
+/// # Errors
+/// This function will return an error if a homomorphic S-Box lookup fails.
+pub fn inv_key_expansion_fhe<B: AesByte>(
+    last_round_key: &[B; 16],
+    expanded_key: &mut [B; 176],
+) -> Result<(), Error> {
+    // Copy round key 10 to the end of the expanded key array
+    expanded_key[160..176].clone_from_slice(&last_round_key[..]);
+
+    // Temporary storage for processing 4 bytes at a time
+    let mut temp: [B; 4] = std::array::from_fn(|_| B::constant(0u8));
+
+    // Build the lookup table used for S-Box substitution
+    let table = B::table(&SBOX)?;
+
+    // Recover the words from the last one down to the first one of round key 1
+    for i in (16..176).step_by(4).rev() {
+        // The word before w[i], already known
+        temp.clone_from_slice(&expanded_key[i - 4..i]);
+
+        // The first word of each round key went through the key schedule core
+        if i % 16 == 0 {
+            temp.rotate_left(1);
+
+            temp.par_iter_mut().try_for_each(|byte| {
+                *byte = byte.lookup(&table)?;
+                Ok::<(), Error>(())
+            })?;
+
+            temp[0] ^= R_CONSTANTS[i / 16];
+        }
+
+        // Undo the XOR with the word of the previous round key
+        for j in 0..4 {
+            expanded_key[i - 16 + j] = expanded_key[i + j].clone() ^ temp[j].clone();
+        }
+    }
+
+    Ok(())
+}
+
+/// The first word of a key schedule that differs from the clear key expansion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleMismatch {
+    /// The index of the word in the schedule, from 0 to 43.
+    pub word: usize,
+    /// The round key holding the word, from 0 (the cipher key) to 10.
+    pub round: usize,
+    /// The word of the clear key expansion.
+    pub expected: [u8; 4],
+    /// The word of the checked schedule.
+    pub actual: [u8; 4],
+}
+
+impl fmt::Display for ScheduleMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "key schedule word {} (word {} of round key {}) is {:02x?}, expected {:02x?}",
+            self.word,
+            self.word % 4,
+            self.round,
+            self.actual,
+            self.expected
+        )
+    }
+}
+
+/// Compares a clear key schedule with the key expansion of `key`.
+///
+/// # Arguments
+/// * `schedule` - The 176-byte key schedule to check, e.g. a decrypted `key_expansion_fhe` output.
+/// * `key` - The AES key the schedule should be expanded from.
+///
+/// # Returns
+/// * `Option<ScheduleMismatch>` - The first mismatching word, or `None` if the schedules match.
+pub fn compare_key_schedule(schedule: &[u8; 176], key: &[u8; 16]) -> Option<ScheduleMismatch> {
+    let expected = key_expansion_clear(key);
+
+    schedule
+        .chunks(4)
+        .zip(expected.chunks(4))
+        .position(|(actual, expected)| actual != expected)
+        .map(|word| ScheduleMismatch {
+            word,
+            round: word / 4,
+            expected: std::array::from_fn(|i| expected[4 * word + i]),
+            actual: std::array::from_fn(|i| schedule[4 * word + i]),
+        })
+}
+
+/// Decrypts an encrypted key schedule with the client key and checks every round key against the
+/// clear key expansion of `key`.
+///
+/// # Arguments
+/// * `expanded_key` - The encrypted key schedule produced by `key_expansion_fhe`.
+/// * `key` - The clear AES key.
+/// * `cks` - The client key.
+///
+/// # Errors
+/// This function will return a verification error describing the first mismatching word and its
+/// round if the schedules differ.
+pub fn verify_key_schedule(
+    expanded_key: &[FheUint8; 176],
+    key: &[u8; 16],
+    cks: &ClientKey,
+) -> Result<(), Error> {
+    let decrypted: Vec<u8> = expanded_key.par_iter().map(|x| x.decrypt(cks)).collect();
+    let schedule: [u8; 176] = std::array::from_fn(|i| decrypted[i]);
+
+    match compare_key_schedule(&schedule, key) {
+        Some(mismatch) => Err(Error::Verification(mismatch.to_string())),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the inverse key expansion.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- key_expansion::tests::inverse_key_expansion --exact --show-output
+/// ```
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::context::FheAesContext;
+    use crate::params::ParameterSet;
+
+    #[test]
+    fn inverse_key_expansion() {
+        let mut rng = rand::thread_rng();
+        let key: [u8; 16] = rng.gen();
+        let expected = key_expansion_clear(&key);
+        let last_round_key: [u8; 16] = std::array::from_fn(|i| expected[160 + i]);
+
+        // Clear bytes
+        let mut expanded_key = [0u8; 176];
+        inv_key_expansion_fhe(&last_round_key, &mut expanded_key).unwrap();
+        assert_eq!(expanded_key, expected);
+
+        // Encrypted bytes
+        let (cks, context) = FheAesContext::generate(ParameterSet::Default, 0).unwrap();
+
+        context.install(|| {
+            let last_round_key_fhe: [FheUint8; 16] =
+                std::array::from_fn(|i| FheUint8::encrypt(last_round_key[i], &cks));
+            let mut expanded_key_fhe: [FheUint8; 176] =
+                std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+            inv_key_expansion_fhe(&last_round_key_fhe, &mut expanded_key_fhe).unwrap();
+
+            verify_key_schedule(&expanded_key_fhe, &key, &cks).unwrap();
+        });
+    }
 }
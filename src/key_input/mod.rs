@@ -0,0 +1,155 @@
+/// This module reads keys and IVs from the sources accepted on the command line.
+/// Passing a key inline leaks it into the shell history and the process list, so key material
+/// can also be read from a file, an environment variable or the terminal.
+/// It includes the following items:
+/// - `read_bytes`: Reads bytes of any length from a source.
+/// - `read_block`: Reads exactly 16 bytes (an AES key or IV) from a source.
+///
+/// A source is one of:
+/// - `<hex>` or `hex:<hex>`: Hexadecimal digits, optionally separated by whitespace, colons or dashes.
+/// - `base64:<data>`: Standard base64 with padding.
+/// - `file:<path>`: The raw bytes of a file, e.g. a 16-byte binary key file.
+/// - `env:<VAR>`: The value of an environment variable, in hexadecimal or `base64:` form.
+/// - `prompt`: A value typed on the terminal without echo, in hexadecimal or `base64:` form.
+///
+/// The bytes are returned in `Zeroizing` buffers, so they are wiped from memory as soon as they
+/// are dropped, e.g. once they have been encrypted into `FheUint8`s.
+use crate::error::{check_len, Error};
+use crate::utils::hex_to_bytes;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use zeroize::Zeroizing;
+
+/// Parses a textual value in hexadecimal (the default) or base64 form.
+fn parse_text(text: &str) -> Result<Zeroizing<Vec<u8>>, Error> {
+    let text = text.trim();
+
+    match text.strip_prefix("base64:") {
+        Some(data) => STANDARD
+            .decode(data)
+            .map(Zeroizing::new)
+            .map_err(|e| Error::InvalidInput(format!("invalid base64: {}", e))),
+        None => hex_to_bytes(text.strip_prefix("hex:").unwrap_or(text)).map(Zeroizing::new),
+    }
+}
+
+/// Reads bytes from `source`.
+///
+/// # Arguments
+/// * `source` - The source, as described in the module documentation.
+/// * `what` - The name of the value, e.g. "key", shown in the terminal prompt.
+///
+/// # Returns
+/// * `Zeroizing<Vec<u8>>` - The bytes, wiped from memory when dropped.
+///
+/// # Errors
+/// This function will return an error if the file, environment variable or terminal cannot be
+/// read, or if the value is not valid hexadecimal or base64.
+pub fn read_bytes(source: &str, what: &str) -> Result<Zeroizing<Vec<u8>>, Error> {
+    if let Some(path) = source.strip_prefix("file:") {
+        return std::fs::read(path)
+            .map(Zeroizing::new)
+            .map_err(|e| Error::Io(format!("cannot read {} from {}", what, path), e));
+    }
+
+    if let Some(name) = source.strip_prefix("env:") {
+        let value = Zeroizing::new(std::env::var(name).map_err(|_| {
+            Error::InvalidInput(format!("environment variable {} is not set", name))
+        })?);
+        return parse_text(&value);
+    }
+
+    if source == "prompt" {
+        let value = Zeroizing::new(
+            rpassword::prompt_password(format!("Enter the {}: ", what))
+                .map_err(|e| Error::Io(format!("cannot read {} from the terminal", what), e))?,
+        );
+        return parse_text(&value);
+    }
+
+    parse_text(source)
+}
+
+/// Reads exactly 16 bytes, an AES-128 key or a counter block, from `source`.
+///
+/// # Errors
+/// This function will return the errors of `read_bytes`, or an error if the source does not hold
+/// 16 bytes.
+pub fn read_block(source: &str, what: &str) -> Result<Zeroizing<[u8; 16]>, Error> {
+    let bytes = read_bytes(source, what)?;
+    check_len(what, 16, bytes.len())?;
+
+    Ok(Zeroizing::new(std::array::from_fn(|i| bytes[i])))
+}
+
+#[cfg(test)]
+/// This module contains tests for the key and IV sources.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- key_input::tests::key_sources --exact --show-output
+/// ```
+mod tests {
+    use super::*;
+    use crate::utils::TestDir;
+
+    #[test]
+    fn key_sources() {
+        let key: [u8; 16] = std::array::from_fn(|i| i as u8);
+
+        // Hexadecimal, with and without separators
+        for source in [
+            "000102030405060708090a0b0c0d0e0f",
+            "hex:000102030405060708090A0B0C0D0E0F",
+            "00:01:02:03:04:05:06:07:08:09:0a:0b:0c:0d:0e:0f",
+            "0001 0203 0405 0607-0809 0a0b 0c0d 0e0f",
+        ] {
+            assert_eq!(*read_block(source, "key").unwrap(), key);
+        }
+
+        // Base64
+        assert_eq!(
+            *read_block("base64:AAECAwQFBgcICQoLDA0ODw==", "key").unwrap(),
+            key
+        );
+
+        // Raw binary file
+        let dir = TestDir::new("key_input");
+        let path = dir.join("raw_key.bin");
+        std::fs::write(&path, key).unwrap();
+        assert_eq!(
+            *read_block(&format!("file:{}", path.display()), "key").unwrap(),
+            key
+        );
+
+        // Environment variable
+        std::env::set_var("FHE_AES128_TEST_KEY", "base64:AAECAwQFBgcICQoLDA0ODw==");
+        assert_eq!(*read_block("env:FHE_AES128_TEST_KEY", "key").unwrap(), key);
+
+        // Errors
+        assert!(matches!(
+            read_block("env:FHE_AES128_UNSET_KEY", "key"),
+            Err(Error::InvalidInput(_))
+        ));
+        assert!(matches!(
+            read_block("base64:not base64", "key"),
+            Err(Error::InvalidInput(_))
+        ));
+        assert!(matches!(
+            read_block("00010203", "key"),
+            Err(Error::ParameterMismatch(_))
+        ));
+
+        // A typo is reported by its position, without the rest of the key
+        match read_block("000102030405060708090a0b0c0d0e0g", "key") {
+            Err(Error::InvalidInput(message)) => {
+                assert!(message.contains("position 31"));
+                assert!(!message.contains("0001020304"));
+            }
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+/// This module implements the Kreyvium stream cipher using Fully Homomorphic Encryption (FHE).
+/// Kreyvium is a variant of Trivium with a 128-bit key and IV, designed for transciphering. It
+/// reuses the Trivium registers and adds two rotating registers holding the key (K*) and the IV (IV*).
+/// It includes the following items:
+/// - `KreyviumStream`: The keystream generator, generic over `StreamBit` like `TriviumStream`.
+use crate::error::{check_len, Error};
+use crate::transciphering::KeystreamGenerator;
+use crate::trivium::{load_bits, Registers, StreamBit, BATCH, WARM_UP};
+use rayon::prelude::*;
+use tfhe::{FheBool, FheUint8};
+
+/// The Kreyvium keystream generator over clear or encrypted bits.
+pub struct KreyviumStream<T: StreamBit> {
+    registers: Registers<T>,
+    /// The key register K*, where `key_register[j]` is the key bit injected at clock cycle j.
+    key_register: Vec<T>,
+    /// The IV register IV*, where `iv_register[j]` is the IV bit injected at clock cycle j.
+    iv_register: Vec<T>,
+    /// Number of clock cycles run so far, i.e. how far K* and IV* have rotated.
+    cycles: usize,
+    /// Output bits produced but not yet returned as bytes.
+    buffer: Vec<T>,
+}
+
+impl<T: StreamBit> KreyviumStream<T> {
+    /// Initializes Kreyvium with a 128-bit key and a 128-bit IV and runs the 1152 warm-up cycles.
+    ///
+    /// # Arguments
+    /// * `key` - The 16 key bytes, usually encrypted.
+    /// * `iv` - The 16 IV bytes, usually public and trivially encrypted.
+    ///
+    /// # Behavior
+    /// - Key and IV bits are loaded in the same order as for Trivium (see `load_bits`).
+    /// - The first register holds K_0..K_92, the second IV_0..IV_83, and the third IV_84..IV_127
+    ///   followed by ones and a final zero.
+    /// - K* and IV* hold the key and IV bits in the order they were loaded, so clock cycle t
+    ///   injects K_(t mod 128) and IV_(t mod 128), which reproduces the published test vectors.
+    ///
+    /// # Errors
+    /// This function will return an error if the key or the IV is not 16 bytes long.
+    pub fn new(key: &[T::Byte], iv: &[T::Byte]) -> Result<Self, Error> {
+        check_len("Kreyvium key", 16, key.len())?;
+        check_len("Kreyvium IV", 16, iv.len())?;
+
+        let key_bits = load_bits::<T>(key);
+        let iv_bits = load_bits::<T>(iv);
+
+        let mut c = iv_bits[84..].to_vec();
+        c.resize(110, T::constant(true));
+        c.push(T::constant(false));
+
+        let registers = Registers::new(key_bits[..93].to_vec(), iv_bits[..84].to_vec(), c);
+
+        let mut stream = Self {
+            registers,
+            key_register: key_bits,
+            iv_register: iv_bits,
+            cycles: 0,
+            buffer: vec![],
+        };
+        for _ in 0..WARM_UP / BATCH {
+            stream.clock_64();
+        }
+
+        Ok(stream)
+    }
+
+    /// Clocks the cipher 64 times, injecting the next bits of K* and IV* as they rotate.
+    fn clock_64(&mut self) -> Vec<T> {
+        let (key_register, iv_register, cycles) =
+            (&self.key_register, &self.iv_register, self.cycles);
+
+        let bits = self.registers.clock_64(|j| {
+            let index = (cycles + j) % 128;
+            (
+                Some(key_register[index].clone()),
+                Some(iv_register[index].clone()),
+            )
+        });
+        self.cycles += BATCH;
+
+        bits
+    }
+
+    /// Returns the next `len` keystream bytes.
+    pub fn next_bytes(&mut self, len: usize) -> Vec<T::Byte> {
+        while self.buffer.len() < len * 8 {
+            let bits = self.clock_64();
+            self.buffer.extend(bits);
+        }
+
+        let bits: Vec<T> = self.buffer.drain(..len * 8).collect();
+        bits.par_chunks(8).map(T::pack_byte).collect()
+    }
+}
+
+impl KeystreamGenerator for KreyviumStream<FheBool> {
+    fn next_keystream(&mut self, len: usize) -> Result<Vec<FheUint8>, Error> {
+        Ok(self.next_bytes(len))
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the Kreyvium stream cipher.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following commands:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- kreyvium::tests::kreyvium_clear --exact --show-output
+/// cargo test --release --package fhe-aes128 --lib -- kreyvium::tests::kreyvium_fhe --exact --show-output
+/// ```
+mod tests {
+    use rand::Rng;
+    use tfhe::prelude::*;
+
+    use super::*;
+    use crate::context::FheAesContext;
+    use crate::params::ParameterSet;
+    use crate::utils::hex_to_bytes;
+
+    #[test]
+    fn kreyvium_clear() {
+        // Key and IV set to zero
+        let mut kreyvium = KreyviumStream::<bool>::new(&[0u8; 16], &[0u8; 16]).unwrap();
+        assert_eq!(
+            kreyvium.next_bytes(8),
+            [0x26, 0xDC, 0xF1, 0xF4, 0xBC, 0x0F, 0x19, 0x22]
+        );
+
+        // Vectors of the tfhe-rs Kreyvium implementation, which set a single key or IV bit or
+        // reuse the key and IV of eSTREAM Trivium Set 6, vector 0
+        let vectors = [
+            (
+                "01000000000000000000000000000000",
+                "00000000000000000000000000000000",
+                "4FD421D4DA3D2C8A",
+            ),
+            (
+                "00000000000000000000000000000000",
+                "01000000000000000000000000000000",
+                "C9217BA0D762ACA1",
+            ),
+            (
+                "0053A6F94C9FF24598EB000000000000",
+                "0D74DB42A91077DE45AC000000000000",
+                "D1F0303482061111",
+            ),
+        ];
+        for (key, iv, expected) in vectors {
+            let key = hex_to_bytes(key).unwrap();
+            let iv = hex_to_bytes(iv).unwrap();
+            let mut kreyvium = KreyviumStream::<bool>::new(&key, &iv).unwrap();
+            assert_eq!(kreyvium.next_bytes(8), hex_to_bytes(expected).unwrap());
+        }
+    }
+
+    #[test]
+    fn kreyvium_fhe() {
+        let mut rng = rand::thread_rng();
+        let key: [u8; 16] = rng.gen();
+        let iv: [u8; 16] = rng.gen();
+
+        let (cks, context) = FheAesContext::generate(ParameterSet::Default, 0).unwrap();
+
+        context.install(|| {
+            let key_fhe: Vec<FheUint8> = key.iter().map(|x| FheUint8::encrypt(*x, &cks)).collect();
+            let iv_fhe: Vec<FheUint8> = iv.iter().map(|x| FheUint8::encrypt_trivial(*x)).collect();
+
+            let mut expected = KreyviumStream::<bool>::new(&key, &iv).unwrap();
+            let mut kreyvium = KreyviumStream::<FheBool>::new(&key_fhe, &iv_fhe).unwrap();
+
+            let result: Vec<u8> = kreyvium
+                .next_keystream(16)
+                .unwrap()
+                .iter()
+                .map(|x| x.decrypt(&cks))
+                .collect();
+            assert_eq!(result, expected.next_bytes(16));
+        });
+    }
+}
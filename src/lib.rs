@@ -0,0 +1,360 @@
+/*!
+ * # FHE AES-128 library
+ *
+ * The homomorphic AES-128 circuit and the modules built on it, shared by the `fhe-aes128`
+ * command-line program and the benchmarks in `benches/`.
+ *
+ * - `context`: `FheAesContext`, a server key with a thread pool whose workers all hold it, in
+ *   which every homomorphic computation runs.
+ * - `key_expansion`, `encryption` and `decryption`: The AES round functions, generic over the
+ *   byte backends of the `backend` module.
+ * - `aes_encrypt_block` / `aes_decrypt_block`: One AES-128 block encryption or decryption.
+ * - `round_keys`: Cached or on-the-fly key schedules, used by `aes_encrypt_block_with_schedule`
+ *   and `aes_decrypt_block_with_schedule`.
+ * - `aes_encrypt_block_checkpointed` / `aes_encrypt_block_from_round` (and their decryption
+ *   counterparts): The AES state after every round, and the resumption from a round.
+ * - `checkpoint`: Round checkpoints of the encrypted state on disk, for preemptible machines.
+ * - `transciphering`, `trivium`, `kreyvium` and `chacha20`: Transciphering with stream ciphers.
+ * - `prf`, `hash` and `drbg`: Constructions on top of the FHE block cipher.
+ * - `artifacts`, `commands`, `key_input`, `params` and `report`: The pipeline subcommands and
+ *   their inputs and outputs.
+ * - `noise` and `cost`: Noise and cost diagnostics of the circuit.
+ * - `cavp`: The NIST AESAVS test harness.
+ * - `registry`: Encrypted AES keys of many clients, each with their own server key.
+ * - `daemon`: A long-lived transciphering server on a Unix socket or localhost TCP.
+ * - `jobs`: Resumable encryption and transciphering jobs, persisted block by block.
+ * - `search`: Encrypted equality and substring search over transciphered data.
+ */
+
+pub mod artifacts;
+pub mod backend;
+pub mod cavp;
+pub mod chacha20;
+pub mod checkpoint;
+pub mod commands;
+pub mod context;
+pub mod cost;
+pub mod daemon;
+pub mod decryption;
+pub mod drbg;
+pub mod encryption;
+pub mod error;
+pub mod hash;
+pub mod jobs;
+pub mod key_expansion;
+pub mod key_input;
+pub mod kreyvium;
+pub mod noise;
+pub mod params;
+pub mod prf;
+pub mod registry;
+pub mod report;
+pub mod round_keys;
+pub mod search;
+pub mod transciphering;
+pub mod trivium;
+pub mod utils;
+
+use backend::AesByte;
+use decryption::{inv_mix_columns, inv_shift_rows, inv_sub_bytes};
+use encryption::{add_blocks, mix_columns, shift_rows, sub_bytes};
+use error::{check_len, Error};
+use round_keys::KeySchedule;
+use utils::SBOX;
+
+/// Encrypts a single block of data using AES encryption with Fully Homomorphic Encryption (FHE).
+///
+/// # Arguments
+///
+/// * `input` - A slice of `FheUint8` representing the plaintext input block.
+/// * `output` - A mutable reference to an array of `FheUint8` where the encrypted output block will be stored.
+/// * `expanded_key` - A slice of `FheUint<FheUint8Id>` representing the expanded AES key.
+///
+/// # Description
+///
+/// This function performs the AES encryption process on a single block of data. It includes
+/// the initial round key addition, followed by 9 rounds of sub bytes, shift rows, mix columns,
+/// and adding the round keys, and a final round without mix columns. It is generic over the
+/// byte backend, so the same circuit also runs on clear or counted bytes.
+///
+/// # Errors
+///
+/// This function will return an error if the input is not 16 bytes long or if a homomorphic S-Box
+/// lookup fails.
+pub fn aes_encrypt_block<B: AesByte>(
+    input: &[B],
+    output: &mut [B; 16],
+    expanded_key: &[B; 176],
+) -> Result<(), Error> {
+    check_len("AES block", 16, input.len())?;
+
+    encrypt_rounds(
+        input.to_vec(),
+        0,
+        output,
+        expanded_key.chunks(16).map(Ok),
+        |_, _| Ok(()),
+    )
+}
+
+/// Encrypts a single block like `aes_encrypt_block`, with the round keys of a cached or on-the-fly
+/// key schedule.
+///
+/// # Errors
+///
+/// This function will return an error if the input is not 16 bytes long, or if a homomorphic S-Box
+/// lookup fails, in the block or in the generation of an on-the-fly round key.
+pub fn aes_encrypt_block_with_schedule<B: AesByte>(
+    input: &[B],
+    output: &mut [B; 16],
+    schedule: &KeySchedule<B>,
+) -> Result<(), Error> {
+    check_len("AES block", 16, input.len())?;
+
+    encrypt_rounds(
+        input.to_vec(),
+        0,
+        output,
+        schedule.forward()?,
+        |_, _| Ok(()),
+    )
+}
+
+/// Returns the next round key of `round_keys`.
+///
+/// # Errors
+///
+/// This function will return an error if generating the round key fails, or if there are fewer
+/// than 11 round keys.
+fn next_key<K>(round_keys: &mut impl Iterator<Item = Result<K, Error>>) -> Result<K, Error> {
+    round_keys.next().unwrap_or_else(|| {
+        Err(Error::InvalidInput(
+            "the key schedule has fewer than 11 round keys".to_string(),
+        ))
+    })
+}
+
+/// Encrypts a single block like `aes_encrypt_block`, handing the encrypted state to `on_round`
+/// after every round, e.g. to checkpoint it to disk.
+///
+/// # Arguments
+///
+/// * `on_round` - Called with the round number, from 0 (the initial round key addition) to 10,
+///   and the state after that round. An error stops the encryption.
+///
+/// # Errors
+///
+/// This function will return an error if the input is not 16 bytes long, if a homomorphic S-Box
+/// lookup fails, or the error of `on_round`.
+pub fn aes_encrypt_block_checkpointed<B: AesByte>(
+    input: &[B],
+    output: &mut [B; 16],
+    expanded_key: &[B; 176],
+    on_round: impl FnMut(usize, &[B]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    check_len("AES block", 16, input.len())?;
+
+    encrypt_rounds(
+        input.to_vec(),
+        0,
+        output,
+        expanded_key.chunks(16).map(Ok),
+        on_round,
+    )
+}
+
+/// Resumes an encryption from the state after round `round`, as handed to the `on_round` of
+/// `aes_encrypt_block_checkpointed`, with the same expanded key.
+///
+/// # Arguments
+///
+/// * `state` - The encrypted state after round `round`.
+/// * `round` - The last completed round, from 0 to 10.
+/// * `on_round` - Called with the state after each of the remaining rounds.
+///
+/// # Errors
+///
+/// This function will return an error if the round is above 10 or the state is not 16 bytes long,
+/// if a homomorphic S-Box lookup fails, or the error of `on_round`.
+pub fn aes_encrypt_block_from_round<B: AesByte>(
+    state: &[B],
+    round: usize,
+    output: &mut [B; 16],
+    expanded_key: &[B; 176],
+    on_round: impl FnMut(usize, &[B]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    check_round(state, round)?;
+
+    let round_keys = expanded_key.chunks(16).skip(round + 1).map(Ok);
+    encrypt_rounds(state.to_vec(), round + 1, output, round_keys, on_round)
+}
+
+/// Returns an error if `round` is not a completed round of a 16-byte AES state.
+fn check_round<B>(state: &[B], round: usize) -> Result<(), Error> {
+    if round > 10 {
+        return Err(Error::InvalidInput(format!(
+            "AES-128 has rounds 0 to 10, got round {}",
+            round
+        )));
+    }
+
+    check_len("AES state", 16, state.len())
+}
+
+/// The AES encryption rounds from round `start` to round 10, on the state after round `start - 1`
+/// (the input block when `start` is 0), with the round keys in order from round key `start` to
+/// round key 10. `on_round` is called with the state after every round.
+pub(crate) fn encrypt_rounds<B: AesByte, K: AsRef<[B]>>(
+    mut state: Vec<B>,
+    start: usize,
+    output: &mut [B; 16],
+    mut round_keys: impl Iterator<Item = Result<K, Error>>,
+    mut on_round: impl FnMut(usize, &[B]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    // Round 0 is the initial round key addition, rounds 1 to 9 the full rounds, and round 10 the
+    // final round (without mix columns)
+    for round in start..=10 {
+        let round_key = next_key(&mut round_keys)?;
+
+        if round > 0 {
+            sub_bytes(&mut state)?; // Sub bytes
+            shift_rows(&mut state); // Shift rows
+            if round < 10 {
+                mix_columns(&mut state); // Mix columns
+            }
+        }
+        add_blocks(&mut state, round_key.as_ref()); // Add round key
+
+        on_round(round, &state)?;
+    }
+
+    // Copy the encrypted state to the output
+    output.clone_from_slice(&state);
+
+    Ok(())
+}
+
+/// Decrypts a single block of data using AES decryption with Fully Homomorphic Encryption (FHE).
+///
+/// # Arguments
+///
+/// * `input` - A slice of `FheUint8` representing the encrypted input block.
+/// * `output` - A mutable reference to an array of `FheUint8` where the decrypted output block will be stored.
+/// * `expanded_key` - A slice of `FheUint<FheUint8Id>` representing the expanded AES key.
+///
+/// # Description
+///
+/// This function performs the AES decryption process on a single block of data. It uses the inverse
+/// operations of the AES encryption process, including inverse shift rows, inverse sub bytes, and
+/// inverse mix columns, along with adding the round keys in reverse order.
+///
+/// # Errors
+///
+/// This function will return an error if the input is not 16 bytes long or if a homomorphic S-Box
+/// lookup fails.
+pub fn aes_decrypt_block<B: AesByte>(
+    input: &[B],
+    output: &mut [B; 16],
+    expanded_key: &[B; 176],
+) -> Result<(), Error> {
+    check_len("AES block", 16, input.len())?;
+
+    let round_keys = expanded_key.chunks(16).rev().map(Ok);
+    decrypt_rounds(input.to_vec(), 0, output, round_keys, |_, _| Ok(()))
+}
+
+/// Decrypts a single block like `aes_decrypt_block`, with the round keys of a cached or on-the-fly
+/// key schedule.
+///
+/// # Errors
+///
+/// This function will return an error if the input is not 16 bytes long, or if a homomorphic S-Box
+/// lookup fails, in the block or in the generation of an on-the-fly round key.
+pub fn aes_decrypt_block_with_schedule<B: AesByte>(
+    input: &[B],
+    output: &mut [B; 16],
+    schedule: &KeySchedule<B>,
+) -> Result<(), Error> {
+    check_len("AES block", 16, input.len())?;
+
+    decrypt_rounds(input.to_vec(), 0, output, schedule.backward()?, |_, _| {
+        Ok(())
+    })
+}
+
+/// Decrypts a single block like `aes_decrypt_block`, handing the state to `on_round` after every
+/// round, e.g. to checkpoint it to disk.
+///
+/// # Arguments
+///
+/// * `on_round` - Called with the round number, from 0 (the addition of round key 10) to 10 (the
+///   addition of round key 0), and the state after that round. An error stops the decryption.
+///
+/// # Errors
+///
+/// This function will return an error if the input is not 16 bytes long, if a homomorphic S-Box
+/// lookup fails, or the error of `on_round`.
+pub fn aes_decrypt_block_checkpointed<B: AesByte>(
+    input: &[B],
+    output: &mut [B; 16],
+    expanded_key: &[B; 176],
+    on_round: impl FnMut(usize, &[B]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    check_len("AES block", 16, input.len())?;
+
+    let round_keys = expanded_key.chunks(16).rev().map(Ok);
+    decrypt_rounds(input.to_vec(), 0, output, round_keys, on_round)
+}
+
+/// Resumes a decryption from the state after round `round`, as handed to the `on_round` of
+/// `aes_decrypt_block_checkpointed`, with the same expanded key.
+///
+/// # Errors
+///
+/// This function will return an error if the round is above 10 or the state is not 16 bytes long,
+/// if a homomorphic S-Box lookup fails, or the error of `on_round`.
+pub fn aes_decrypt_block_from_round<B: AesByte>(
+    state: &[B],
+    round: usize,
+    output: &mut [B; 16],
+    expanded_key: &[B; 176],
+    on_round: impl FnMut(usize, &[B]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    check_round(state, round)?;
+
+    let round_keys = expanded_key.chunks(16).rev().skip(round + 1).map(Ok);
+    decrypt_rounds(state.to_vec(), round + 1, output, round_keys, on_round)
+}
+
+/// The AES decryption rounds from round `start` to round 10, on the state after round `start - 1`
+/// (the input block when `start` is 0), with the round keys in reverse order, from round key
+/// `10 - start` to round key 0. `on_round` is called with the state after every round.
+pub(crate) fn decrypt_rounds<B: AesByte, K: AsRef<[B]>>(
+    mut state: Vec<B>,
+    start: usize,
+    output: &mut [B; 16],
+    mut round_keys: impl Iterator<Item = Result<K, Error>>,
+    mut on_round: impl FnMut(usize, &[B]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    // Round 0 is the initial round key addition, rounds 1 to 9 the full rounds, and round 10 the
+    // final round (without inverse mix columns)
+    for round in start..=10 {
+        let round_key = next_key(&mut round_keys)?;
+
+        if round > 0 {
+            inv_shift_rows(&mut state); // Inverse shift rows
+            inv_sub_bytes(&mut state)?; // Inverse sub bytes
+        }
+        add_blocks(&mut state, round_key.as_ref()); // Add round key
+        if round > 0 && round < 10 {
+            inv_mix_columns(&mut state); // Inverse mix columns
+        }
+
+        on_round(round, &state)?;
+    }
+
+    // Copy the decrypted state to the output
+    output.clone_from_slice(&state);
+
+    Ok(())
+}
@@ -11,24 +11,54 @@
  * - Key expansion using FHE operations
  * - Performance measurement for encryption and decryption
  * - Command-line interface for specifying encryption key, IV, and output count
+ * - AES-based PRF for deterministic tokenization of encrypted identifiers
+ * - NIST SP 800-90A CTR_DRBG with an encrypted internal state
+ * - Matyas–Meyer–Oseas and Davies–Meyer hash constructions over FHE AES
+ * - Trivium and Kreyvium transciphering, selectable with `--cipher` for throughput comparisons
+ * - ChaCha20 (RFC 8439) transciphering on `FheUint32` additions, rotations and XORs
+ * - Subcommands for each pipeline stage, exchanging serialized keys and ciphertexts
+ * - Invalid inputs and failed operations reported as errors with distinct exit codes
+ * - Keys and IVs read from hex, base64, raw files, environment variables or a terminal prompt
+ * - Selectable TFHE parameter sets (`--params`), recorded in and checked across artifacts
+ * - Noise margin diagnostics of every AES round transformation (`--noise`)
+ * - Round functions generic over a byte backend, with a clear backend that counts operations
+ * - A cost model of the bootstraps, keyswitches and runtime of every AES stage (`cost`)
+ * - A NIST AESAVS known-answer and Monte Carlo harness, on clear or encrypted bytes (`cavp`)
+ * - Inverse key expansion, rebuilding the key schedule from the encrypted last round key
+ * - Cached or on-the-fly key schedules, chosen per key (`expand --schedule`)
+ * - A registry of many clients' encrypted AES keys, each client with its own server key
+ * - Execution contexts owning a thread pool whose workers all hold the server key
+ * - A transciphering daemon on a Unix socket or localhost TCP (`serve`, `client`)
+ * - Resumable jobs persisted block by block, with progress and cancellation (`job`)
+ * - Round-level checkpoints of the encrypted AES state, resumable from any round
+ * - Encrypted equality and substring search of transciphered data (`search`)
  *
  * ## Dependencies
  * - `tfhe` for Fully Homomorphic Encryption operations
  * - `aes` for standard AES encryption (used for verification)
  * - `clap` for command-line argument parsing
  * - `rayon` for parallel computation distribution
+ * - `bincode` and `serde` for serializing keys and ciphertexts between subcommands
+ * - `base64`, `rpassword` and `zeroize` for reading key material and wiping it after use
  * - `rand` for random number generation in tests
+ * - `criterion` for the benchmarks in `benches/`, which use the library target (`src/lib.rs`)
  *
  * ## Usage
- * The program encrypts a specified number of blocks using AES-128 in an FHE environment
+ * The `run` subcommand encrypts a specified number of blocks using AES-128 in an FHE environment
  * and then decrypts them to verify correctness. The execution time for both encryption
  * and decryption is measured.
  *
  * Example command:
  * ```
- * cargo run --release -- -n 3 -k 000102030405060708090a0b0c0d0e0f -i 00112233445566778899aabbccddeeff
+ * cargo run --release -- run -n 3 -k 000102030405060708090a0b0c0d0e0f -i 00112233445566778899aabbccddeeff
  * ```
- * This encrypts and decrypts one block using the specified key and IV.
+ * This encrypts and decrypts three blocks using the specified key and IV.
+ *
+ * The `keygen`, `encrypt-key`, `expand`, `encrypt`, `decrypt`, `transcipher` and `verify`
+ * subcommands run one stage each and read and write their keys and ciphertexts as files, so the
+ * client-side and server-side stages can run on different machines.
+ *
+ * The `cost` subcommand estimates the bootstraps and the runtime of every AES stage without keys.
  *
  * ## Testing
  * The implementation includes unit tests for:
@@ -42,146 +72,258 @@
  * ```
  */
 
-#![allow(unused)]
-mod decryption;
-mod encryption;
-mod key_expansion;
-pub mod utils;
-
-use std::time::Instant;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 use aes::cipher::{BlockEncrypt, KeyInit};
 use aes::Aes128;
-use clap::Parser;
-use decryption::{inv_mix_columns, inv_shift_rows, inv_sub_bytes};
-use encryption::*;
-use key_expansion::*;
+use clap::{Parser, Subcommand};
+use fhe_aes128::artifacts::create_output;
+use fhe_aes128::chacha20::{chacha20_counter, ChaCha20Stream};
+use fhe_aes128::context::FheAesContext;
+use fhe_aes128::error::{check_len, Error};
+use fhe_aes128::key_expansion::*;
+use fhe_aes128::key_input::{read_block, read_bytes};
+use fhe_aes128::kreyvium::KreyviumStream;
+use fhe_aes128::noise::{diagnose_encrypt_block, worst_per_round, NoiseProbe};
+use fhe_aes128::params::ParameterSet;
+use fhe_aes128::report::{millis, BlockReport, ReportFormat, RunReport};
+use fhe_aes128::transciphering::{transcipher, Cipher, KeystreamGenerator};
+use fhe_aes128::trivium::TriviumStream;
+use fhe_aes128::utils::increment_counter;
+use fhe_aes128::{aes_decrypt_block, aes_encrypt_block, commands};
 use tfhe::prelude::*;
-use tfhe::{
-    generate_keys, set_server_key, ConfigBuilder, FheUint, FheUint8, FheUint8Id, MatchValues,
-};
-use utils::{hex_to_u8_array, increment_counter, SBOX};
-
-fn get_match_values() -> MatchValues<u8> {
-    let match_vector = (0u8..=255u8).map(|x| (x, SBOX[x as usize])).collect();
-
-    MatchValues::new(match_vector).unwrap()
-}
+use tfhe::{ClientKey, FheBool, FheUint, FheUint32, FheUint8, FheUint8Id};
+use zeroize::Zeroizing;
 
-/// Encrypts a single block of data using AES encryption with Fully Homomorphic Encryption (FHE).
+/// Transciphers `data` with a stream cipher and verifies the result.
 ///
 /// # Arguments
 ///
-/// * `input` - A vector of `FheUint8` representing the plaintext input block.
-/// * `output` - A mutable reference to an array of `FheUint8` where the encrypted output block will be stored.
-/// * `expanded_key` - A slice of `FheUint<FheUint8Id>` representing the expanded AES key.
+/// * `cipher` - The stream cipher to use (Trivium, Kreyvium or ChaCha20).
+/// * `key_fhe` - The encrypted key bytes.
+/// * `key` - The clear key bytes, used to produce the symmetric ciphertext on the client side.
+/// * `iv` - The public IV bytes. For ChaCha20, the first 4 bytes are the little-endian initial
+///   block counter and the last 12 bytes are the nonce.
+/// * `data` - The plaintext to encrypt with the clear cipher and then transcipher.
+/// * `cks` - The client key, used to decrypt and verify the transciphered bytes.
 ///
-/// # Description
+/// # Returns
 ///
-/// This function performs the AES encryption process on a single block of data. It includes
-/// the initial round key addition, followed by 9 rounds of sub bytes, shift rows, mix columns,
-/// and adding the round keys, and a final round without mix columns.
-fn aes_encrypt_block(
-    input: &Vec<FheUint8>,
-    output: &mut [FheUint8; 16],
-    expanded_key: &[FheUint<FheUint8Id>; 176],
-) {
-    let mut state = input.clone();
-
-    // Initial round key addition
-    add_blocks(&mut state, &expanded_key[0..16]);
-
-    // Perform 9 rounds of encryption
-    for round in 1..10 {
-        sub_bytes(&mut state); // Sub bytes
-        shift_rows(&mut state); // Shift rows
-        mix_columns(&mut state); // Mix columns
-        add_blocks(&mut state, &expanded_key[round * 16..(round + 1) * 16]); // Add round key
-    }
-
-    // Final round (without mix columns)
-    sub_bytes(&mut state);
-    shift_rows(&mut state);
-    add_blocks(&mut state, &expanded_key[160..176]); // Add final round key
-
-    // Copy the encrypted state to the output
-    output.clone_from_slice(&state);
-}
-
-/// Decrypts a single block of data using AES decryption with Fully Homomorphic Encryption (FHE).
+/// * `Vec<BlockReport>` - The transciphering time and verification result of each 16-byte block.
 ///
-/// # Arguments
+/// # Errors
 ///
-/// * `input` - A vector of `FheUint8` representing the encrypted input block.
-/// * `output` - A mutable reference to an array of `FheUint8` where the decrypted output block will be stored.
-/// * `expanded_key` - A slice of `FheUint<FheUint8Id>` representing the expanded AES key.
+/// This function will return an error if the key or IV has the wrong length for `cipher`, or if
+/// transciphering fails.
 ///
 /// # Description
 ///
-/// This function performs the AES decryption process on a single block of data. It uses the inverse
-/// operations of the AES encryption process, including inverse shift rows, inverse sub bytes, and
-/// inverse mix columns, along with adding the round keys in reverse order.
-fn aes_decrypt_block(
-    input: &Vec<FheUint8>,
-    output: &mut [FheUint8; 16],
-    expanded_key: &[FheUint<FheUint8Id>; 176],
-) {
-    let mut state = input.clone();
-
-    // Initial round key addition
-    add_blocks(&mut state, &expanded_key[160..176]);
-
-    // Perform 9 rounds of decryption
-    for round in (1..10).rev() {
-        inv_shift_rows(&mut state); // Inverse shift rows
-        inv_sub_bytes(&mut state); // Inverse sub bytes
-        add_blocks(&mut state, &expanded_key[round * 16..(round + 1) * 16]); // Add round key
-        inv_mix_columns(&mut state); // Inverse mix columns
-    }
-
-    // Final round (without inverse mix columns)
-    inv_shift_rows(&mut state);
-    inv_sub_bytes(&mut state);
-    add_blocks(&mut state, &expanded_key[0..16]); // Add initial round key
-
-    // Copy the decrypted state to the output
-    output.clone_from_slice(&state);
+/// Only the transciphering (keystream warm-up, keystream generation and XOR) is included in
+/// the measured time, like the encryption-only timing of the AES path. The warm-up happens when
+/// the generator is created and is counted in the time of the first block.
+fn run_stream_cipher(
+    cipher: Cipher,
+    key_fhe: &[FheUint8],
+    key: &[u8],
+    iv: &[u8; 16],
+    data: &[u8],
+    cks: &ClientKey,
+) -> Result<Vec<BlockReport>, Error> {
+    // Client side: encrypt the data with the clear cipher
+    let keystream = match cipher {
+        Cipher::Trivium => {
+            TriviumStream::<bool>::new(&key[..10], &iv[..10])?.next_bytes(data.len())
+        }
+        Cipher::Kreyvium => KreyviumStream::<bool>::new(key, iv)?.next_bytes(data.len()),
+        Cipher::Chacha20 => ChaCha20Stream::<u32>::new(key, chacha20_counter(iv), &iv[4..])?
+            .next_bytes(data.len())?,
+        Cipher::Aes => unreachable!("AES runs the block encryption flow"),
+    };
+    let ciphertext: Vec<u8> = keystream.iter().zip(data).map(|(k, d)| k ^ d).collect();
+
+    // The IV is public, so it is encrypted trivially
+    let iv_fhe: Vec<FheUint8> = iv.iter().map(|x| FheUint8::encrypt_trivial(*x)).collect();
+
+    let setup_time = Instant::now();
+
+    let mut generator: Box<dyn KeystreamGenerator> = match cipher {
+        Cipher::Trivium => Box::new(TriviumStream::<FheBool>::new(
+            &key_fhe[..10],
+            &iv_fhe[..10],
+        )?),
+        Cipher::Kreyvium => Box::new(KreyviumStream::<FheBool>::new(key_fhe, &iv_fhe)?),
+        Cipher::Chacha20 => Box::new(ChaCha20Stream::<FheUint32>::new(
+            key_fhe,
+            chacha20_counter(iv),
+            &iv_fhe[4..],
+        )?),
+        Cipher::Aes => unreachable!("AES runs the block encryption flow"),
+    };
+
+    let mut setup_duration = Some(setup_time.elapsed());
+
+    // Transciphering block by block, cross checking each block against the original data
+    ciphertext
+        .chunks(16)
+        .zip(data.chunks(16))
+        .enumerate()
+        .map(|(index, (block, expected))| {
+            let block_time = Instant::now();
+            let output = transcipher(&mut generator, block)?;
+            let block_duration = block_time.elapsed() + setup_duration.take().unwrap_or_default();
+
+            let result: Vec<u8> = output.iter().map(|x| x.decrypt(cks)).collect();
+
+            Ok(BlockReport {
+                index,
+                encryption_ms: millis(block_duration),
+                decryption_ms: None,
+                verified: result == expected,
+            })
+        })
+        .collect()
 }
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
-/// Struct representing the command line arguments for the application.
-struct Args {
+/// Struct representing the command line interface of the application.
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+/// The stages of the FHE AES pipeline, plus `run` which chains them in a single process.
+enum Command {
+    /// Generate a client key and a server key (client side).
+    Keygen(commands::KeygenArgs),
+    /// Encrypt a symmetric key with the client key (client side).
+    EncryptKey(commands::EncryptKeyArgs),
+    /// Expand an encrypted AES key into the encrypted key schedule (server side).
+    Expand(commands::ExpandArgs),
+    /// Encrypt counter blocks with FHE AES (server side).
+    Encrypt(commands::EncryptArgs),
+    /// Decrypt blocks with FHE AES, keeping the result encrypted (server side).
+    Decrypt(commands::DecryptArgs),
+    /// Transcipher a symmetric ciphertext into FHE ciphertexts (server side).
+    Transcipher(commands::TranscipherArgs),
+    /// Decrypt an encrypted artifact and compare it with the expected bytes (client side).
+    Verify(commands::VerifyArgs),
+    /// Run key generation, key expansion, encryption, decryption and verification in one shot.
+    Run(RunArgs),
+    /// Estimate the bootstraps and the runtime of each AES stage, without keys.
+    Cost(commands::CostArgs),
+    /// Check the AES circuit against the NIST AESAVS known-answer and Monte Carlo files.
+    Cavp(commands::CavpArgs),
+    /// Serve transciphering requests on a local socket, with the keys loaded once (server side).
+    Serve(commands::ServeArgs),
+    /// Send an AES-CTR ciphertext to a `serve` process for transciphering (client side).
+    Client(commands::ClientArgs),
+    /// Run, resume, inspect or cancel resumable block-level jobs (server side).
+    Job(commands::JobArgs),
+    /// Encrypt a search pattern with the client key (client side).
+    EncryptPattern(commands::EncryptPatternArgs),
+    /// Search AES-CTR encrypted data for an encrypted pattern, with an encrypted result (server side).
+    Search(commands::SearchArgs),
+}
+
+#[derive(clap::Args, Debug)]
+/// Struct representing the arguments of the one-shot `run` subcommand.
+struct RunArgs {
     /// The number of outputs to generate. Defaults to 1 if not specified.
-    #[arg(short, long, default_value_t = 1)]
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
     number_of_outputs: u32,
 
     /// The initialization vector (IV) for AES encryption. This is a required argument.
+    /// It is read from the same sources as the key.
     #[arg(short, long)]
     iv: String,
 
     /// The encryption key for AES encryption. This is a required argument.
+    /// ChaCha20 takes a 256-bit key (64 hexadecimal characters).
+    /// Accepts hexadecimal (separators allowed), `base64:<data>`, `file:<path>` (raw bytes),
+    /// `env:<VAR>` or `prompt`.
     #[arg(short, long)]
     key: String,
+
+    /// The cipher used to generate the keystream. Trivium uses the first 80 bits of the key and IV.
+    #[arg(short, long, value_enum, default_value_t = Cipher::Aes)]
+    cipher: Cipher,
+
+    /// The TFHE parameter set used to generate the keys.
+    #[arg(long, value_enum, default_value_t = ParameterSet::Default)]
+    params: ParameterSet,
+
+    /// Measure the noise margin of the AES state after every round transformation, in an extra
+    /// pass over the blocks that is not included in the timings. AES only.
+    #[arg(long)]
+    noise: bool,
+
+    /// Write a machine-readable report of the run in the given format.
+    #[arg(long, value_enum)]
+    report: Option<ReportFormat>,
+
+    /// Where to write the report, or `-` for stdout.
+    #[arg(long, default_value = "-")]
+    report_output: PathBuf,
 }
-// cargo run --release -- -n 1 -k 000102030405060708090a0b0c0d0e0f -i 00112233445566778899aabbccddeeff
+// cargo run --release -- run -n 1 -k 000102030405060708090a0b0c0d0e0f -i 00112233445566778899aabbccddeeff
 
 /// This program performs Fully Homomorphic Encryption (FHE) based AES encryption and decryption.
+/// Each subcommand runs one stage of the pipeline; `run` chains all of them in a single process.
+/// Errors are printed on stderr and the process exits with the code of the error kind.
+fn main() {
+    let result = match Cli::parse().command {
+        Command::Keygen(args) => commands::keygen(args),
+        Command::EncryptKey(args) => commands::encrypt_key(args),
+        Command::Expand(args) => commands::expand(args),
+        Command::Encrypt(args) => commands::encrypt(args),
+        Command::Decrypt(args) => commands::decrypt(args),
+        Command::Transcipher(args) => commands::transcipher_file(args),
+        Command::Verify(args) => commands::verify(args),
+        Command::Run(args) => run(args),
+        Command::Cost(args) => commands::cost(args),
+        Command::Cavp(args) => commands::cavp(args),
+        Command::Serve(args) => commands::serve(args),
+        Command::Client(args) => commands::client(args),
+        Command::Job(args) => commands::job(args),
+        Command::EncryptPattern(args) => commands::encrypt_pattern(args),
+        Command::Search(args) => commands::search_file(args),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+/// Runs the whole pipeline in a single process.
 /// It takes an initialization vector (IV) and a key as input, encrypts a specified number of
 /// outputs using AES-128 in an FHE setting, and then decrypts them to verify correctness.
-/// The program also measures and prints the time taken for encryption and decryption.
-fn main() {
-    let args = Args::parse();
+/// It also measures and prints the time taken by each phase, and writes a report if requested.
+///
+/// # Errors
+///
+/// This function will return an error if the key or IV is invalid, if a homomorphic operation
+/// fails, if the report cannot be written, or if an FHE output does not match the clear reference.
+fn run(args: RunArgs) -> Result<(), Error> {
+    let timestamp = chrono::Utc::now().to_rfc3339();
 
     // Convert the iv and key to an array of u8
-    let iv = hex_to_u8_array(&args.iv).unwrap();
-    let key = hex_to_u8_array(&args.key).unwrap();
-
-    // Create the AES128 encryptred ciphertext using standard AES128 crate
-    // for final verification
-    let mut expected_state = iv.clone();
-    let aes_cipher = Aes128::new((&key).into());
-    aes_cipher.encrypt_block((&mut expected_state).into());
+    let iv = *read_block(&args.iv, "IV")?;
+    let key_bytes = read_bytes(&args.key, "key")?;
+    check_len(
+        &format!("{:?} key", args.cipher),
+        args.cipher.key_len(),
+        key_bytes.len(),
+    )?;
+
+    if args.noise && args.cipher != Cipher::Aes {
+        return Err(Error::InvalidInput(
+            "noise diagnostics are only available for AES".to_string(),
+        ));
+    }
 
     // Increment the counter for required number of outputs
     let mut counters_encryption: Vec<[u8; 16]> = vec![iv];
@@ -191,93 +333,181 @@ fn main() {
         counters_encryption.push(incremented_iv);
     }
 
-    let config = ConfigBuilder::default().build();
-    let (cks, sks) = generate_keys(config);
+    let keygen_time = Instant::now();
 
-    // Distributing the server key to all the threads
-    rayon::broadcast(|_| set_server_key(sks.clone()));
-    set_server_key(sks);
+    // Every worker of the context holds the server key
+    let (cks, context) = FheAesContext::generate(args.params, 0)?;
 
-    // Generating the FHE-AES key from the hex string input
-    let key_fhe: [FheUint<FheUint8Id>; 16] =
-        std::array::from_fn(|index| FheUint8::encrypt(key[index], &cks));
+    let keygen_duration = keygen_time.elapsed();
 
-    let mut expanded_key: [FheUint<FheUint8Id>; 176] =
-        std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
+    let mut key_expansion_duration = None;
+    let mut decryption_duration = None;
+    let mut noise = None;
 
-    // ----------FHE-AES-KEY-EXPANSION-------------
-    key_expansion_fhe(&key_fhe, &mut expanded_key);
+    // Stream ciphers transcipher the counter blocks, so every cipher processes the same data
+    let blocks = context.install(|| -> Result<Vec<BlockReport>, Error> {
+        let blocks = if args.cipher != Cipher::Aes {
+            let key_fhe: Vec<FheUint8> = key_bytes
+                .iter()
+                .map(|x| FheUint8::encrypt(*x, &cks))
+                .collect();
+            let data: Vec<u8> = counters_encryption.concat();
+            run_stream_cipher(args.cipher, &key_fhe, &key_bytes, &iv, &data, &cks)?
+        } else {
+            // The length was checked above; the copy is wiped when dropped like `key_bytes`
+            let key: Zeroizing<[u8; 16]> = Zeroizing::new(std::array::from_fn(|i| key_bytes[i]));
 
-    let mut output_encryption: Vec<[FheUint8; 16]> =
-        vec![std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks))];
+            // Create the AES128 encryptred ciphertexts using standard AES128 crate
+            // for final verification
+            let aes_cipher = Aes128::new((&*key).into());
 
-    // Measure the time of computation
-    let computation_time = Instant::now();
+            // Generating the FHE-AES key from the hex string input
+            let key_fhe: [FheUint<FheUint8Id>; 16] =
+                std::array::from_fn(|index| FheUint8::encrypt(key[index], &cks));
 
-    // ------FHE-AES-ENCRYPTION for specified number_of_outputs-------
-    for i in 0..(args.number_of_outputs) as usize {
-        let mut _output_encryption: [FheUint<FheUint8Id>; 16] =
-            std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
+            let mut expanded_key: [FheUint<FheUint8Id>; 176] =
+                std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
 
-        let input: Vec<FheUint8> = counters_encryption[i]
-            .iter()
-            .map(|x| FheUint8::encrypt(*x, &cks))
-            .collect();
+            // ----------FHE-AES-KEY-EXPANSION-------------
+            let key_expansion_time = Instant::now();
+            key_expansion_fhe(&key_fhe, &mut expanded_key)?;
+            key_expansion_duration = Some(key_expansion_time.elapsed());
+
+            let mut blocks = vec![];
+            let mut total_decryption = Duration::ZERO;
+
+            for (index, counter) in counters_encryption.iter().enumerate() {
+                let input: Vec<FheUint8> = counter
+                    .iter()
+                    .map(|x| FheUint8::encrypt(*x, &cks))
+                    .collect();
+
+                // ------FHE-AES-ENCRYPTION-------
+                let mut output_encryption: [FheUint8; 16] =
+                    std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+
+                let encryption_time = Instant::now();
+                aes_encrypt_block(&input, &mut output_encryption, &expanded_key)?;
+                let encryption_duration = encryption_time.elapsed();
+
+                // ------FHE-AES-DECRYPTION-------
+                let mut output_decryption: [FheUint8; 16] =
+                    std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+
+                let decryption_time = Instant::now();
+                aes_decrypt_block(&output_encryption, &mut output_decryption, &expanded_key)?;
+                let block_decryption = decryption_time.elapsed();
+                total_decryption += block_decryption;
+
+                // Cross checking the AES outputs
+                let mut expected_state = *counter;
+                aes_cipher.encrypt_block((&mut expected_state).into());
+
+                let encrypted: Vec<u8> =
+                    output_encryption.iter().map(|x| x.decrypt(&cks)).collect();
+                let decrypted: Vec<u8> =
+                    output_decryption.iter().map(|x| x.decrypt(&cks)).collect();
+
+                blocks.push(BlockReport {
+                    index,
+                    encryption_ms: millis(encryption_duration),
+                    decryption_ms: Some(millis(block_decryption)),
+                    verified: encrypted == expected_state && decrypted == counter,
+                });
+            }
 
-        if i == 0 {
-            aes_encrypt_block(&input, &mut output_encryption[i], &expanded_key);
-            continue;
-        }
+            decryption_duration = Some(total_decryption);
 
-        aes_encrypt_block(&input, &mut _output_encryption, &expanded_key);
-        output_encryption.push(_output_encryption);
-    }
+            // ------FHE-AES-NOISE-DIAGNOSTICS-------
+            if args.noise {
+                let probe = NoiseProbe::new(&cks);
+                let mut steps = vec![];
 
-    // Only incrluding the time taken in encryption in the computation time data. 
-    let computation_duration = computation_time.elapsed().as_secs();
+                for counter in &counters_encryption {
+                    let input: Vec<FheUint8> = counter
+                        .iter()
+                        .map(|x| FheUint8::encrypt(*x, &cks))
+                        .collect();
+                    steps.extend(diagnose_encrypt_block(&input, &expanded_key, &probe)?.1);
+                }
 
-    // -------FHE-AES-DECRYPTION for specified number_of_outputs-------
-    let mut output_decryption: Vec<[FheUint8; 16]> =
-        vec![std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks))];
+                noise = Some(worst_per_round(&steps));
+            }
 
-    for i in 0..(args.number_of_outputs) as usize {
-        let mut _output_decryption: [FheUint8; 16] =
-            std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
+            blocks
+        };
+
+        Ok(blocks)
+    })?;
+
+    // Only incrluding the time taken in encryption in the computation time data.
+    let encryption_ms: f64 = blocks.iter().map(|block| block.encryption_ms).sum();
+
+    let report = RunReport {
+        timestamp,
+        cipher: format!("{:?}", args.cipher),
+        params: args.params.to_string(),
+        number_of_outputs: args.number_of_outputs,
+        threads: context.threads(),
+        keygen_ms: millis(keygen_duration),
+        key_expansion_ms: key_expansion_duration.map(millis),
+        encryption_ms,
+        decryption_ms: decryption_duration.map(millis),
+        verified: blocks.iter().all(|block| block.verified),
+        blocks,
+        noise,
+    };
+
+    let summary = format!(
+        "{:?} of {} outputs took {:.0} ms (key generation {:.0} ms, key expansion {} ms, decryption {} ms)",
+        args.cipher,
+        report.number_of_outputs,
+        report.encryption_ms,
+        report.keygen_ms,
+        report
+            .key_expansion_ms
+            .map_or("-".to_string(), |x| format!("{:.0}", x)),
+        report
+            .decryption_ms
+            .map_or("-".to_string(), |x| format!("{:.0}", x)),
+    );
 
-        if i == 0 {
-            aes_decrypt_block(
-                &output_encryption[i].clone().into(),
-                &mut output_decryption[i],
-                &expanded_key,
-            );
-            continue;
+    // The summary moves to stderr when a report is written, so stdout stays machine-readable
+    match args.report {
+        Some(format) => {
+            eprintln!("{}", summary);
+            let mut writer = create_output(&args.report_output)?;
+            report
+                .write(format, &mut writer)
+                .map_err(|e| Error::Io("cannot write report".to_string(), e))?;
         }
-
-        aes_decrypt_block(
-            &output_encryption[i].clone().into(),
-            &mut _output_decryption,
-            &expanded_key,
-        );
-
-        output_decryption.push(_output_decryption);
+        None => println!("{}", summary),
     }
 
-    // Cross checking the AES outputs
-    for i in 0..16 {
-        let result: u8 = output_encryption[0][i].decrypt(&cks);
-        assert_eq!(result, expected_state[i]);
+    // The noise table always goes to stderr, like the summary of a run with a report
+    for round in report.noise.iter().flatten() {
+        eprintln!(
+            "Round {:2}: worst noise margin {:5.1} bits after {}{}",
+            round.round,
+            round.margin_bits,
+            round.worst_step,
+            if round.decryptable && round.margin_bits > 0.0 {
+                ""
+            } else {
+                " (NOT DECRYPTABLE)"
+            }
+        );
     }
 
-    for i in 0..16 {
-        let result: u8 = output_decryption[0][i].decrypt(&cks);
-        assert_eq!(result, iv[i]);
+    if !report.verified {
+        return Err(Error::Verification(format!(
+            "{} of {} FHE outputs do not match the clear reference",
+            report.blocks.iter().filter(|block| !block.verified).count(),
+            report.number_of_outputs
+        )));
     }
 
-    println!(
-        "AES of {} outputs took {} seconds",
-        output_decryption.len(),
-        computation_duration
-    );
+    Ok(())
 }
 
 #[cfg(test)]
@@ -291,7 +521,8 @@ fn main() {
 ///
 /// - `aes_decryption`: Tests AES decryption by generating a random IV and key, decrypting a block, and verifying the result.
 ///
-/// - `aes_key_expansion`: Tests AES key expansion by generating a random key and expanding it using FHE.
+/// - `aes_key_expansion`: Tests AES key expansion by generating a random key, expanding it using FHE and verifying the
+///   decrypted schedule against the clear key expansion.
 ///
 /// # Usage
 ///
@@ -304,15 +535,19 @@ fn main() {
 /// ```
 mod tests {
     use rand::Rng;
-    use std::{fs::File, io::Write, time::Instant};
+    use std::fmt::Write as _;
+    use std::time::Instant;
+
+    use fhe_aes128::utils::{hex_to_u8_array, key_expansion_clear};
 
     use super::*;
 
     fn generate_random_hex_string() -> String {
         let mut rng = rand::thread_rng();
-        (0..16)
-            .map(|_| format!("{:02x}", rng.gen::<u8>()))
-            .collect()
+        (0..16).fold(String::new(), |mut output, _| {
+            let _ = write!(output, "{:02x}", rng.gen::<u8>());
+            output
+        })
     }
 
     #[test]
@@ -322,7 +557,7 @@ mod tests {
         let iv = hex_to_u8_array(&generate_random_hex_string()).unwrap();
         let key = hex_to_u8_array(&generate_random_hex_string()).unwrap();
 
-        let mut expected_state = iv.clone();
+        let mut expected_state = iv;
         let aes_cipher = Aes128::new((&key).into());
         aes_cipher.encrypt_block((&mut expected_state).into());
 
@@ -334,51 +569,49 @@ mod tests {
             counters_encryption.push(incremented_iv);
         }
 
-        let config = ConfigBuilder::default().build();
-        let (cks, sks) = generate_keys(config);
+        let (cks, context) = FheAesContext::generate(ParameterSet::Default, 0).unwrap();
 
-        rayon::broadcast(|_| set_server_key(sks.clone()));
-        set_server_key(sks);
+        context.install(|| {
+            let key_fhe: [FheUint<FheUint8Id>; 16] =
+                std::array::from_fn(|index| FheUint8::encrypt(key[index], &cks));
 
-        let key_fhe: [FheUint<FheUint8Id>; 16] =
-            std::array::from_fn(|index| FheUint8::encrypt(key[index], &cks));
+            let mut expanded_key: [FheUint<FheUint8Id>; 176] =
+                std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
 
-        let mut expanded_key: [FheUint<FheUint8Id>; 176] =
-            std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
+            key_expansion_fhe(&key_fhe, &mut expanded_key).unwrap();
 
-        key_expansion_fhe(&key_fhe, &mut expanded_key);
+            let mut output_encryption: [FheUint<FheUint8Id>; 16] =
+                std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
 
-        let mut output_encryption: [FheUint<FheUint8Id>; 16] =
-            std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
+            for (i, counter) in counters_encryption.iter().enumerate() {
+                let mut _output_encryption: [FheUint<FheUint8Id>; 16] =
+                    std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
 
-        for i in 0..number_of_outputs {
-            let mut _output_encryption: [FheUint<FheUint8Id>; 16] =
-                std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
+                let input: Vec<FheUint8> = counter
+                    .iter()
+                    .map(|x| FheUint8::encrypt(*x, &cks))
+                    .collect();
 
-            let input: Vec<FheUint8> = counters_encryption[i]
-                .iter()
-                .map(|x| FheUint8::encrypt(*x, &cks))
-                .collect();
+                if i == 0 {
+                    aes_encrypt_block(&input, &mut output_encryption, &expanded_key).unwrap();
+                    continue;
+                }
 
-            if i == 0 {
-                aes_encrypt_block(&input, &mut output_encryption, &expanded_key);
-                continue;
+                aes_encrypt_block(&input, &mut _output_encryption, &expanded_key).unwrap();
             }
 
-            aes_encrypt_block(&input, &mut _output_encryption, &expanded_key);
-        }
-
-        let encryption_duration = encryption_start.elapsed().as_secs();
+            let encryption_duration = encryption_start.elapsed().as_secs();
 
-        for i in 0..16 {
-            let result: u8 = output_encryption[i].decrypt(&cks);
-            assert_eq!(result, expected_state[i]);
-        }
+            for i in 0..16 {
+                let result: u8 = output_encryption[i].decrypt(&cks);
+                assert_eq!(result, expected_state[i]);
+            }
 
-        println!(
-            "AES encryption of {} outputs took {} seconds",
-            number_of_outputs, encryption_duration
-        );
+            println!(
+                "AES encryption of {} outputs took {} seconds",
+                number_of_outputs, encryption_duration
+            );
+        });
     }
 
     #[test]
@@ -388,7 +621,7 @@ mod tests {
         let iv = hex_to_u8_array(&generate_random_hex_string()).unwrap();
         let key = hex_to_u8_array(&generate_random_hex_string()).unwrap();
 
-        let mut expected_state = iv.clone();
+        let mut expected_state = iv;
         let aes_cipher = Aes128::new((&key).into());
         aes_cipher.encrypt_block((&mut expected_state).into());
 
@@ -400,67 +633,77 @@ mod tests {
             counters_decryption.push(incremented_exepcted_state);
         }
 
-        let config = ConfigBuilder::default().build();
-        let (cks, sks) = generate_keys(config);
+        let (cks, context) = FheAesContext::generate(ParameterSet::Default, 0).unwrap();
 
-        rayon::broadcast(|_| set_server_key(sks.clone()));
-        set_server_key(sks);
+        context.install(|| {
+            let key_fhe: [FheUint<FheUint8Id>; 16] =
+                std::array::from_fn(|index| FheUint8::encrypt(key[index], &cks));
 
-        let key_fhe: [FheUint<FheUint8Id>; 16] =
-            std::array::from_fn(|index| FheUint8::encrypt(key[index], &cks));
+            let mut expanded_key: [FheUint<FheUint8Id>; 176] =
+                std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
 
-        let mut expanded_key: [FheUint<FheUint8Id>; 176] =
-            std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
+            key_expansion_fhe(&key_fhe, &mut expanded_key).unwrap();
 
-        key_expansion_fhe(&key_fhe, &mut expanded_key);
+            let mut output_decryption: [FheUint<FheUint8Id>; 16] =
+                std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
 
-        let mut output_decryption: [FheUint<FheUint8Id>; 16] =
-            std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
+            for (i, counter) in counters_decryption.iter().enumerate() {
+                let mut _output_decryption: [FheUint<FheUint8Id>; 16] =
+                    std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
 
-        for i in 0..number_of_outputs {
-            let mut _output_decryption: [FheUint<FheUint8Id>; 16] =
-                std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
+                let input: Vec<FheUint8> = counter
+                    .iter()
+                    .map(|x| FheUint8::encrypt(*x, &cks))
+                    .collect();
 
-            let input: Vec<FheUint8> = counters_decryption[i]
-                .iter()
-                .map(|x| FheUint8::encrypt(*x, &cks))
-                .collect();
+                if i == 0 {
+                    aes_decrypt_block(&input, &mut output_decryption, &expanded_key).unwrap();
+                    continue;
+                }
 
-            if i == 0 {
-                aes_decrypt_block(&input, &mut output_decryption, &expanded_key);
-                continue;
+                aes_decrypt_block(&input, &mut _output_decryption, &expanded_key).unwrap();
             }
 
-            aes_decrypt_block(&input, &mut _output_decryption, &expanded_key);
-        }
-
-        let decryption_duration = decryption_start.elapsed().as_secs();
+            let decryption_duration = decryption_start.elapsed().as_secs();
 
-        for i in 0..16 {
-            let result: u8 = output_decryption[i].decrypt(&cks);
-            assert_eq!(result, iv[i]);
-        }
+            for i in 0..16 {
+                let result: u8 = output_decryption[i].decrypt(&cks);
+                assert_eq!(result, iv[i]);
+            }
 
-        println!(
-            "AES decryption of {} outputs took {} seconds",
-            number_of_outputs, decryption_duration
-        );
+            println!(
+                "AES decryption of {} outputs took {} seconds",
+                number_of_outputs, decryption_duration
+            );
+        });
     }
 
     #[test]
     fn aes_key_expansion() {
-        let config = ConfigBuilder::default().build();
-        let (cks, sks) = generate_keys(config);
-
-        rayon::broadcast(|_| set_server_key(sks.clone()));
-        set_server_key(sks);
+        let (cks, context) = FheAesContext::generate(ParameterSet::Default, 0).unwrap();
 
-        let key = hex_to_u8_array(&generate_random_hex_string()).unwrap();
-        let key_fhe = std::array::from_fn(|index| FheUint8::encrypt(key[index], &cks));
+        context.install(|| {
+            let key = hex_to_u8_array(&generate_random_hex_string()).unwrap();
+            let key_fhe = std::array::from_fn(|index| FheUint8::encrypt(key[index], &cks));
 
-        let mut expanded_key: [FheUint<FheUint8Id>; 176] =
-            std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
+            let mut expanded_key: [FheUint<FheUint8Id>; 176] =
+                std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
 
-        key_expansion_fhe(&key_fhe, &mut expanded_key);
+            key_expansion_fhe(&key_fhe, &mut expanded_key).unwrap();
+            verify_key_schedule(&expanded_key, &key, &cks).unwrap();
+
+            // A corrupted byte of round key 2 is reported with its word and round
+            let corrupted: u8 = expanded_key[37].decrypt(&cks);
+            expanded_key[37] = FheUint8::encrypt(corrupted ^ 1, &cks);
+            assert!(matches!(
+                verify_key_schedule(&expanded_key, &key, &cks),
+                Err(Error::Verification(_))
+            ));
+
+            let mut schedule = key_expansion_clear(&key);
+            schedule[37] ^= 1;
+            let mismatch = compare_key_schedule(&schedule, &key).unwrap();
+            assert_eq!((mismatch.word, mismatch.round), (9, 2));
+        });
     }
 }
@@ -0,0 +1,280 @@
+/// This module implements noise diagnostics for the FHE AES circuit.
+/// Every TFHE ciphertext carries noise that grows with each operation and is reset by
+/// programmable bootstrapping. A ciphertext decrypts correctly as long as its noise stays below
+/// half of the encoding step. This module measures, with the client key, how far the noise of the
+/// intermediate AES states is from that limit, which validates aggressive parameter choices.
+/// It includes the following items:
+/// - `NoiseProbe`: Measures the noise margin of encrypted bytes with the client key.
+/// - `StepNoise`: The worst margin of the state after one round transformation.
+/// - `RoundNoise`: The worst margin of a round, over its transformations.
+/// - `diagnose_encrypt_block`: Encrypts a block like `aes_encrypt_block`, measuring the state after every transformation.
+/// - `worst_per_round`: Summarizes step measurements per round.
+///
+/// The margin is given in bits: `log2(delta / 2) - log2(|noise|)`, where `delta` is the encoding
+/// step of a shortint block. A margin of 0 bits or less means the block may decrypt incorrectly.
+/// Each measured state is also decrypted and compared with the same transformations run on the
+/// clear `u8` backend, which catches blocks whose noise has already crossed the limit.
+use crate::encryption::{add_blocks, mix_columns, shift_rows, sub_bytes};
+use crate::error::Error;
+use rayon::prelude::*;
+use serde::Serialize;
+use tfhe::core_crypto::prelude::{decrypt_lwe_ciphertext, LweSecretKeyOwned};
+use tfhe::integer::IntegerCiphertext;
+use tfhe::prelude::*;
+use tfhe::{ClientKey, FheUint8};
+
+/// The noise margin of the AES state after one round transformation.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StepNoise {
+    /// The round of the transformation, 0 for the initial round key addition.
+    pub round: usize,
+    /// The transformation: `sub_bytes`, `shift_rows`, `mix_columns` or `add_blocks`.
+    pub step: &'static str,
+    /// The smallest noise margin over the bytes of the state, in bits.
+    pub margin_bits: f64,
+    /// Whether every byte of the state decrypts to the clear AES state.
+    pub decryptable: bool,
+}
+
+/// The worst noise margin of an AES round.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RoundNoise {
+    /// The round, 0 for the initial round key addition.
+    pub round: usize,
+    /// The transformation with the smallest margin.
+    pub worst_step: &'static str,
+    /// The smallest noise margin of the round, in bits.
+    pub margin_bits: f64,
+    /// Whether every state of the round decrypted to the clear AES state.
+    pub decryptable: bool,
+}
+
+/// Measures the noise of encrypted bytes with the LWE secret keys of a client key.
+pub struct NoiseProbe<'a> {
+    cks: &'a ClientKey,
+    /// The GLWE secret key seen as an LWE key, for ciphertexts coming out of a bootstrap.
+    large_key: LweSecretKeyOwned<u64>,
+    /// The LWE secret key, for ciphertexts coming out of a key switch.
+    small_key: LweSecretKeyOwned<u64>,
+}
+
+impl<'a> NoiseProbe<'a> {
+    /// Extracts the LWE secret keys of `cks`.
+    pub fn new(cks: &'a ClientKey) -> Self {
+        let integer_key = cks.clone().into_raw_parts().0;
+        let (glwe_key, small_key, _) = integer_key.into_raw_parts().into_raw_parts();
+
+        Self {
+            cks,
+            large_key: glwe_key.into_lwe_secret_key(),
+            small_key,
+        }
+    }
+
+    /// Returns the smallest noise margin, in bits, of the shortint blocks of `byte`.
+    pub fn margin_bits(&self, byte: &FheUint8) -> f64 {
+        let radix = byte.clone().into_raw_parts().0;
+
+        radix
+            .blocks()
+            .iter()
+            .map(|block| {
+                let dimension = block.ct.lwe_size().to_lwe_dimension();
+                let key = if dimension == self.large_key.lwe_dimension() {
+                    &self.large_key
+                } else {
+                    &self.small_key
+                };
+
+                // One padding bit, then the carry and message bits
+                let delta = (1u64 << 63) / (block.message_modulus.0 * block.carry_modulus.0);
+
+                let plaintext = decrypt_lwe_ciphertext(key, &block.ct).0;
+                let rounded = plaintext.wrapping_add(delta / 2) / delta * delta;
+                let noise = plaintext.wrapping_sub(rounded) as i64;
+
+                ((delta / 2) as f64).log2() - (noise.unsigned_abs().max(1) as f64).log2()
+            })
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// Measures the AES state after a transformation.
+    ///
+    /// # Arguments
+    /// * `round` - The round of the transformation.
+    /// * `step` - The name of the transformation.
+    /// * `state` - The encrypted state.
+    /// * `expected` - The clear state computed by the reference implementation.
+    pub fn measure(
+        &self,
+        round: usize,
+        step: &'static str,
+        state: &[FheUint8],
+        expected: &[u8],
+    ) -> StepNoise {
+        let margin_bits = state
+            .par_iter()
+            .map(|byte| self.margin_bits(byte))
+            .reduce(|| f64::INFINITY, f64::min);
+
+        let decrypted: Vec<u8> = state.iter().map(|byte| byte.decrypt(self.cks)).collect();
+
+        StepNoise {
+            round,
+            step,
+            margin_bits,
+            decryptable: decrypted == expected,
+        }
+    }
+}
+
+/// Encrypts a block like `aes_encrypt_block`, measuring the noise of the state after every
+/// transformation.
+///
+/// # Arguments
+/// * `input` - The encrypted input block.
+/// * `expanded_key` - The encrypted AES key schedule.
+/// * `probe` - The noise probe, holding the client key.
+///
+/// # Returns
+/// * `([FheUint8; 16], Vec<StepNoise>)` - The encrypted output block and the 40 step measurements.
+///
+/// # Behavior
+/// - The clear reference states are computed from the decrypted input and key schedule with the
+///   `u8` backend, so the diagnostics only run where the client key is available.
+///
+/// # Errors
+/// This function will return an error if a homomorphic S-Box lookup fails.
+pub fn diagnose_encrypt_block(
+    input: &[FheUint8],
+    expanded_key: &[FheUint8; 176],
+    probe: &NoiseProbe,
+) -> Result<([FheUint8; 16], Vec<StepNoise>), Error> {
+    let round_keys: Vec<u8> = expanded_key.iter().map(|x| x.decrypt(probe.cks)).collect();
+
+    let mut expected: Vec<u8> = input.iter().map(|x| x.decrypt(probe.cks)).collect();
+    let mut state = input.to_vec();
+    let mut steps = vec![];
+
+    add_blocks(&mut expected, &round_keys[0..16]);
+    add_blocks(&mut state, &expanded_key[0..16]);
+    steps.push(probe.measure(0, "add_blocks", &state, &expected));
+
+    for round in 1..=10 {
+        let round_key = round * 16..(round + 1) * 16;
+
+        sub_bytes(&mut state)?;
+        sub_bytes(&mut expected)?;
+        steps.push(probe.measure(round, "sub_bytes", &state, &expected));
+
+        shift_rows(&mut state);
+        shift_rows(&mut expected);
+        steps.push(probe.measure(round, "shift_rows", &state, &expected));
+
+        // The final round has no MixColumns
+        if round < 10 {
+            mix_columns(&mut state);
+            mix_columns(&mut expected);
+            steps.push(probe.measure(round, "mix_columns", &state, &expected));
+        }
+
+        add_blocks(&mut state, &expanded_key[round_key.clone()]);
+        add_blocks(&mut expected, &round_keys[round_key]);
+        steps.push(probe.measure(round, "add_blocks", &state, &expected));
+    }
+
+    Ok((std::array::from_fn(|i| state[i].clone()), steps))
+}
+
+/// Returns the worst margin of each round, over all the given step measurements.
+///
+/// # Arguments
+/// * `steps` - Step measurements, possibly of several blocks.
+pub fn worst_per_round(steps: &[StepNoise]) -> Vec<RoundNoise> {
+    let mut rounds: Vec<RoundNoise> = vec![];
+
+    for step in steps {
+        match rounds.iter_mut().find(|round| round.round == step.round) {
+            Some(round) => {
+                if step.margin_bits < round.margin_bits {
+                    round.margin_bits = step.margin_bits;
+                    round.worst_step = step.step;
+                }
+                round.decryptable &= step.decryptable;
+            }
+            None => rounds.push(RoundNoise {
+                round: step.round,
+                worst_step: step.step,
+                margin_bits: step.margin_bits,
+                decryptable: step.decryptable,
+            }),
+        }
+    }
+
+    rounds.sort_by_key(|round| round.round);
+    rounds
+}
+
+#[cfg(test)]
+/// This module contains tests for the noise diagnostics.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- noise::tests::noise_diagnostics --exact --show-output
+/// ```
+mod tests {
+    use aes::cipher::{BlockEncrypt, KeyInit};
+    use aes::Aes128;
+    use rand::Rng;
+
+    use super::*;
+    use crate::context::FheAesContext;
+    use crate::key_expansion::key_expansion_fhe;
+    use crate::params::ParameterSet;
+
+    #[test]
+    fn noise_diagnostics() {
+        let mut rng = rand::thread_rng();
+        let key: [u8; 16] = rng.gen();
+        let input: [u8; 16] = rng.gen();
+
+        let (cks, context) = FheAesContext::generate(ParameterSet::Default, 0).unwrap();
+
+        context.install(|| {
+            let key_fhe: [FheUint8; 16] = std::array::from_fn(|i| FheUint8::encrypt(key[i], &cks));
+            let mut expanded_key: [FheUint8; 176] =
+                std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+            key_expansion_fhe(&key_fhe, &mut expanded_key).unwrap();
+
+            let input_fhe: Vec<FheUint8> =
+                input.iter().map(|x| FheUint8::encrypt(*x, &cks)).collect();
+
+            let probe = NoiseProbe::new(&cks);
+            let (output, steps) =
+                diagnose_encrypt_block(&input_fhe, &expanded_key, &probe).unwrap();
+
+            let mut expected = input;
+            Aes128::new((&key).into()).encrypt_block((&mut expected).into());
+            let result: Vec<u8> = output.iter().map(|x| x.decrypt(&cks)).collect();
+            assert_eq!(result, expected);
+
+            // 1 initial step, 9 rounds of 4 steps and a final round of 3 steps
+            assert_eq!(steps.len(), 40);
+            assert!(steps.iter().all(|step| step.decryptable));
+
+            let rounds = worst_per_round(&steps);
+            assert_eq!(rounds.len(), 11);
+            assert!(rounds.iter().all(|round| round.margin_bits > 0.0));
+
+            for round in rounds {
+                println!(
+                    "round {:2}: {:5.1} bits ({})",
+                    round.round, round.margin_bits, round.worst_step
+                );
+            }
+        });
+    }
+}
@@ -0,0 +1,144 @@
+/// This module defines the TFHE parameter sets that can be selected from the CLI and the API.
+/// A parameter set fixes the message and carry moduli of the shortint blocks, the kind of
+/// programmable bootstrapping (PBS) and the failure probability of each bootstrap, and so the
+/// security, speed and correctness of every homomorphic operation.
+/// It includes the following items:
+/// - `ParameterSet`: The selectable parameter sets.
+/// - `ParameterSet::config`: Builds the `tfhe` configuration used to generate the keys.
+/// - `ParameterSet::check`: Checks that two artifacts were produced with the same parameters.
+///
+/// Keys and ciphertexts generated with different parameter sets are not compatible, so every
+/// serialized artifact records the parameter set it was produced with (see the `artifacts` module).
+use crate::error::Error;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use tfhe::shortint::parameters::{
+    PBSParameters, PARAM_MESSAGE_2_CARRY_2_KS_PBS,
+    V0_11_PARAM_MESSAGE_1_CARRY_1_KS_PBS_GAUSSIAN_2M64,
+    V0_11_PARAM_MESSAGE_3_CARRY_3_KS_PBS_GAUSSIAN_2M64,
+    V0_11_PARAM_MULTI_BIT_GROUP_3_MESSAGE_2_CARRY_2_KS_PBS_GAUSSIAN_2M64,
+};
+use tfhe::{Config, ConfigBuilder};
+
+/// The TFHE parameter sets, all targeting 128 bits of security with a failure probability of 2^-64
+/// per bootstrap, the only failure probability `tfhe` 0.11 provides.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ParameterSet {
+    /// The `tfhe` defaults: 2-bit messages and carries, classic PBS, TUniform noise.
+    #[default]
+    Default,
+    /// 1-bit messages and carries, classic PBS, Gaussian noise. More blocks per byte, each with a cheaper PBS.
+    #[value(name = "message-1-carry-1")]
+    Message1Carry1,
+    /// 3-bit messages and carries, classic PBS, Gaussian noise. Fewer blocks per byte, each with a costlier PBS.
+    #[value(name = "message-3-carry-3")]
+    Message3Carry3,
+    /// 2-bit messages and carries, multi-bit PBS with groups of 3, Gaussian noise. Faster on many-core machines.
+    MultiBit,
+}
+
+impl ParameterSet {
+    /// Returns the shortint block parameters of the set.
+    fn pbs_parameters(&self) -> PBSParameters {
+        match self {
+            ParameterSet::Default => PARAM_MESSAGE_2_CARRY_2_KS_PBS.into(),
+            ParameterSet::Message1Carry1 => {
+                V0_11_PARAM_MESSAGE_1_CARRY_1_KS_PBS_GAUSSIAN_2M64.into()
+            }
+            ParameterSet::Message3Carry3 => {
+                V0_11_PARAM_MESSAGE_3_CARRY_3_KS_PBS_GAUSSIAN_2M64.into()
+            }
+            ParameterSet::MultiBit => {
+                V0_11_PARAM_MULTI_BIT_GROUP_3_MESSAGE_2_CARRY_2_KS_PBS_GAUSSIAN_2M64.into()
+            }
+        }
+    }
+
+    /// Builds the `tfhe` configuration of the set, to pass to `generate_keys`.
+    pub fn config(&self) -> Config {
+        match self {
+            ParameterSet::Default => ConfigBuilder::default().build(),
+            _ => ConfigBuilder::with_custom_parameters(self.pbs_parameters()).build(),
+        }
+    }
+
+    /// Returns an error if `actual`, the parameter set of `what`, differs from this set.
+    ///
+    /// # Arguments
+    /// * `what` - The name of the checked artifact, e.g. "encrypted key".
+    /// * `actual` - The parameter set recorded in the artifact.
+    ///
+    /// # Errors
+    /// This function will return an error if the parameter sets differ, since keys and ciphertexts
+    /// of different parameter sets cannot be combined.
+    pub fn check(&self, what: &str, actual: ParameterSet) -> Result<(), Error> {
+        if *self != actual {
+            return Err(Error::ParameterMismatch(format!(
+                "{} uses the {} parameter set, expected {}",
+                what, actual, self
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for ParameterSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The command line name of the set, e.g. `multi-bit`
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => write!(f, "{:?}", self),
+        }
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the TFHE parameter sets.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- params::tests::parameter_sets --exact --show-output
+/// ```
+mod tests {
+    use tfhe::prelude::*;
+    use tfhe::{generate_keys, set_server_key, FheUint8};
+
+    use super::*;
+
+    #[test]
+    fn parameter_sets() {
+        // Every set selects its own shortint parameters
+        let all = ParameterSet::value_variants();
+        for (i, a) in all.iter().enumerate() {
+            for b in &all[i + 1..] {
+                assert_ne!(a.pbs_parameters(), b.pbs_parameters(), "{} and {}", a, b);
+            }
+        }
+
+        for params in [ParameterSet::Message1Carry1, ParameterSet::MultiBit] {
+            let (cks, sks) = generate_keys(params.config());
+            set_server_key(sks);
+
+            let a = FheUint8::encrypt(0x53u8, &cks);
+            let result: u8 = (&a ^ 0xcau8).decrypt(&cks);
+            assert_eq!(result, 0x53 ^ 0xca);
+        }
+
+        assert!(ParameterSet::Default
+            .check("server key", ParameterSet::Default)
+            .is_ok());
+        assert!(matches!(
+            ParameterSet::Default.check("encrypted key", ParameterSet::MultiBit),
+            Err(Error::ParameterMismatch(_))
+        ));
+        assert_eq!(
+            ParameterSet::Message1Carry1.to_string(),
+            "message-1-carry-1"
+        );
+    }
+}
@@ -0,0 +1,259 @@
+/// This module implements an AES-based pseudorandom function (PRF) and deterministic tokenization
+/// using Fully Homomorphic Encryption (FHE). Both the AES key and the input identifiers stay encrypted.
+/// It includes functions for performing the following operations:
+/// - `length_prefixed_blocks`: Encodes a variable-length input as a sequence of 16-byte blocks.
+/// - `cbc_mac`: Chains the encoded blocks through `aes_encrypt_block` (CBC-MAC).
+/// - `prf`: Evaluates the PRF on a variable-length encrypted input.
+/// - `tokenize` / `tokenize_batch`: Produces truncated PRF outputs (tokens) for one or many identifiers.
+///
+/// CBC-MAC alone is only a secure PRF for fixed-length inputs, so every input is prefixed with
+/// its length before being chained. The length of an encrypted input is public anyway (it is the
+/// number of ciphertexts), so the prefix block is a trivial encryption and costs nothing to encode.
+use crate::aes_encrypt_block;
+use crate::encryption::add_blocks;
+use crate::error::Error;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use rayon::prelude::*;
+use tfhe::prelude::*;
+use tfhe::FheUint8;
+
+/// Size of an AES block in bytes.
+const BLOCK_SIZE: usize = 16;
+
+/// Encodes a variable-length input as a sequence of 16-byte blocks.
+///
+/// # Arguments
+/// * `input` - The bytes to encode, either encrypted or in the clear.
+/// * `encode` - Converts a constant byte into the element type of `input` (e.g. a trivial encryption).
+///
+/// # Returns
+/// * `Vec<Vec<T>>` - The length block followed by the input, zero-padded to a multiple of 16 bytes.
+///
+/// # Behavior
+/// - The first block holds the input length in bytes as a big-endian 128-bit integer.
+/// - The input follows, with its last block padded with zeros. An empty input is encoded as the length block only.
+pub fn length_prefixed_blocks<T: Clone>(input: &[T], encode: impl Fn(u8) -> T) -> Vec<Vec<T>> {
+    let mut blocks = vec![(input.len() as u128)
+        .to_be_bytes()
+        .iter()
+        .map(|byte| encode(*byte))
+        .collect::<Vec<T>>()];
+
+    for chunk in input.chunks(BLOCK_SIZE) {
+        let mut block = chunk.to_vec();
+        block.resize(BLOCK_SIZE, encode(0u8)); // Zero-pad the last block
+        blocks.push(block);
+    }
+
+    blocks
+}
+
+/// Computes the CBC-MAC of a sequence of encrypted blocks using AES with FHE.
+///
+/// # Arguments
+/// * `blocks` - The encrypted 16-byte blocks to authenticate, in order.
+/// * `expanded_key` - The encrypted AES key schedule produced by `key_expansion_fhe`.
+///
+/// # Returns
+/// * `[FheUint8; 16]` - The encrypted last chaining value.
+///
+/// # Behavior
+/// - The chaining value starts at zero, so the first block is encrypted directly.
+/// - Each following block is XORed into the chaining value before the next AES encryption.
+///
+/// # Errors
+/// This function will return an error if `blocks` is empty or if a homomorphic AES encryption
+/// fails.
+pub fn cbc_mac(
+    blocks: &[Vec<FheUint8>],
+    expanded_key: &[FheUint8; 176],
+) -> Result<[FheUint8; 16], Error> {
+    if blocks.is_empty() {
+        return Err(Error::InvalidInput(
+            "CBC-MAC needs at least one block".to_string(),
+        ));
+    }
+
+    let mut output: [FheUint8; 16] = std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+    let mut chaining = blocks[0].clone();
+
+    for (i, block) in blocks.iter().enumerate() {
+        if i > 0 {
+            chaining = output.to_vec();
+            add_blocks(&mut chaining, block); // XOR the next block into the chaining value
+        }
+
+        aes_encrypt_block(&chaining, &mut output, expanded_key)?;
+    }
+
+    Ok(output)
+}
+
+/// Evaluates the AES-based PRF on a variable-length encrypted input.
+///
+/// # Arguments
+/// * `input` - The encrypted input bytes. Any length, including zero, is accepted.
+/// * `expanded_key` - The encrypted AES key schedule produced by `key_expansion_fhe`.
+///
+/// # Returns
+/// * `[FheUint8; 16]` - The encrypted PRF output, CBC-MAC over the length-prefixed input.
+///
+/// # Errors
+/// This function will return an error if a homomorphic AES encryption fails.
+pub fn prf(input: &[FheUint8], expanded_key: &[FheUint8; 176]) -> Result<[FheUint8; 16], Error> {
+    let blocks = length_prefixed_blocks(input, FheUint8::encrypt_trivial);
+
+    cbc_mac(&blocks, expanded_key)
+}
+
+/// Computes the same PRF as [`prf`] in the clear using the standard AES128 crate.
+///
+/// This is used to verify encrypted tokens on the client side.
+pub fn prf_clear(key: &[u8; 16], input: &[u8]) -> [u8; 16] {
+    let aes_cipher = Aes128::new(key.into());
+    let mut chaining = [0u8; 16];
+
+    for block in length_prefixed_blocks(input, |byte| byte) {
+        for (c, b) in chaining.iter_mut().zip(block.iter()) {
+            *c ^= b;
+        }
+        aes_cipher.encrypt_block((&mut chaining).into());
+    }
+
+    chaining
+}
+
+/// Maps an encrypted identifier to an encrypted token of `token_len` bytes.
+///
+/// # Arguments
+/// * `input` - The encrypted identifier bytes.
+/// * `expanded_key` - The encrypted AES key schedule produced by `key_expansion_fhe`.
+/// * `token_len` - The number of PRF output bytes to keep, between 1 and 16.
+///
+/// # Errors
+/// This function will return an error if `token_len` is 0 or larger than 16, or if a homomorphic
+/// AES encryption fails.
+pub fn tokenize(
+    input: &[FheUint8],
+    expanded_key: &[FheUint8; 176],
+    token_len: usize,
+) -> Result<Vec<FheUint8>, Error> {
+    if !(1..=BLOCK_SIZE).contains(&token_len) {
+        return Err(Error::InvalidInput(format!(
+            "token length must be between 1 and 16 bytes, got {}",
+            token_len
+        )));
+    }
+
+    let output = prf(input, expanded_key)?;
+
+    Ok(output[..token_len].to_vec())
+}
+
+/// Tokenizes a list of encrypted identifiers under the same encrypted key.
+///
+/// # Arguments
+/// * `inputs` - The encrypted identifiers. They may have different lengths.
+/// * `expanded_key` - The encrypted AES key schedule produced by `key_expansion_fhe`.
+/// * `token_len` - The number of PRF output bytes to keep for each token, between 1 and 16.
+///
+/// # Returns
+/// * `Vec<Vec<FheUint8>>` - One encrypted token per identifier, in input order.
+///
+/// # Behavior
+/// - Identifiers are processed in parallel for efficiency using `par_iter()`.
+///
+/// # Errors
+/// This function will return the first error of `tokenize`.
+pub fn tokenize_batch(
+    inputs: &[Vec<FheUint8>],
+    expanded_key: &[FheUint8; 176],
+    token_len: usize,
+) -> Result<Vec<Vec<FheUint8>>, Error> {
+    inputs
+        .par_iter()
+        .map(|input| tokenize(input, expanded_key, token_len))
+        .collect()
+}
+
+#[cfg(test)]
+/// This module contains tests for the AES-based PRF.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following commands:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- prf::tests::length_prefixed_encoding --exact --show-output
+/// cargo test --release --package fhe-aes128 --lib -- prf::tests::prf_tokens --exact --show-output
+/// ```
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::context::FheAesContext;
+    use crate::key_expansion::key_expansion_fhe;
+    use crate::params::ParameterSet;
+
+    #[test]
+    fn length_prefixed_encoding() {
+        let input: Vec<u8> = (1..=20).collect();
+        let blocks = length_prefixed_blocks(&input, |byte| byte);
+
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[0][15], 20);
+        assert!(blocks[0][..15].iter().all(|byte| *byte == 0));
+        assert_eq!(blocks[1], (1..=16).collect::<Vec<u8>>());
+        assert_eq!(
+            blocks[2],
+            vec![17, 18, 19, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        // Zero padding must not make inputs of different lengths collide
+        assert_ne!(
+            prf_clear(&[0u8; 16], &[0u8; 3]),
+            prf_clear(&[0u8; 16], &[0u8; 4])
+        );
+        assert_eq!(length_prefixed_blocks(&[] as &[u8], |byte| byte).len(), 1);
+    }
+
+    #[test]
+    fn prf_tokens() {
+        let mut rng = rand::thread_rng();
+        let key: [u8; 16] = rng.gen();
+        let ids: Vec<Vec<u8>> = vec![b"alice".to_vec(), b"bob@example.com-0001".to_vec()];
+
+        let (cks, context) = FheAesContext::generate(ParameterSet::Default, 0).unwrap();
+
+        context.install(|| {
+            let key_fhe: [FheUint8; 16] =
+                std::array::from_fn(|index| FheUint8::encrypt(key[index], &cks));
+            let mut expanded_key: [FheUint8; 176] =
+                std::array::from_fn(|_| FheUint8::encrypt(0u8, &cks));
+            key_expansion_fhe(&key_fhe, &mut expanded_key).unwrap();
+
+            let ids_fhe: Vec<Vec<FheUint8>> = ids
+                .iter()
+                .map(|id| id.iter().map(|x| FheUint8::encrypt(*x, &cks)).collect())
+                .collect();
+
+            for token_len in [0, 17] {
+                let rejected = tokenize(&ids_fhe[0], &expanded_key, token_len);
+                assert!(matches!(rejected, Err(Error::InvalidInput(_))));
+            }
+            assert!(matches!(
+                cbc_mac(&[], &expanded_key),
+                Err(Error::InvalidInput(_))
+            ));
+
+            let tokens = tokenize_batch(&ids_fhe, &expanded_key, 8).unwrap();
+
+            for (id, token) in ids.iter().zip(tokens.iter()) {
+                let expected = prf_clear(&key, id);
+                let result: Vec<u8> = token.iter().map(|x| x.decrypt(&cks)).collect();
+                assert_eq!(result, expected[..8]);
+            }
+        });
+    }
+}
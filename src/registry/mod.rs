@@ -0,0 +1,578 @@
+/// This module implements a registry of encrypted AES keys, for a server that transciphers data for
+/// many clients, each with their own TFHE keys and encrypted AES keys.
+/// It includes the following items:
+/// - `KeyRegistry`: The encrypted key schedules by key ID, each bound to a client, with eviction of
+///   the least recently used schedules and persistence to a directory.
+/// - `TranscipherRequest`: A request to transcipher an AES-CTR ciphertext with a registered key.
+///
+/// The `tfhe` server key is thread-local, so a computation must run on threads holding the server
+/// key of the client its ciphertexts belong to. Every client has its own `FheAesContext`, and every
+/// computation on a key runs inside the context of its client: requests for keys of different
+/// clients run in parallel without ever switching the server key of a thread.
+use crate::artifacts::{load, save};
+use crate::context::FheAesContext;
+use crate::error::Error;
+use crate::params::ParameterSet;
+use crate::round_keys::KeySchedule;
+use crate::transciphering::{transcipher, AesCtrKeystream};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use tfhe::{FheUint8, ServerKey};
+
+/// A key schedule in the registry.
+struct KeyEntry {
+    /// The ID of the client the key belongs to.
+    client_id: String,
+    /// The schedule, or `None` once evicted to the store.
+    schedule: Option<Arc<KeySchedule<FheUint8>>>,
+    /// The registry clock at the last use of the key, for least recently used eviction.
+    last_used: u64,
+}
+
+/// The key index of the registry, behind a single lock.
+#[derive(Default)]
+struct Keys {
+    entries: HashMap<String, KeyEntry>,
+    /// Incremented at every use of a key.
+    clock: u64,
+    /// Incremented at every `remove_key`, so a reload that raced with a removal is detected.
+    removals: u64,
+}
+
+impl Keys {
+    /// Returns the client ID and schedule of a key held in memory, marking it used at `clock`.
+    fn resident(
+        &mut self,
+        key_id: &str,
+        clock: u64,
+    ) -> Option<(String, Arc<KeySchedule<FheUint8>>)> {
+        let entry = self.entries.get_mut(key_id)?;
+        let schedule = entry.schedule.clone()?;
+        entry.last_used = entry.last_used.max(clock);
+
+        Some((entry.client_id.clone(), schedule))
+    }
+}
+
+/// A request to transcipher an AES-CTR ciphertext with a registered key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TranscipherRequest {
+    /// The ID of the key the data was encrypted with.
+    pub key_id: String,
+    /// The initial counter block.
+    pub iv: [u8; 16],
+    /// The AES-CTR ciphertext.
+    pub ciphertext: Vec<u8>,
+}
+
+/// Encrypted AES key schedules by key ID, and the server keys of their clients.
+pub struct KeyRegistry {
+    clients: RwLock<HashMap<String, Arc<FheAesContext>>>,
+    keys: Mutex<Keys>,
+    /// The maximum number of schedules held in memory.
+    capacity: usize,
+    /// The directory the schedules are persisted to, if any.
+    store: Option<PathBuf>,
+}
+
+impl KeyRegistry {
+    /// Creates an empty registry holding at most `capacity` schedules in memory.
+    ///
+    /// # Behavior
+    /// Without a store, schedules are never evicted, since they could not be reloaded: the
+    /// capacity only applies with `with_store`.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            clients: RwLock::new(HashMap::new()),
+            keys: Mutex::new(Keys::default()),
+            capacity: capacity.max(1),
+            store: None,
+        }
+    }
+
+    /// Persists the schedules to `dir`, one file per key, so they can be evicted from memory and
+    /// reloaded on demand, including by a registry restarted on the same directory.
+    ///
+    /// # Errors
+    /// This function will return an error if the directory cannot be created.
+    pub fn with_store(mut self, dir: &Path) -> Result<Self, Error> {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| Error::Io(format!("cannot create {}", dir.display()), e))?;
+        self.store = Some(dir.to_path_buf());
+
+        Ok(self)
+    }
+
+    /// Registers the server key of a client, replacing any previous one.
+    ///
+    /// # Arguments
+    /// * `client_id` - The ID of the client.
+    /// * `params` - The parameter set the client's keys were generated with.
+    /// * `server_key` - The client's TFHE server key.
+    /// * `threads` - The number of worker threads of the client, or 0 for the rayon default.
+    ///
+    /// # Errors
+    /// This function will return an error if the context of the client cannot be built.
+    pub fn add_client(
+        &self,
+        client_id: &str,
+        params: ParameterSet,
+        server_key: ServerKey,
+        threads: usize,
+    ) -> Result<(), Error> {
+        let context = FheAesContext::new(params, server_key, threads)?;
+
+        self.clients
+            .write()
+            .expect("client lock poisoned")
+            .insert(client_id.to_string(), Arc::new(context));
+
+        Ok(())
+    }
+
+    /// Returns the context of a client.
+    ///
+    /// # Errors
+    /// This function will return an error if the client is unknown.
+    pub fn client(&self, client_id: &str) -> Result<Arc<FheAesContext>, Error> {
+        self.clients
+            .read()
+            .expect("client lock poisoned")
+            .get(client_id)
+            .cloned()
+            .ok_or_else(|| Error::InvalidInput(format!("unknown client {:?}", client_id)))
+    }
+
+    /// Registers the encrypted key schedule `key_id` of a client, replacing any previous one.
+    ///
+    /// # Behavior
+    /// With a store, the schedule is written to it, and the least recently used schedules are
+    /// evicted from memory to stay within the capacity.
+    ///
+    /// # Errors
+    /// This function will return an error if the key ID is not made of ASCII letters, digits, `-`
+    /// and `_`, if the client is unknown, or if the schedule cannot be persisted.
+    pub fn insert_key(
+        &self,
+        key_id: &str,
+        client_id: &str,
+        schedule: KeySchedule<FheUint8>,
+    ) -> Result<(), Error> {
+        check_key_id(key_id)?;
+        let client = self.client(client_id)?;
+
+        if let Some(path) = self.key_path(key_id) {
+            save(
+                &path,
+                client.params(),
+                &(client_id.to_string(), schedule.to_bytes()),
+            )?;
+        }
+
+        let mut keys = self.keys.lock().expect("key lock poisoned");
+        keys.clock += 1;
+        let entry = KeyEntry {
+            client_id: client_id.to_string(),
+            schedule: Some(Arc::new(schedule)),
+            last_used: keys.clock,
+        };
+        keys.entries.insert(key_id.to_string(), entry);
+        self.evict_over_capacity(&mut keys);
+
+        Ok(())
+    }
+
+    /// Removes a key from the registry and from its store.
+    ///
+    /// # Returns
+    /// * `bool` - Whether the key was registered.
+    ///
+    /// # Errors
+    /// This function will return an error if the persisted schedule cannot be deleted.
+    pub fn remove_key(&self, key_id: &str) -> Result<bool, Error> {
+        // The file is deleted under the lock, so a reload that read it before the removal finds
+        // it gone when it locks again to insert the schedule
+        let mut keys = self.keys.lock().expect("key lock poisoned");
+        keys.removals += 1;
+        let removed = keys.entries.remove(key_id).is_some();
+
+        match self.key_path(key_id) {
+            Some(path) if path.exists() => {
+                std::fs::remove_file(&path)
+                    .map_err(|e| Error::Io(format!("cannot delete {}", path.display()), e))?;
+                Ok(true)
+            }
+            _ => Ok(removed),
+        }
+    }
+
+    /// Evicts a schedule from memory; it is reloaded from the store on its next use.
+    ///
+    /// # Returns
+    /// * `bool` - Whether the schedule was in memory.
+    ///
+    /// # Errors
+    /// This function will return an error if the registry has no store.
+    pub fn evict(&self, key_id: &str) -> Result<bool, Error> {
+        if self.store.is_none() {
+            return Err(Error::InvalidInput(
+                "cannot evict a key from a registry without a store".to_string(),
+            ));
+        }
+
+        let mut keys = self.keys.lock().expect("key lock poisoned");
+
+        Ok(keys
+            .entries
+            .get_mut(key_id)
+            .and_then(|entry| entry.schedule.take())
+            .is_some())
+    }
+
+    /// Returns the IDs of the registered keys, in memory or in the store.
+    ///
+    /// # Errors
+    /// This function will return an error if the store cannot be listed.
+    pub fn key_ids(&self) -> Result<Vec<String>, Error> {
+        let mut ids: Vec<String> = self
+            .keys
+            .lock()
+            .expect("key lock poisoned")
+            .entries
+            .keys()
+            .cloned()
+            .collect();
+
+        if let Some(dir) = &self.store {
+            let entries = std::fs::read_dir(dir)
+                .map_err(|e| Error::Io(format!("cannot list {}", dir.display()), e))?;
+
+            for entry in entries {
+                let path = entry
+                    .map_err(|e| Error::Io(format!("cannot list {}", dir.display()), e))?
+                    .path();
+                if path.extension().is_some_and(|x| x == "key") {
+                    ids.extend(path.file_stem().and_then(|x| x.to_str()).map(String::from));
+                }
+            }
+        }
+
+        ids.sort();
+        ids.dedup();
+
+        Ok(ids)
+    }
+
+    /// Returns the number of schedules held in memory.
+    pub fn resident(&self) -> usize {
+        self.keys
+            .lock()
+            .expect("key lock poisoned")
+            .entries
+            .values()
+            .filter(|entry| entry.schedule.is_some())
+            .count()
+    }
+
+    /// Returns the schedule of a key and the context of its client, reloading the schedule from
+    /// the store if it was evicted.
+    ///
+    /// # Errors
+    /// This function will return an error if the key or its client is unknown, or if the
+    /// persisted schedule cannot be loaded or was produced with other parameters than the
+    /// client's server key.
+    pub fn get(
+        &self,
+        key_id: &str,
+    ) -> Result<(Arc<FheAesContext>, Arc<KeySchedule<FheUint8>>), Error> {
+        let mut keys = self.keys.lock().expect("key lock poisoned");
+        keys.clock += 1;
+        let clock = keys.clock;
+
+        if let Some((client_id, schedule)) = keys.resident(key_id, clock) {
+            drop(keys);
+
+            return Ok((self.client(&client_id)?, schedule));
+        }
+
+        // Not in memory: reload it from the store, without holding the lock meanwhile so the
+        // requests for other keys are not blocked behind the read
+        let removals = keys.removals;
+        drop(keys);
+
+        let unknown = || Error::InvalidInput(format!("unknown key {:?}", key_id));
+        let path = match self.key_path(key_id) {
+            Some(path) if check_key_id(key_id).is_ok() && path.exists() => path,
+            _ => return Err(unknown()),
+        };
+        let (params, (client_id, bytes)): (ParameterSet, (String, Vec<FheUint8>)) = load(&path)?;
+
+        let client = self.client(&client_id)?;
+        client
+            .params()
+            .check(&format!("Key {:?}", key_id), params)?;
+        let schedule = Arc::new(KeySchedule::from_bytes(bytes)?);
+
+        let mut keys = self.keys.lock().expect("key lock poisoned");
+
+        // Another request may have reloaded or replaced the key in the meantime
+        if let Some((client_id, resident)) = keys.resident(key_id, clock) {
+            drop(keys);
+
+            return Ok((self.client(&client_id)?, resident));
+        }
+
+        // Or removed it: the schedule read from its file must not be brought back
+        if keys.removals != removals && !path.exists() {
+            return Err(unknown());
+        }
+
+        let entry = KeyEntry {
+            client_id,
+            schedule: Some(schedule.clone()),
+            last_used: clock,
+        };
+        keys.entries.insert(key_id.to_string(), entry);
+        self.evict_over_capacity(&mut keys);
+
+        Ok((client, schedule))
+    }
+
+    /// Runs `op` on the schedule of a key, in the context of its client.
+    ///
+    /// # Errors
+    /// This function will return an error if the key cannot be found (see `get`), or the error of
+    /// `op`.
+    pub fn with_key<R: Send>(
+        &self,
+        key_id: &str,
+        op: impl FnOnce(&KeySchedule<FheUint8>) -> Result<R, Error> + Send,
+    ) -> Result<R, Error> {
+        let (client, schedule) = self.get(key_id)?;
+
+        client.install(|| op(&schedule))
+    }
+
+    /// Transciphers an AES-CTR ciphertext with a registered key.
+    ///
+    /// # Returns
+    /// * `Vec<FheUint8>` - The plaintext bytes, encrypted under the key's client FHE key.
+    ///
+    /// # Errors
+    /// This function will return an error if the key cannot be found or a homomorphic operation
+    /// fails.
+    pub fn transcipher(&self, request: &TranscipherRequest) -> Result<Vec<FheUint8>, Error> {
+        self.with_key(&request.key_id, |schedule| {
+            transcipher(
+                &mut AesCtrKeystream::new(schedule, request.iv),
+                &request.ciphertext,
+            )
+        })
+    }
+
+    /// Handles a batch of requests in parallel, each in the context of its key's client.
+    ///
+    /// # Returns
+    /// * `Vec<Result<Vec<FheUint8>, Error>>` - The result of every request, in order.
+    pub fn transcipher_all(
+        &self,
+        requests: &[TranscipherRequest],
+    ) -> Vec<Result<Vec<FheUint8>, Error>> {
+        // The requests are only dispatched from the calling pool; the work runs in the client contexts
+        requests
+            .par_iter()
+            .with_max_len(1)
+            .map(|request| self.transcipher(request))
+            .collect()
+    }
+
+    /// Returns the store file of a key, if the registry has a store.
+    fn key_path(&self, key_id: &str) -> Option<PathBuf> {
+        self.store
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.key", key_id)))
+    }
+
+    /// Evicts the least recently used schedules until at most `capacity` are in memory.
+    fn evict_over_capacity(&self, keys: &mut Keys) {
+        if self.store.is_none() {
+            return;
+        }
+
+        while keys
+            .entries
+            .values()
+            .filter(|x| x.schedule.is_some())
+            .count()
+            > self.capacity
+        {
+            let oldest = keys
+                .entries
+                .values_mut()
+                .filter(|x| x.schedule.is_some())
+                .min_by_key(|x| x.last_used);
+
+            if let Some(entry) = oldest {
+                entry.schedule = None;
+            }
+        }
+    }
+}
+
+/// Returns an error if a key ID cannot be used as a file name.
+fn check_key_id(key_id: &str) -> Result<(), Error> {
+    let valid = !key_id.is_empty()
+        && key_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    match valid {
+        true => Ok(()),
+        false => Err(Error::InvalidInput(format!(
+            "key ID {:?} must be made of ASCII letters, digits, '-' and '_'",
+            key_id
+        ))),
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the key registry.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- registry::tests::multi_key_registry --exact --show-output
+/// ```
+mod tests {
+    use aes::cipher::{BlockEncrypt, KeyInit};
+    use aes::Aes128;
+    use rand::Rng;
+    use tfhe::prelude::*;
+    use tfhe::{generate_keys, ClientKey, ConfigBuilder};
+
+    use super::*;
+    use crate::round_keys::ScheduleMode;
+    use crate::utils::{key_expansion_clear, TestDir};
+
+    /// Encrypts `plaintext` with AES-CTR in the clear.
+    fn aes_ctr(key: &[u8; 16], iv: [u8; 16], plaintext: &[u8]) -> Vec<u8> {
+        let cipher = Aes128::new(key.into());
+        let mut counter = iv;
+        let mut keystream = vec![];
+
+        while keystream.len() < plaintext.len() {
+            let mut block = counter;
+            cipher.encrypt_block((&mut block).into());
+            keystream.extend(block);
+            counter = crate::utils::increment_counter(&counter);
+        }
+
+        plaintext
+            .iter()
+            .zip(keystream)
+            .map(|(p, k)| p ^ k)
+            .collect()
+    }
+
+    /// Encrypts the clear key schedule of `key` under `cks`.
+    fn encrypted_schedule(
+        key: &[u8; 16],
+        cks: &ClientKey,
+        mode: ScheduleMode,
+    ) -> KeySchedule<FheUint8> {
+        let expanded_key = key_expansion_clear(key);
+        let expanded_key_fhe = Box::new(std::array::from_fn(|i| {
+            FheUint8::encrypt(expanded_key[i], cks)
+        }));
+
+        KeySchedule::from_expanded_key(expanded_key_fhe, mode)
+    }
+
+    #[test]
+    fn multi_key_registry() {
+        let mut rng = rand::thread_rng();
+        let dir = TestDir::new("registry");
+
+        // Two clients with their own FHE keys, and three AES keys
+        let config = ConfigBuilder::default().build();
+        let (cks_a, sks_a) = generate_keys(config);
+        let (cks_b, sks_b) = generate_keys(config);
+
+        let registry = KeyRegistry::new(2).with_store(&dir).unwrap();
+        registry
+            .add_client("alice", ParameterSet::Default, sks_a, 0)
+            .unwrap();
+        registry
+            .add_client("bob", ParameterSet::Default, sks_b.clone(), 0)
+            .unwrap();
+
+        let keys: [[u8; 16]; 3] = rng.gen();
+        let owners = [("alice", &cks_a), ("alice", &cks_a), ("bob", &cks_b)];
+        let modes = [
+            ScheduleMode::Cached,
+            ScheduleMode::OnTheFly,
+            ScheduleMode::Cached,
+        ];
+
+        for (i, ((client_id, cks), mode)) in owners.iter().zip(modes).enumerate() {
+            let schedule = encrypted_schedule(&keys[i], cks, mode);
+            registry
+                .insert_key(&format!("key-{}", i), client_id, schedule)
+                .unwrap();
+        }
+
+        // The least recently used schedule was evicted, and every key is still listed
+        assert_eq!(registry.resident(), 2);
+        assert_eq!(registry.key_ids().unwrap(), vec!["key-0", "key-1", "key-2"]);
+        assert!(registry
+            .insert_key(
+                "../key",
+                "alice",
+                encrypted_schedule(&keys[0], &cks_a, ScheduleMode::Cached)
+            )
+            .is_err());
+        assert!(registry.get("key-3").is_err());
+
+        // The requests for different clients' keys run in parallel, each with its client's key
+        let requests: Vec<TranscipherRequest> = (0..3)
+            .map(|i| TranscipherRequest {
+                key_id: format!("key-{}", i),
+                iv: rng.gen(),
+                ciphertext: (0..4).map(|_| rng.gen()).collect(),
+            })
+            .collect();
+        let results = registry.transcipher_all(&requests);
+
+        for (i, (request, result)) in requests.iter().zip(results).enumerate() {
+            let plaintext: Vec<u8> = result
+                .unwrap()
+                .iter()
+                .map(|x| x.decrypt(owners[i].1))
+                .collect();
+            assert_eq!(
+                plaintext,
+                aes_ctr(&keys[i], request.iv, &request.ciphertext)
+            );
+        }
+
+        // A restarted registry reloads the schedules from the store
+        drop(registry);
+        let registry = KeyRegistry::new(1).with_store(&dir).unwrap();
+        assert!(registry.get("key-2").is_err());
+
+        registry
+            .add_client("bob", ParameterSet::Default, sks_b, 0)
+            .unwrap();
+        assert_eq!(
+            registry.get("key-2").unwrap().1.mode(),
+            ScheduleMode::Cached
+        );
+        assert!(registry.remove_key("key-2").unwrap());
+        assert!(registry.get("key-2").is_err());
+    }
+}
@@ -0,0 +1,180 @@
+/// This module implements the machine-readable reports of the `run` subcommand.
+/// A report records the run parameters, the per-phase and per-block durations in milliseconds and
+/// the verification results, so performance regressions can be tracked over time.
+/// It includes the following items:
+/// - `ReportFormat`: The report formats exposed on the command line (JSON or CSV).
+/// - `RunReport` / `BlockReport`: The records of a run and of each of its blocks.
+/// - `millis`: Converts a `Duration` to fractional milliseconds.
+///
+/// The JSON report is a single object with a `blocks` array. The CSV report has one row per block,
+/// with the run-level columns repeated on every row.
+use crate::noise::RoundNoise;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::Write;
+use std::time::Duration;
+
+/// The formats in which a run report can be written.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// A single JSON object.
+    Json,
+    /// A CSV table with one row per block.
+    Csv,
+}
+
+/// The timings and verification result of a single 16-byte block.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BlockReport {
+    /// The index of the block, starting at 0.
+    pub index: usize,
+    /// The time spent encrypting (or transciphering) the block.
+    pub encryption_ms: f64,
+    /// The time spent decrypting the block, if the cipher was run in both directions.
+    pub decryption_ms: Option<f64>,
+    /// Whether the block matched the clear reference implementation.
+    pub verified: bool,
+}
+
+/// The record of a whole run.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RunReport {
+    /// The start of the run, in RFC 3339 format.
+    pub timestamp: String,
+    /// The cipher that was evaluated.
+    pub cipher: String,
+    /// The TFHE parameter set of the keys.
+    pub params: String,
+    /// The number of 16-byte blocks processed.
+    pub number_of_outputs: u32,
+    /// The number of threads in the rayon pool.
+    pub threads: usize,
+    /// The time spent generating the client and server keys.
+    pub keygen_ms: f64,
+    /// The time spent expanding the AES key; absent for the stream ciphers.
+    pub key_expansion_ms: Option<f64>,
+    /// The total time spent encrypting (or transciphering) the blocks.
+    pub encryption_ms: f64,
+    /// The total time spent decrypting the blocks; absent for the stream ciphers.
+    pub decryption_ms: Option<f64>,
+    /// Whether every block matched the clear reference implementation.
+    pub verified: bool,
+    /// The per-block records.
+    pub blocks: Vec<BlockReport>,
+    /// The worst noise margin of each AES round over all the blocks, with `--noise`. It is only
+    /// written to JSON reports.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub noise: Option<Vec<RoundNoise>>,
+}
+
+/// Converts `duration` to fractional milliseconds.
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Formats an optional duration as a CSV field, empty when absent.
+fn csv_field(value: Option<f64>) -> String {
+    value.map(|x| x.to_string()).unwrap_or_default()
+}
+
+impl RunReport {
+    /// Writes the report to `writer` in the given `format`.
+    ///
+    /// # Errors
+    /// This function will return an error if writing fails.
+    pub fn write(&self, format: ReportFormat, writer: &mut impl Write) -> std::io::Result<()> {
+        match format {
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(&mut *writer, self)?;
+                writeln!(writer)
+            }
+            ReportFormat::Csv => {
+                writeln!(
+                    writer,
+                    "timestamp,cipher,params,number_of_outputs,threads,keygen_ms,key_expansion_ms,\
+                     encryption_ms,decryption_ms,verified,block,block_encryption_ms,\
+                     block_decryption_ms,block_verified"
+                )?;
+
+                for block in &self.blocks {
+                    writeln!(
+                        writer,
+                        "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                        self.timestamp,
+                        self.cipher,
+                        self.params,
+                        self.number_of_outputs,
+                        self.threads,
+                        self.keygen_ms,
+                        csv_field(self.key_expansion_ms),
+                        self.encryption_ms,
+                        csv_field(self.decryption_ms),
+                        self.verified,
+                        block.index,
+                        block.encryption_ms,
+                        csv_field(block.decryption_ms),
+                        block.verified
+                    )?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the run reports.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- report::tests::report_formats --exact --show-output
+/// ```
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_formats() {
+        let report = RunReport {
+            timestamp: "2025-01-01T00:00:00+00:00".to_string(),
+            cipher: "Aes".to_string(),
+            params: "default".to_string(),
+            number_of_outputs: 2,
+            threads: 4,
+            keygen_ms: 1.5,
+            key_expansion_ms: Some(2.0),
+            encryption_ms: 3.0,
+            decryption_ms: None,
+            verified: true,
+            blocks: (0..2)
+                .map(|index| BlockReport {
+                    index,
+                    encryption_ms: 1.5,
+                    decryption_ms: None,
+                    verified: true,
+                })
+                .collect(),
+            noise: None,
+        };
+
+        let mut json = vec![];
+        report.write(ReportFormat::Json, &mut json).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["threads"], 4);
+        assert_eq!(value["decryption_ms"], serde_json::Value::Null);
+        assert_eq!(value["blocks"].as_array().unwrap().len(), 2);
+
+        let mut csv = vec![];
+        report.write(ReportFormat::Csv, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[1],
+            "2025-01-01T00:00:00+00:00,Aes,default,2,4,1.5,2,3,,true,0,1.5,,true"
+        );
+    }
+}
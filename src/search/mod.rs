@@ -0,0 +1,269 @@
+/// This module implements encrypted search over transciphered data: encrypted bytes are compared
+/// with an encrypted pattern, and the result stays encrypted.
+/// It includes the following items:
+/// - `equals`: Whether the data equals the pattern, as an encrypted flag.
+/// - `match_positions`: An encrypted flag per offset, set where the pattern occurs.
+/// - `contains` / `count_matches`: Whether the pattern occurs, and how many times.
+/// - `search`: One of the above, selected by a `SearchMode`, as a `SearchResult`.
+/// - `search_aes_ctr`: The whole pipeline, from an AES-CTR ciphertext to an encrypted search result.
+///
+/// The lengths of the data and of the pattern are public, since they are the numbers of
+/// ciphertexts: comparisons that the lengths alone decide return a trivial encryption without any
+/// homomorphic operation.
+use crate::error::Error;
+use crate::round_keys::KeySchedule;
+use crate::transciphering::{transcipher, AesCtrKeystream};
+use clap::ValueEnum;
+use rayon::prelude::*;
+use std::fmt;
+use tfhe::prelude::*;
+use tfhe::{FheBool, FheUint32, FheUint8};
+
+/// What a search computes.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SearchMode {
+    /// Whether the data equals the pattern, as a flag.
+    Equals,
+    /// Whether the pattern occurs in the data, as a flag.
+    #[default]
+    Contains,
+    /// The number of occurrences of the pattern in the data, overlapping ones included.
+    Count,
+}
+
+impl fmt::Display for SearchMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.to_possible_value().expect("no skipped variants");
+        write!(f, "{}", value.get_name())
+    }
+}
+
+/// An encrypted search result.
+pub enum SearchResult {
+    /// The encrypted match flag of `Equals` and `Contains`.
+    Flag(FheBool),
+    /// The encrypted number of matches of `Count`.
+    Count(FheUint32),
+}
+
+impl SearchResult {
+    /// Encodes the result as encrypted bytes, like the other artifacts: the flag as one byte
+    /// (0 or 1), the count as 4 big-endian bytes.
+    pub fn to_bytes(&self) -> Vec<FheUint8> {
+        match self {
+            SearchResult::Flag(flag) => vec![FheUint8::cast_from(flag.clone())],
+            SearchResult::Count(count) => [24u32, 16, 8, 0]
+                .par_iter()
+                .map(|shift| FheUint8::cast_from(count >> *shift))
+                .collect(),
+        }
+    }
+}
+
+/// Compares encrypted data with an encrypted pattern.
+///
+/// # Returns
+/// * `FheBool` - The encrypted equality flag; a trivial `false` if the lengths differ.
+pub fn equals(data: &[FheUint8], pattern: &[FheUint8]) -> FheBool {
+    if data.len() != pattern.len() {
+        return FheBool::encrypt_trivial(false);
+    }
+
+    data.par_iter()
+        .zip(pattern.par_iter())
+        .map(|(x, y)| x.eq(y))
+        .reduce_with(|a, b| a & b)
+        .unwrap_or_else(|| FheBool::encrypt_trivial(true))
+}
+
+/// Looks for an encrypted pattern at every offset of encrypted data.
+///
+/// # Returns
+/// * `Vec<FheBool>` - One encrypted flag per offset at which the pattern fits, set where it occurs.
+///
+/// # Errors
+/// This function will return an error if the pattern is empty.
+pub fn match_positions(data: &[FheUint8], pattern: &[FheUint8]) -> Result<Vec<FheBool>, Error> {
+    if pattern.is_empty() {
+        return Err(Error::InvalidInput(
+            "the search pattern must not be empty".to_string(),
+        ));
+    }
+    if pattern.len() > data.len() {
+        return Ok(vec![]);
+    }
+
+    Ok((0..=data.len() - pattern.len())
+        .into_par_iter()
+        .map(|offset| equals(&data[offset..offset + pattern.len()], pattern))
+        .collect())
+}
+
+/// Checks whether an encrypted pattern occurs in encrypted data.
+///
+/// # Returns
+/// * `FheBool` - The encrypted match flag; a trivial `false` if the pattern is longer than the data.
+///
+/// # Errors
+/// This function will return an error if the pattern is empty.
+pub fn contains(data: &[FheUint8], pattern: &[FheUint8]) -> Result<FheBool, Error> {
+    Ok(match_positions(data, pattern)?
+        .into_par_iter()
+        .reduce_with(|a, b| a | b)
+        .unwrap_or_else(|| FheBool::encrypt_trivial(false)))
+}
+
+/// Counts the occurrences of an encrypted pattern in encrypted data, overlapping ones included.
+///
+/// # Returns
+/// * `FheUint32` - The encrypted number of occurrences; a trivial 0 if the pattern is longer than
+///   the data.
+///
+/// # Errors
+/// This function will return an error if the pattern is empty.
+pub fn count_matches(data: &[FheUint8], pattern: &[FheUint8]) -> Result<FheUint32, Error> {
+    Ok(match_positions(data, pattern)?
+        .into_par_iter()
+        .map(FheUint32::cast_from)
+        .reduce_with(|a, b| a + b)
+        .unwrap_or_else(|| FheUint32::encrypt_trivial(0u32)))
+}
+
+/// Searches encrypted data for an encrypted pattern.
+///
+/// # Errors
+/// This function will return an error if the pattern is empty.
+pub fn search(
+    data: &[FheUint8],
+    pattern: &[FheUint8],
+    mode: SearchMode,
+) -> Result<SearchResult, Error> {
+    match mode {
+        SearchMode::Equals => Ok(SearchResult::Flag(equals(data, pattern))),
+        SearchMode::Contains => Ok(SearchResult::Flag(contains(data, pattern)?)),
+        SearchMode::Count => Ok(SearchResult::Count(count_matches(data, pattern)?)),
+    }
+}
+
+/// Transciphers an AES-CTR ciphertext and searches the encrypted plaintext for an encrypted
+/// pattern: the server never sees the data, the pattern or the result in the clear.
+///
+/// # Arguments
+/// * `schedule` - The encrypted key schedule of the AES key.
+/// * `iv` - The initial counter block.
+/// * `ciphertext` - The AES-CTR ciphertext.
+/// * `pattern` - The pattern, encrypted under the same client key as the schedule.
+/// * `mode` - What the search computes.
+///
+/// # Errors
+/// This function will return an error if the pattern is empty or a homomorphic operation fails.
+pub fn search_aes_ctr(
+    schedule: &KeySchedule<FheUint8>,
+    iv: [u8; 16],
+    ciphertext: &[u8],
+    pattern: &[FheUint8],
+    mode: SearchMode,
+) -> Result<SearchResult, Error> {
+    // Checked before the expensive transciphering
+    if pattern.is_empty() {
+        return Err(Error::InvalidInput(
+            "the search pattern must not be empty".to_string(),
+        ));
+    }
+
+    let data = transcipher(&mut AesCtrKeystream::new(schedule, iv), ciphertext)?;
+
+    search(&data, pattern, mode)
+}
+
+#[cfg(test)]
+/// This module contains tests for the encrypted search.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following command:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- search::tests::encrypted_search --exact --show-output
+/// ```
+mod tests {
+    use aes::cipher::{BlockEncrypt, KeyInit};
+    use aes::Aes128;
+    use rand::Rng;
+    use tfhe::prelude::*;
+
+    use super::*;
+    use crate::context::FheAesContext;
+    use crate::params::ParameterSet;
+    use crate::round_keys::ScheduleMode;
+    use crate::utils::{increment_counter, key_expansion_clear};
+
+    #[test]
+    fn encrypted_search() {
+        let mut rng = rand::thread_rng();
+        let (cks, context) = FheAesContext::generate(ParameterSet::Default, 0).unwrap();
+        let encrypt = |bytes: &[u8]| -> Vec<FheUint8> {
+            bytes.iter().map(|x| FheUint8::encrypt(*x, &cks)).collect()
+        };
+
+        let data = encrypt(b"abcabcab");
+        let (abc, abd, cab) = (encrypt(b"abc"), encrypt(b"abd"), encrypt(b"cab"));
+
+        context.install(|| {
+            assert!(equals(&data, &data).decrypt(&cks));
+            assert!(!equals(&data[..3], &abd).decrypt(&cks));
+            assert!(!equals(&data, &abc).decrypt(&cks));
+
+            let positions: Vec<bool> = match_positions(&data, &abc)
+                .unwrap()
+                .iter()
+                .map(|x| x.decrypt(&cks))
+                .collect();
+            assert_eq!(positions, [true, false, false, true, false, false]);
+
+            assert!(contains(&data, &cab).unwrap().decrypt(&cks));
+            assert!(!contains(&data, &abd).unwrap().decrypt(&cks));
+            assert!(!contains(&abc, &data).unwrap().decrypt(&cks));
+            assert!(contains(&data, &[]).is_err());
+
+            let count: u32 = count_matches(&data, &abc).unwrap().decrypt(&cks);
+            assert_eq!(count, 2);
+
+            let bytes: Vec<u8> = SearchResult::Count(count_matches(&data, &cab).unwrap())
+                .to_bytes()
+                .iter()
+                .map(|x| x.decrypt(&cks))
+                .collect();
+            assert_eq!(bytes, [0, 0, 0, 2]);
+        });
+
+        // AES ciphertext in, encrypted search result out
+        let key: [u8; 16] = rng.gen();
+        let iv: [u8; 16] = rng.gen();
+        let mut ciphertext = b"id=4711;name=eve;".to_vec();
+        let mut counter = iv;
+        for chunk in ciphertext.chunks_mut(16) {
+            let mut block = counter;
+            Aes128::new((&key).into()).encrypt_block((&mut block).into());
+            chunk.iter_mut().zip(block).for_each(|(c, k)| *c ^= k);
+            counter = increment_counter(&counter);
+        }
+
+        let expanded_key = key_expansion_clear(&key);
+        let schedule = KeySchedule::from_expanded_key(
+            Box::new(std::array::from_fn(|i| {
+                FheUint8::encrypt(expanded_key[i], &cks)
+            })),
+            ScheduleMode::Cached,
+        );
+        let pattern = encrypt(b"4711");
+
+        let result = context.install(|| {
+            search_aes_ctr(&schedule, iv, &ciphertext, &pattern, SearchMode::Contains).unwrap()
+        });
+        match result {
+            SearchResult::Flag(flag) => assert!(flag.decrypt(&cks)),
+            SearchResult::Count(_) => panic!("contains returns a flag"),
+        }
+    }
+}
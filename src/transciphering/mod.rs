@@ -0,0 +1,128 @@
+/// This module defines the transciphering interface shared by every cipher in the crate.
+/// Transciphering turns data encrypted with a symmetric cipher into FHE ciphertexts: the server
+/// evaluates the keystream under FHE with the encrypted symmetric key and XORs it with the clear
+/// symmetric ciphertext, which leaves the plaintext encrypted under FHE.
+/// It includes the following items:
+/// - `KeystreamGenerator`: Produces encrypted keystream bytes; implemented by AES-CTR, Trivium, Kreyvium and ChaCha20.
+/// - `AesCtrKeystream`: AES in counter mode on top of `aes_encrypt_block_with_schedule`.
+/// - `transcipher`: XORs a symmetric ciphertext with the encrypted keystream of any generator.
+/// - `Cipher`: The cipher selection exposed on the command line.
+use crate::aes_encrypt_block_with_schedule;
+use crate::error::Error;
+use crate::round_keys::KeySchedule;
+use crate::utils::increment_counter;
+use clap::ValueEnum;
+use rayon::prelude::*;
+use tfhe::prelude::*;
+use tfhe::FheUint8;
+
+/// A stream of encrypted keystream bytes.
+pub trait KeystreamGenerator {
+    /// Returns the next `len` encrypted keystream bytes.
+    ///
+    /// # Errors
+    /// This function will return an error if a homomorphic operation fails.
+    fn next_keystream(&mut self, len: usize) -> Result<Vec<FheUint8>, Error>;
+}
+
+impl<G: KeystreamGenerator + ?Sized> KeystreamGenerator for Box<G> {
+    fn next_keystream(&mut self, len: usize) -> Result<Vec<FheUint8>, Error> {
+        (**self).next_keystream(len)
+    }
+}
+
+/// The symmetric ciphers that can be used for transciphering.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cipher {
+    /// AES-128 in counter mode.
+    Aes,
+    /// Trivium, with an 80-bit key and IV.
+    Trivium,
+    /// Kreyvium, with a 128-bit key and IV.
+    Kreyvium,
+    /// ChaCha20, with a 256-bit key, a 32-bit block counter and a 96-bit nonce.
+    Chacha20,
+}
+
+impl Cipher {
+    /// Returns the length in bytes of the key expected on the command line.
+    pub fn key_len(&self) -> usize {
+        match self {
+            Cipher::Chacha20 => 32,
+            _ => 16,
+        }
+    }
+}
+
+/// AES-128 in counter mode, using the encrypted key schedule and a public initial counter block.
+pub struct AesCtrKeystream<'a> {
+    schedule: &'a KeySchedule<FheUint8>,
+    /// The next counter block to encrypt.
+    counter: [u8; 16],
+    /// Keystream bytes produced but not yet returned.
+    buffer: Vec<FheUint8>,
+}
+
+impl<'a> AesCtrKeystream<'a> {
+    /// Creates an AES-CTR keystream starting at the counter block `iv`.
+    ///
+    /// # Arguments
+    /// * `schedule` - The encrypted AES key schedule, cached or generated on the fly.
+    /// * `iv` - The initial counter block, incremented as a 128-bit big-endian integer.
+    pub fn new(schedule: &'a KeySchedule<FheUint8>, iv: [u8; 16]) -> Self {
+        Self {
+            schedule,
+            counter: iv,
+            buffer: vec![],
+        }
+    }
+}
+
+impl KeystreamGenerator for AesCtrKeystream<'_> {
+    fn next_keystream(&mut self, len: usize) -> Result<Vec<FheUint8>, Error> {
+        while self.buffer.len() < len {
+            // The counter is public, so it is encrypted trivially
+            let input: Vec<FheUint8> = self
+                .counter
+                .iter()
+                .map(|x| FheUint8::encrypt_trivial(*x))
+                .collect();
+
+            let mut output: [FheUint8; 16] =
+                std::array::from_fn(|_| FheUint8::encrypt_trivial(0u8));
+            aes_encrypt_block_with_schedule(&input, &mut output, self.schedule)?;
+
+            self.buffer.extend(output);
+            self.counter = increment_counter(&self.counter);
+        }
+
+        Ok(self.buffer.drain(..len).collect())
+    }
+}
+
+/// Transciphers a symmetric ciphertext into FHE ciphertexts of the plaintext.
+///
+/// # Arguments
+/// * `generator` - The encrypted keystream of the cipher that produced `ciphertext`.
+/// * `ciphertext` - The clear symmetric ciphertext bytes.
+///
+/// # Returns
+/// * `Vec<FheUint8>` - The plaintext bytes encrypted under FHE.
+///
+/// # Behavior
+/// - Each keystream byte is XORed with the matching ciphertext byte in parallel.
+///
+/// # Errors
+/// This function will return an error if the keystream cannot be generated.
+pub fn transcipher(
+    generator: &mut impl KeystreamGenerator,
+    ciphertext: &[u8],
+) -> Result<Vec<FheUint8>, Error> {
+    let keystream = generator.next_keystream(ciphertext.len())?;
+
+    Ok(keystream
+        .into_par_iter()
+        .zip(ciphertext.par_iter())
+        .map(|(k, c)| k ^ *c)
+        .collect())
+}
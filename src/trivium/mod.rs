@@ -0,0 +1,314 @@
+/// This module implements the Trivium stream cipher using Fully Homomorphic Encryption (FHE).
+/// Trivium only needs XOR and AND on single bits, which makes it much cheaper than AES under TFHE,
+/// and it is commonly used for transciphering in the tfhe-rs ecosystem.
+/// It includes the following items:
+/// - `StreamBit`: The bit type the cipher runs on, implemented for `bool` (clear) and `FheBool` (encrypted).
+/// - `TriviumStream`: The keystream generator, generic over `StreamBit`.
+/// - `Registers`: The 288-bit shift register shared with the `kreyvium` module.
+///
+/// The three registers of Trivium are only tapped at least 64 positions away from their input, so
+/// 64 consecutive clock cycles are independent of each other and are computed in parallel.
+use crate::error::{check_len, Error};
+use crate::transciphering::KeystreamGenerator;
+use rayon::prelude::*;
+use tfhe::prelude::*;
+use tfhe::{FheBool, FheUint8};
+
+/// Number of clock cycles computed in parallel by [`Registers::clock_64`].
+pub(crate) const BATCH: usize = 64;
+
+/// Number of warm-up clock cycles (4 full cycles of the 288-bit state).
+pub(crate) const WARM_UP: usize = 4 * 288;
+
+/// A single bit of the cipher state, either in the clear or encrypted.
+pub trait StreamBit: Clone + Send + Sync {
+    /// The byte type used for keys, IVs and the keystream.
+    type Byte: Clone + Send + Sync;
+
+    /// Returns the constant bit `value` (a trivial encryption for FHE bits).
+    fn constant(value: bool) -> Self;
+
+    /// XOR of two bits (addition in GF(2)).
+    fn xor(&self, other: &Self) -> Self;
+
+    /// AND of two bits (multiplication in GF(2)).
+    fn and(&self, other: &Self) -> Self;
+
+    /// Splits a byte into its 8 bits, least significant bit first.
+    fn unpack_byte(byte: &Self::Byte) -> [Self; 8];
+
+    /// Packs 8 bits, least significant bit first, into a byte.
+    fn pack_byte(bits: &[Self]) -> Self::Byte;
+}
+
+impl StreamBit for bool {
+    type Byte = u8;
+
+    fn constant(value: bool) -> Self {
+        value
+    }
+
+    fn xor(&self, other: &Self) -> Self {
+        self ^ other
+    }
+
+    fn and(&self, other: &Self) -> Self {
+        self & other
+    }
+
+    fn unpack_byte(byte: &u8) -> [Self; 8] {
+        std::array::from_fn(|i| (byte >> i) & 1 == 1)
+    }
+
+    fn pack_byte(bits: &[Self]) -> u8 {
+        bits.iter()
+            .enumerate()
+            .fold(0u8, |byte, (i, bit)| byte | ((*bit as u8) << i))
+    }
+}
+
+impl StreamBit for FheBool {
+    type Byte = FheUint8;
+
+    fn constant(value: bool) -> Self {
+        FheBool::encrypt_trivial(value)
+    }
+
+    fn xor(&self, other: &Self) -> Self {
+        self ^ other
+    }
+
+    fn and(&self, other: &Self) -> Self {
+        self & other
+    }
+
+    fn unpack_byte(byte: &FheUint8) -> [Self; 8] {
+        std::array::from_fn(|i| ((byte >> i as u8) & 1u8).eq(1u8))
+    }
+
+    fn pack_byte(bits: &[Self]) -> FheUint8 {
+        bits.iter()
+            .enumerate()
+            .fold(FheUint8::encrypt_trivial(0u8), |byte, (i, bit)| {
+                byte | (FheUint8::cast_from(bit.clone()) << i as u8)
+            })
+    }
+}
+
+/// Loads key or IV bytes as cipher bits, in the order of the eSTREAM Trivium reference code.
+///
+/// The bits of all bytes are taken least significant bit first and the whole sequence is reversed,
+/// so the most significant bit of the last byte becomes the first bit of the register.
+pub fn load_bits<T: StreamBit>(bytes: &[T::Byte]) -> Vec<T> {
+    let mut bits: Vec<T> = bytes.iter().flat_map(T::unpack_byte).collect();
+    bits.reverse();
+
+    bits
+}
+
+/// The 288-bit state of Trivium, split into its three shift registers.
+///
+/// `a[i]` holds s_(i+1), `b[i]` holds s_(94+i) and `c[i]` holds s_(178+i) in the notation of the
+/// Trivium specification.
+pub struct Registers<T> {
+    a: Vec<T>,
+    b: Vec<T>,
+    c: Vec<T>,
+}
+
+impl<T: StreamBit> Registers<T> {
+    /// Builds the registers from their initial contents, padding each one with zeros.
+    ///
+    /// # Panics
+    /// - The function will panic if a register is given more bits than it holds (93, 84 and 111).
+    pub fn new(a: Vec<T>, b: Vec<T>, c: Vec<T>) -> Self {
+        let pad = |mut register: Vec<T>, len: usize| {
+            assert!(register.len() <= len, "Too many bits for the register");
+            register.resize(len, T::constant(false));
+            register
+        };
+
+        Self {
+            a: pad(a, 93),
+            b: pad(b, 84),
+            c: pad(c, 111),
+        }
+    }
+
+    /// Clocks the registers 64 times and returns the 64 output bits.
+    ///
+    /// # Arguments
+    /// * `extra` - For clock cycle `j` of the batch, returns the bits XORed into t3 and into the
+    ///   updated t1. Trivium uses constant zeros; Kreyvium injects its key and IV registers here.
+    ///
+    /// # Behavior
+    /// - After `j` shifts, position `i` of a register holds its original bit `i - j`. All taps are at
+    ///   position 65 or later, so every cycle of the batch reads only original bits.
+    /// - The 64 cycles are computed in parallel using `into_par_iter()`.
+    pub fn clock_64(&mut self, extra: impl Fn(usize) -> (Option<T>, Option<T>) + Sync) -> Vec<T> {
+        let (a, b, c) = (&self.a, &self.b, &self.c);
+
+        let cycles: Vec<(T, T, T, T)> = (0..BATCH)
+            .into_par_iter()
+            .map(|j| {
+                let (key_bit, iv_bit) = extra(j);
+
+                let t1 = a[65 - j].xor(&a[92 - j]);
+                let t2 = b[68 - j].xor(&b[83 - j]);
+                let mut t3 = c[65 - j].xor(&c[110 - j]);
+                if let Some(key_bit) = &key_bit {
+                    t3 = t3.xor(key_bit);
+                }
+
+                let z = t1.xor(&t2).xor(&t3);
+
+                let mut t1 = t1.xor(&a[90 - j].and(&a[91 - j])).xor(&b[77 - j]);
+                if let Some(iv_bit) = &iv_bit {
+                    t1 = t1.xor(iv_bit);
+                }
+                let t2 = t2.xor(&b[81 - j].and(&b[82 - j])).xor(&c[86 - j]);
+                let t3 = t3.xor(&c[108 - j].and(&c[109 - j])).xor(&a[68 - j]);
+
+                (z, t3, t1, t2)
+            })
+            .collect();
+
+        // The newest bit ends up at position 0 of each register
+        let shift = |register: &mut Vec<T>, new_bits: Vec<T>| {
+            let len = register.len();
+            let mut shifted: Vec<T> = new_bits.into_iter().rev().collect();
+            shifted.extend_from_slice(&register[..len - BATCH]);
+            *register = shifted;
+        };
+
+        let mut output = Vec::with_capacity(BATCH);
+        let (mut new_a, mut new_b, mut new_c) = (vec![], vec![], vec![]);
+        for (z, t3, t1, t2) in cycles {
+            output.push(z);
+            new_a.push(t3);
+            new_b.push(t1);
+            new_c.push(t2);
+        }
+        shift(&mut self.a, new_a);
+        shift(&mut self.b, new_b);
+        shift(&mut self.c, new_c);
+
+        output
+    }
+}
+
+/// The Trivium keystream generator over clear or encrypted bits.
+pub struct TriviumStream<T: StreamBit> {
+    registers: Registers<T>,
+    /// Output bits produced but not yet returned as bytes.
+    buffer: Vec<T>,
+}
+
+impl<T: StreamBit> TriviumStream<T> {
+    /// Initializes Trivium with an 80-bit key and an 80-bit IV and runs the 1152 warm-up cycles.
+    ///
+    /// # Arguments
+    /// * `key` - The 10 key bytes, usually encrypted.
+    /// * `iv` - The 10 IV bytes, usually public and trivially encrypted.
+    ///
+    /// # Errors
+    /// This function will return an error if the key or the IV is not 10 bytes long.
+    pub fn new(key: &[T::Byte], iv: &[T::Byte]) -> Result<Self, Error> {
+        check_len("Trivium key", 10, key.len())?;
+        check_len("Trivium IV", 10, iv.len())?;
+
+        // The last three bits of the third register are set to 1
+        let mut c = vec![T::constant(false); 108];
+        c.extend([T::constant(true), T::constant(true), T::constant(true)]);
+
+        let mut registers = Registers::new(load_bits::<T>(key), load_bits::<T>(iv), c);
+        for _ in 0..WARM_UP / BATCH {
+            registers.clock_64(|_| (None, None));
+        }
+
+        Ok(Self {
+            registers,
+            buffer: vec![],
+        })
+    }
+
+    /// Returns the next `len` keystream bytes.
+    pub fn next_bytes(&mut self, len: usize) -> Vec<T::Byte> {
+        while self.buffer.len() < len * 8 {
+            let bits = self.registers.clock_64(|_| (None, None));
+            self.buffer.extend(bits);
+        }
+
+        let bits: Vec<T> = self.buffer.drain(..len * 8).collect();
+        bits.par_chunks(8).map(T::pack_byte).collect()
+    }
+}
+
+impl KeystreamGenerator for TriviumStream<FheBool> {
+    fn next_keystream(&mut self, len: usize) -> Result<Vec<FheUint8>, Error> {
+        Ok(self.next_bytes(len))
+    }
+}
+
+#[cfg(test)]
+/// This module contains tests for the Trivium stream cipher.
+///
+/// # Usage
+///
+/// To run the tests with --release flag, use the following commands:
+///
+/// ```sh
+/// cargo test --release --package fhe-aes128 --lib -- trivium::tests::trivium_clear --exact --show-output
+/// cargo test --release --package fhe-aes128 --lib -- trivium::tests::trivium_fhe --exact --show-output
+/// ```
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::context::FheAesContext;
+    use crate::params::ParameterSet;
+    use crate::utils::hex_to_bytes;
+
+    #[test]
+    fn trivium_clear() {
+        // Key and IV set to zero
+        let mut trivium = TriviumStream::<bool>::new(&[0u8; 10], &[0u8; 10]).unwrap();
+        assert_eq!(
+            trivium.next_bytes(16),
+            hex_to_bytes("FBE0BF265859051B517A2E4E239FC97F").unwrap()
+        );
+
+        // eSTREAM Set 1, vector 0
+        let key = hex_to_bytes("80000000000000000000").unwrap();
+        let mut trivium = TriviumStream::<bool>::new(&key, &[0u8; 10]).unwrap();
+        assert_eq!(
+            trivium.next_bytes(16),
+            hex_to_bytes("38EB86FF730D7A9CAF8DF13A4420540D").unwrap()
+        );
+    }
+
+    #[test]
+    fn trivium_fhe() {
+        let mut rng = rand::thread_rng();
+        let key: [u8; 10] = rng.gen();
+        let iv: [u8; 10] = rng.gen();
+
+        let (cks, context) = FheAesContext::generate(ParameterSet::Default, 0).unwrap();
+
+        context.install(|| {
+            let key_fhe: Vec<FheUint8> = key.iter().map(|x| FheUint8::encrypt(*x, &cks)).collect();
+            let iv_fhe: Vec<FheUint8> = iv.iter().map(|x| FheUint8::encrypt_trivial(*x)).collect();
+
+            let mut expected = TriviumStream::<bool>::new(&key, &iv).unwrap();
+            let mut trivium = TriviumStream::<FheBool>::new(&key_fhe, &iv_fhe).unwrap();
+
+            let result: Vec<u8> = trivium
+                .next_keystream(16)
+                .unwrap()
+                .iter()
+                .map(|x| x.decrypt(&cks))
+                .collect();
+            assert_eq!(result, expected.next_bytes(16));
+        });
+    }
+}
@@ -1,3 +1,6 @@
+use crate::error::Error;
+use zeroize::Zeroizing;
+
 pub const SBOX: [u8; 256] = [
     0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
     0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
@@ -36,6 +39,20 @@ pub const INV_SBOX: [u8; 256] = [
     0x17, 0x2b, 0x04, 0x7e, 0xba, 0x77, 0xd6, 0x26, 0xe1, 0x69, 0x14, 0x63, 0x55, 0x21, 0x0c, 0x7d,
 ];
 
+/// Round constants (RCON) used in AES key expansion.
+/// These constants are used in the key schedule core function to introduce non-linearity
+/// and ensure key uniqueness across different rounds.
+///
+/// The values follow the AES key expansion specification:
+/// - RCON[0] is unused (0x00).
+/// - RCON[1] to RCON[10] correspond to the first 10 rounds of AES key expansion.
+/// - Each value is derived from powers of 2 in the finite field GF(2^8).
+///
+/// These constants help in the generation of round keys, ensuring cryptographic security.
+pub const R_CONSTANTS: [u8; 11] = [
+    0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1B, 0x36,
+];
+
 /// Converts a hexadecimal string slice to a vector of u8 values.
 ///
 /// # Arguments
@@ -47,32 +64,86 @@ pub const INV_SBOX: [u8; 256] = [
 /// A `Result` which is:
 ///
 /// * `Ok(Vec<u8>)` containing the byte values if the conversion is successful.
-/// * `Err(&'static str)` if the input string contains invalid hexadecimal characters or is of odd length.
+/// * `Err(Error::InvalidInput)` if the input string contains invalid hexadecimal characters or does not hold 16 bytes.
 ///
 /// # Errors
 ///
 /// This function will return an error if the input string contains characters that are not valid hexadecimal digits
-/// or if the length of the string is not even.
+/// or separators, or if it does not hold exactly 32 hexadecimal digits.
 ///
 /// # Examples
 ///
 /// ```
-/// let hex_str = "48656c6c6f";
+/// # use fhe_aes128::utils::hex_to_u8_array;
+/// let hex_str = "000102030405060708090a0b0c0d0e0f";
 /// let bytes = hex_to_u8_array(hex_str).unwrap();
+/// assert_eq!(bytes, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+/// ```
+pub fn hex_to_u8_array(hex: &str) -> Result<[u8; 16], Error> {
+    let bytes = Zeroizing::new(hex_to_bytes(hex)?);
+
+    if bytes.len() != 16 {
+        return Err(Error::InvalidInput(format!(
+            "hex string must hold 32 digits for 128 bits, got {}",
+            bytes.len() * 2
+        )));
+    }
+
+    Ok(std::array::from_fn(|i| bytes[i]))
+}
+
+/// Converts a hexadecimal string slice of any even length to a vector of u8 values.
+/// Whitespace, colons and dashes between the digits are ignored, so `00:01:02` and
+/// `00 01 02` are accepted.
+///
+/// # Arguments
+///
+/// * `hex` - A string slice that holds the hexadecimal representation.
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// * `Ok(Vec<u8>)` containing the byte values if the conversion is successful.
+/// * `Err(Error::InvalidInput)` if the input string contains invalid hexadecimal characters or is of odd length.
+///
+/// # Examples
+///
+/// ```
+/// # use fhe_aes128::utils::hex_to_bytes;
+/// let bytes = hex_to_bytes("48656c6c6f").unwrap();
 /// assert_eq!(bytes, vec![72, 101, 108, 108, 111]);
 /// ```
-pub fn hex_to_u8_array(hex: &str) -> Result<[u8; 16], &'static str> {
-    if hex.len() != 32 {
-        return Err("Hex string must be 32 characters long for 128 bits");
+pub fn hex_to_bytes(hex: &str) -> Result<Vec<u8>, Error> {
+    // The digits may be those of a key: they are wiped on drop, and an invalid character is
+    // reported by its position only, never with the rest of the string.
+    let mut digits = Zeroizing::new(Vec::with_capacity(hex.len()));
+
+    for (position, c) in hex.chars().enumerate() {
+        if c.is_whitespace() || c == ':' || c == '-' {
+            continue;
+        }
+
+        let digit = c.to_digit(16).ok_or_else(|| {
+            Error::InvalidInput(format!(
+                "invalid hex character {:?} at position {}",
+                c, position
+            ))
+        })?;
+        digits.push(digit as u8);
     }
 
-    let mut array = [0u8; 16];
-    for (i, chunk) in hex.as_bytes().chunks(2).enumerate() {
-        let hex_str = std::str::from_utf8(chunk).map_err(|_| "Invalid UTF-8 in hex string")?;
-        array[i] = u8::from_str_radix(hex_str, 16).map_err(|_| "Invalid hex character")?;
+    if digits.len() % 2 != 0 {
+        return Err(Error::InvalidInput(format!(
+            "hex string must have an even number of characters, got {}",
+            digits.len()
+        )));
     }
 
-    Ok(array)
+    Ok(digits
+        .chunks(2)
+        .map(|pair| (pair[0] << 4) | pair[1])
+        .collect())
 }
 
 /// Increments a 16-byte counter represented as an array of u8 values.
@@ -88,12 +159,13 @@ pub fn hex_to_u8_array(hex: &str) -> Result<[u8; 16], &'static str> {
 /// # Examples
 ///
 /// ```
+/// # use fhe_aes128::utils::increment_counter;
 /// let iv = [0x00; 16];
 /// let incremented_iv = increment_counter(&iv);
 /// assert_eq!(incremented_iv, [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]);
 /// ```
 pub fn increment_counter(iv: &[u8; 16]) -> [u8; 16] {
-    let mut counter = iv.clone();
+    let mut counter = *iv;
 
     let len = counter.len();
     for i in (0..len).rev() {
@@ -107,5 +179,96 @@ pub fn increment_counter(iv: &[u8; 16]) -> [u8; 16] {
         }
     }
 
-    return counter;
+    counter
+}
+
+/// Expands a 128-bit AES key into the 176-byte key schedule in the clear, as specified in FIPS-197.
+///
+/// This is the reference the homomorphic key expansion is checked against, so it works on 32-bit
+/// words and shares no code with `key_expansion_fhe`.
+///
+/// # Arguments
+///
+/// * `key` - The AES key.
+///
+/// # Returns
+///
+/// The 11 round keys, round key 0 being the key itself.
+///
+/// # Examples
+///
+/// ```
+/// # use fhe_aes128::utils::key_expansion_clear;
+/// let key = [
+///     0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f, 0x3c,
+/// ];
+/// let expanded_key = key_expansion_clear(&key);
+/// assert_eq!(expanded_key[172..176], [0xb6, 0x63, 0x0c, 0xa6]);
+/// ```
+pub fn key_expansion_clear(key: &[u8; 16]) -> [u8; 176] {
+    // SubWord: the S-Box applied to each byte of a word
+    let sub_word = |word: u32| u32::from_be_bytes(word.to_be_bytes().map(|x| SBOX[x as usize]));
+
+    let mut words = [0u32; 44];
+    for (word, bytes) in words.iter_mut().zip(key.chunks(4)) {
+        *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+
+    for i in 4..44 {
+        let mut temp = words[i - 1];
+        if i % 4 == 0 {
+            // RotWord, SubWord and the round constant in the most significant byte
+            temp = sub_word(temp.rotate_left(8)) ^ ((R_CONSTANTS[i / 4] as u32) << 24);
+        }
+        words[i] = words[i - 4] ^ temp;
+    }
+
+    let mut expanded_key = [0u8; 176];
+    for (bytes, word) in expanded_key.chunks_mut(4).zip(words) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+
+    expanded_key
+}
+
+/// A directory for the files of one test, deleted when dropped.
+///
+/// The name holds the process id and a per-process counter, so concurrent tests and test runs
+/// never share a directory.
+#[cfg(test)]
+pub(crate) struct TestDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    /// Creates an empty directory `fhe_aes128_<name>_<pid>_<counter>` in the temporary directory.
+    pub(crate) fn new(name: &str) -> Self {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "fhe_aes128_{}_{}_{}",
+            name,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+
+        Self(path)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestDir {
+    type Target = std::path::Path;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
 }